@@ -1,5 +1,21 @@
+use http::{header, HeaderMap};
 use serde::{Deserialize, Serialize};
 
+/// Request header that forces a render mode for a single request (`blocking` or `streaming`).
+pub const RENDER_MODE_HEADER: &str = "x-render-mode";
+
+/// User-agent fragments of crawlers that should receive fully rendered documents.
+const CRAWLER_USER_AGENTS: &[&str] = &[
+    "bot",
+    "crawler",
+    "spider",
+    "slurp",
+    "facebookexternalhit",
+    "embedly",
+    "lighthouse",
+    "headlesschrome",
+];
+
 /// Controls how a route should be rendered by the runtime.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub enum RenderMode {
@@ -10,8 +26,44 @@ pub enum RenderMode {
     Streaming,
 }
 
+impl RenderMode {
+    /// Resolves the mode for a single request to a route configured with `self`.
+    ///
+    /// An explicit [`RENDER_MODE_HEADER`] wins; otherwise crawlers are served blocking renders
+    /// so they index the complete document.
+    pub fn for_request(self, headers: &HeaderMap) -> RenderMode {
+        let requested = headers
+            .get(RENDER_MODE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| match value.trim().to_ascii_lowercase().as_str() {
+                "blocking" => Some(RenderMode::Blocking),
+                "streaming" => Some(RenderMode::Streaming),
+                _ => None,
+            });
+        if let Some(mode) = requested {
+            return mode;
+        }
+
+        let is_crawler = headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|agent| agent.to_ascii_lowercase())
+            .is_some_and(|agent| {
+                CRAWLER_USER_AGENTS
+                    .iter()
+                    .any(|fragment| agent.contains(fragment))
+            });
+        if is_crawler {
+            RenderMode::Blocking
+        } else {
+            self
+        }
+    }
+}
+
 /// Declarative configuration for a renderable route.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct RouteConfig {
     /// Stable identifier that is typically shared with the bundle registry.
     pub id: String,
@@ -23,6 +75,74 @@ pub struct RouteConfig {
     /// Optional time-to-live for cached render results, expressed in seconds.
     #[serde(default)]
     pub cache_ttl_seconds: Option<u64>,
+    /// How long past its TTL a cached render may still be served while it is re-rendered in
+    /// the background, expressed in seconds.
+    #[serde(default)]
+    pub cache_stale_seconds: Option<u64>,
+    /// Request headers whose values are part of the cache key, in addition to the path.
+    #[serde(default)]
+    pub cache_vary_headers: Vec<String>,
+    /// Cookies whose values are part of the cache key, in addition to the path.
+    #[serde(default)]
+    pub cache_vary_cookies: Vec<String>,
+    /// Bundle that renders this route; the server's default bundle when unset.
+    #[serde(default)]
+    pub bundle: Option<String>,
+    /// Data fetched before each render and handed to the bundle as `context.data`.
+    #[serde(default)]
+    pub data: Vec<RouteData>,
+}
+
+/// A data provider a route fetches from before rendering.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteData {
+    /// Name the provider was registered under.
+    pub provider: String,
+    /// Property of `context.data` holding the result; the provider name when unset.
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Longest the fetch may take, expressed in milliseconds; the server default when unset.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Renders with `null` instead of failing the request when the fetch fails or times out.
+    #[serde(default)]
+    pub optional: bool,
+}
+
+impl RouteData {
+    /// Fetches from the provider registered as `provider`, failing the request if it fails.
+    pub fn new(provider: impl Into<String>) -> Self {
+        Self {
+            provider: provider.into(),
+            key: None,
+            timeout_ms: None,
+            optional: false,
+        }
+    }
+
+    /// Stores the result under `key` instead of the provider name.
+    pub fn with_key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    /// Gives up on the fetch after `timeout_ms`.
+    pub fn with_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    /// Renders with `null` when the fetch fails.
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    /// Property of `context.data` holding the result.
+    pub fn key(&self) -> &str {
+        self.key.as_deref().unwrap_or(&self.provider)
+    }
 }
 
 impl RouteConfig {
@@ -33,6 +153,87 @@ impl RouteConfig {
             pattern: pattern.into(),
             render_mode: RenderMode::default(),
             cache_ttl_seconds: None,
+            cache_stale_seconds: None,
+            cache_vary_headers: Vec::new(),
+            cache_vary_cookies: Vec::new(),
+            bundle: None,
+            data: Vec::new(),
+        }
+    }
+
+    /// Overrides the rendering strategy for this route.
+    pub fn with_render_mode(mut self, render_mode: RenderMode) -> Self {
+        self.render_mode = render_mode;
+        self
+    }
+
+    /// Caches successful renders of this route for `ttl_seconds`.
+    pub fn with_cache_ttl_seconds(mut self, ttl_seconds: u64) -> Self {
+        self.cache_ttl_seconds = Some(ttl_seconds);
+        self
+    }
+
+    /// Serves renders up to `stale_seconds` past their TTL while refreshing them.
+    pub fn with_cache_stale_seconds(mut self, stale_seconds: u64) -> Self {
+        self.cache_stale_seconds = Some(stale_seconds);
+        self
+    }
+
+    /// Renders this route with the bundle registered under `bundle`.
+    pub fn with_bundle(mut self, bundle: impl Into<String>) -> Self {
+        self.bundle = Some(bundle.into());
+        self
+    }
+
+    /// Fetches `data` before each render of this route.
+    pub fn with_data(mut self, data: RouteData) -> Self {
+        self.data.push(data);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().expect("valid header"));
         }
+        headers
+    }
+
+    #[test]
+    fn route_mode_applies_to_regular_clients() {
+        let browser = headers(&[(
+            "user-agent",
+            "Mozilla/5.0 (X11; Linux x86_64) Firefox/128.0",
+        )]);
+        assert_eq!(
+            RenderMode::Streaming.for_request(&browser),
+            RenderMode::Streaming
+        );
+    }
+
+    #[test]
+    fn crawlers_receive_blocking_renders() {
+        let crawler = headers(&[("user-agent", "Mozilla/5.0 (compatible; Googlebot/2.1)")]);
+        assert_eq!(
+            RenderMode::Streaming.for_request(&crawler),
+            RenderMode::Blocking
+        );
+    }
+
+    #[test]
+    fn header_overrides_route_and_user_agent() {
+        let forced = headers(&[
+            ("user-agent", "Googlebot/2.1"),
+            (RENDER_MODE_HEADER, "Streaming"),
+        ]);
+        assert_eq!(
+            RenderMode::Blocking.for_request(&forced),
+            RenderMode::Streaming
+        );
     }
 }
@@ -0,0 +1,19 @@
+use async_trait::async_trait;
+use serde_json::Value;
+
+use crate::{AppError, RequestContext};
+
+/// An async source of JSON that routes fetch before rendering, such as a product or CMS API.
+///
+/// The server runs the providers a route declares concurrently and cancels a fetch by dropping
+/// its future, when it times out, when another required fetch fails or when the client goes
+/// away. Implementations should therefore not rely on running to completion.
+#[async_trait]
+pub trait DataProvider: Send + Sync {
+    /// Fetches the data `request` needs. Failures surface as the request's error unless the
+    /// route marks the data optional, so use [`ErrorCode::UpstreamFailure`] for upstream
+    /// outages.
+    ///
+    /// [`ErrorCode::UpstreamFailure`]: crate::ErrorCode::UpstreamFailure
+    async fn fetch(&self, request: &RequestContext) -> Result<Value, AppError>;
+}
@@ -9,18 +9,43 @@ use serde::Serialize;
 #[serde(rename_all = "snake_case")]
 pub enum ErrorCode {
     BadRequest,
+    Unauthorized,
     NotFound,
     UpstreamFailure,
+    RenderTimeout,
+    OutOfMemory,
+    ClientClosed,
     Internal,
 }
 
 impl ErrorCode {
+    /// Stable snake_case name, matching the serialised form; used as a metric label.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "bad_request",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::UpstreamFailure => "upstream_failure",
+            ErrorCode::RenderTimeout => "render_timeout",
+            ErrorCode::OutOfMemory => "out_of_memory",
+            ErrorCode::ClientClosed => "client_closed",
+            ErrorCode::Internal => "internal",
+        }
+    }
+
     /// Converts the error code into an HTTP status code used when surfacing errors over HTTP.
     pub fn status_code(self) -> StatusCode {
         match self {
             ErrorCode::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
             ErrorCode::NotFound => StatusCode::NOT_FOUND,
             ErrorCode::UpstreamFailure => StatusCode::BAD_GATEWAY,
+            ErrorCode::RenderTimeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorCode::OutOfMemory => StatusCode::SERVICE_UNAVAILABLE,
+            // Nginx's "client closed request"; only ever seen in logs since nobody is listening.
+            ErrorCode::ClientClosed => {
+                StatusCode::from_u16(499).expect("499 is a valid status code")
+            }
             ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -50,6 +75,12 @@ impl AppError {
         self
     }
 
+    /// Replaces the message, keeping the code and source.
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+
     /// Returns the public error code.
     pub fn code(&self) -> ErrorCode {
         self.code
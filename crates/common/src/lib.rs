@@ -1,7 +1,13 @@
 pub mod config;
+pub mod data;
 pub mod errors;
 pub mod request;
+pub mod routing;
+pub mod trace;
 
-pub use config::{RenderMode, RouteConfig};
+pub use config::{RenderMode, RouteConfig, RouteData, RENDER_MODE_HEADER};
+pub use data::DataProvider;
 pub use errors::{AppError, ErrorCode};
-pub use request::{RequestContext, TraceContext};
+pub use request::RequestContext;
+pub use routing::{RouteMatch, RoutePattern, RouteTable};
+pub use trace::{SpanId, TraceContext, TraceId, TraceParent};
@@ -5,14 +5,10 @@ use http::{header, HeaderMap, Method};
 use serde::Serialize;
 use uuid::Uuid;
 
-/// Trace identifiers extracted from incoming requests to aid logging and telemetry correlation.
-#[derive(Debug, Clone, Serialize)]
-pub struct TraceContext {
-    pub request_id: Uuid,
-    pub trace_id: Uuid,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub parent_trace_id: Option<String>,
-}
+use crate::{
+    routing::RouteMatch,
+    trace::{TraceContext, TraceId, TraceParent, TRACEPARENT_HEADER, TRACESTATE_HEADER},
+};
 
 /// Normalised request metadata that is passed through the render pipeline.
 #[derive(Debug, Clone, Serialize)]
@@ -22,18 +18,25 @@ pub struct RequestContext {
     pub path: String,
     pub headers: BTreeMap<String, String>,
     pub cookies: BTreeMap<String, String>,
+    /// Route that matched the path, if the request has been routed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<RouteMatch>,
+    /// Results of the route's data providers, keyed as declared. Handed to the bundle as
+    /// `context.data` rather than as part of the request.
+    #[serde(skip)]
+    pub data: BTreeMap<String, serde_json::Value>,
 }
 
 impl RequestContext {
     /// Builds a new request context from HTTP primitives.
     pub fn from_http_parts(method: &Method, path: impl Into<String>, headers: &HeaderMap) -> Self {
         let path = path.into();
-        let request_id = extract_request_id(headers);
-        let trace_id = extract_trace_id(headers).unwrap_or_else(Uuid::new_v4);
-        let parent_trace_id = headers
-            .get("traceparent")
-            .and_then(|value| value.to_str().ok())
-            .map(|value| value.to_owned());
+        let trace = TraceContext::new(
+            extract_request_id(headers),
+            header_str(headers, TRACEPARENT_HEADER).and_then(TraceParent::parse),
+            header_str(headers, TRACESTATE_HEADER).map(str::to_owned),
+            extract_trace_id(headers),
+        );
 
         let mut header_map = BTreeMap::new();
         for (name, value) in headers.iter() {
@@ -45,17 +48,27 @@ impl RequestContext {
         let cookies = extract_cookies(headers);
 
         Self {
-            trace: TraceContext {
-                request_id,
-                trace_id,
-                parent_trace_id,
-            },
+            trace,
             method: method.to_string(),
             path,
             headers: header_map,
             cookies,
+            route: None,
+            data: BTreeMap::new(),
         }
     }
+
+    /// Records the route that matched this request.
+    pub fn with_route(mut self, route: RouteMatch) -> Self {
+        self.route = Some(route);
+        self
+    }
+
+    /// Records the data fetched for this request's route.
+    pub fn with_data(mut self, data: BTreeMap<String, serde_json::Value>) -> Self {
+        self.data = data;
+        self
+    }
 }
 
 fn extract_request_id(headers: &HeaderMap) -> Uuid {
@@ -66,11 +79,15 @@ fn extract_request_id(headers: &HeaderMap) -> Uuid {
         .unwrap_or_else(Uuid::new_v4)
 }
 
-fn extract_trace_id(headers: &HeaderMap) -> Option<Uuid> {
-    headers
-        .get("x-trace-id")
-        .and_then(|value| value.to_str().ok())
+/// Legacy `x-trace-id` header, used to seed a new trace when no `traceparent` is present.
+fn extract_trace_id(headers: &HeaderMap) -> Option<TraceId> {
+    header_str(headers, "x-trace-id")
         .and_then(|value| Uuid::parse_str(value).ok())
+        .map(TraceId::from)
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
 }
 
 fn extract_cookies(headers: &HeaderMap) -> BTreeMap<String, String> {
@@ -119,7 +136,18 @@ mod tests {
         let trace_id = Uuid::new_v4();
         headers.insert("x-trace-id", trace_id.to_string().parse().unwrap());
         let ctx = RequestContext::from_http_parts(&Method::GET, "/trace", &headers);
+        assert_eq!(ctx.trace.trace_id, TraceId::from(trace_id));
 
-        assert_eq!(ctx.trace.trace_id, trace_id);
+        headers.insert(
+            TRACEPARENT_HEADER,
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+        let ctx = RequestContext::from_http_parts(&Method::GET, "/trace", &headers);
+        assert_eq!(
+            ctx.trace.trace_id.to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
     }
 }
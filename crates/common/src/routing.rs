@@ -0,0 +1,249 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use serde::Serialize;
+
+use crate::{
+    config::RouteConfig,
+    errors::{AppError, ErrorCode},
+};
+
+/// The route that matched a request along with the path parameters it captured.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RouteMatch {
+    /// Identifier of the matched [`RouteConfig`].
+    pub id: String,
+    /// Named parameters and wildcards captured from the path.
+    pub params: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// Must equal the path segment exactly.
+    Static(String),
+    /// `:name` captures exactly one segment.
+    Param(String),
+    /// `*name` (or a bare `*`) captures the remaining segments, possibly none.
+    Wildcard(Option<String>),
+}
+
+impl Segment {
+    /// Lower ranks are more specific and win when several routes match.
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Static(_) => 0,
+            Segment::Param(_) => 1,
+            Segment::Wildcard(_) => 2,
+        }
+    }
+}
+
+/// A parsed route pattern such as `/products/:id` or `/docs/*path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutePattern {
+    segments: Vec<Segment>,
+}
+
+impl RoutePattern {
+    /// Parses `pattern`, rejecting malformed parameters and misplaced wildcards.
+    pub fn parse(pattern: &str) -> Result<Self, AppError> {
+        let invalid = |reason: &str| {
+            AppError::new(
+                ErrorCode::BadRequest,
+                format!("route pattern '{pattern}' {reason}"),
+            )
+        };
+
+        let Some(rest) = pattern.strip_prefix('/') else {
+            return Err(invalid("must start with '/'"));
+        };
+
+        let raw: Vec<&str> = split_path(rest).collect();
+        let mut segments = Vec::with_capacity(raw.len());
+        let mut names: Vec<&str> = Vec::new();
+        for (index, segment) in raw.iter().enumerate() {
+            let parsed = if let Some(name) = segment.strip_prefix(':') {
+                if name.is_empty() {
+                    return Err(invalid("has a parameter without a name"));
+                }
+                if names.contains(&name) {
+                    return Err(invalid(&format!("captures '{name}' more than once")));
+                }
+                names.push(name);
+                Segment::Param(name.to_string())
+            } else if let Some(name) = segment.strip_prefix('*') {
+                if index + 1 != raw.len() {
+                    return Err(invalid("may only use a wildcard as its last segment"));
+                }
+                if names.contains(&name) {
+                    return Err(invalid(&format!("captures '{name}' more than once")));
+                }
+                Segment::Wildcard((!name.is_empty()).then(|| name.to_string()))
+            } else {
+                Segment::Static(segment.to_string())
+            };
+            segments.push(parsed);
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Matches `path`, returning the captured parameters on success.
+    pub fn matches(&self, path: &str) -> Option<BTreeMap<String, String>> {
+        let parts: Vec<&str> = split_path(path.strip_prefix('/').unwrap_or(path)).collect();
+        let mut params = BTreeMap::new();
+
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Static(expected) => {
+                    if parts.get(index) != Some(&expected.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    params.insert(name.clone(), (*parts.get(index)?).to_string());
+                }
+                Segment::Wildcard(name) => {
+                    if let Some(name) = name {
+                        params.insert(name.clone(), parts[index.min(parts.len())..].join("/"));
+                    }
+                    return Some(params);
+                }
+            }
+        }
+
+        (parts.len() == self.segments.len()).then_some(params)
+    }
+
+    /// Orders patterns so that, segment by segment, static beats parameter beats wildcard.
+    fn specificity(&self, other: &Self) -> Ordering {
+        self.segments
+            .iter()
+            .map(Segment::rank)
+            .cmp(other.segments.iter().map(Segment::rank))
+            .then_with(|| other.segments.len().cmp(&self.segments.len()))
+    }
+}
+
+/// Ordered set of routes used to resolve a request path to its [`RouteConfig`].
+#[derive(Debug, Clone, Default)]
+pub struct RouteTable {
+    routes: Vec<(RoutePattern, RouteConfig)>,
+}
+
+impl RouteTable {
+    /// Builds a table from `routes`, rejecting duplicate ids and malformed patterns.
+    pub fn new(routes: Vec<RouteConfig>) -> Result<Self, AppError> {
+        let mut table = Vec::with_capacity(routes.len());
+        for route in routes {
+            if table
+                .iter()
+                .any(|(_, existing): &(RoutePattern, RouteConfig)| existing.id == route.id)
+            {
+                return Err(AppError::new(
+                    ErrorCode::BadRequest,
+                    format!("route id '{}' is declared more than once", route.id),
+                ));
+            }
+            table.push((RoutePattern::parse(&route.pattern)?, route));
+        }
+
+        // Stable, so equally specific routes keep their declaration order.
+        table.sort_by(|(left, _), (right, _)| left.specificity(right));
+        Ok(Self { routes: table })
+    }
+
+    /// Returns the most specific route matching `path`.
+    pub fn resolve(&self, path: &str) -> Option<(&RouteConfig, RouteMatch)> {
+        self.routes.iter().find_map(|(pattern, route)| {
+            pattern.matches(path).map(|params| {
+                let matched = RouteMatch {
+                    id: route.id.clone(),
+                    params,
+                };
+                (route, matched)
+            })
+        })
+    }
+
+    /// Iterates over the configured routes, most specific first.
+    pub fn routes(&self) -> impl Iterator<Item = &RouteConfig> {
+        self.routes.iter().map(|(_, route)| route)
+    }
+}
+
+/// Splits a path into segments, ignoring empty ones produced by repeated or trailing slashes.
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(routes: &[(&str, &str)]) -> RouteTable {
+        RouteTable::new(
+            routes
+                .iter()
+                .map(|(id, pattern)| RouteConfig::new(*id, *pattern))
+                .collect(),
+        )
+        .expect("valid routes")
+    }
+
+    #[test]
+    fn named_params_are_captured() {
+        let routes = table(&[("product", "/products/:id/reviews/:review")]);
+        let (route, matched) = routes.resolve("/products/42/reviews/7").expect("match");
+
+        assert_eq!(route.id, "product");
+        assert_eq!(matched.params["id"], "42");
+        assert_eq!(matched.params["review"], "7");
+        assert!(routes.resolve("/products/42").is_none());
+    }
+
+    #[test]
+    fn wildcards_capture_the_remaining_path() {
+        let routes = table(&[("docs", "/docs/*path"), ("any", "/*")]);
+
+        let (_, matched) = routes.resolve("/docs/guide/intro").expect("match");
+        assert_eq!(matched.params["path"], "guide/intro");
+
+        let (_, matched) = routes.resolve("/docs").expect("match");
+        assert_eq!(matched.params["path"], "");
+
+        let (route, matched) = routes.resolve("/elsewhere").expect("catch-all");
+        assert_eq!(route.id, "any");
+        assert!(matched.params.is_empty());
+    }
+
+    #[test]
+    fn static_segments_win_over_params() {
+        let routes = table(&[
+            ("product", "/products/:id"),
+            ("catch-all", "/*rest"),
+            ("new-product", "/products/new"),
+        ]);
+
+        assert_eq!(
+            routes.resolve("/products/new").expect("match").1.id,
+            "new-product"
+        );
+        assert_eq!(
+            routes.resolve("/products/9").expect("match").1.id,
+            "product"
+        );
+        assert_eq!(routes.resolve("/").expect("match").1.id, "catch-all");
+    }
+
+    #[test]
+    fn malformed_patterns_are_rejected() {
+        for pattern in ["products", "/products/:", "/*rest/more", "/:id/:id"] {
+            assert!(RoutePattern::parse(pattern).is_err(), "{pattern}");
+        }
+        assert!(RouteTable::new(vec![
+            RouteConfig::new("home", "/"),
+            RouteConfig::new("home", "/index"),
+        ])
+        .is_err());
+    }
+}
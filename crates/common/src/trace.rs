@@ -0,0 +1,301 @@
+//! W3C Trace Context (`traceparent` / `tracestate`) identifiers.
+//!
+//! See <https://www.w3.org/TR/trace-context/>.
+
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+use uuid::Uuid;
+
+/// Header carrying the caller's trace id, span id and flags.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+/// Header carrying vendor-specific trace state, forwarded untouched.
+pub const TRACESTATE_HEADER: &str = "tracestate";
+
+/// The only `traceparent` version this implementation emits.
+const VERSION: &str = "00";
+/// `trace-flags` bit marking the trace as sampled by the caller.
+const SAMPLED: u8 = 0x01;
+
+/// A 16-byte trace id, shared by every span in a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceId([u8; 16]);
+
+/// An 8-byte span id, unique within its trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpanId([u8; 8]);
+
+impl TraceId {
+    /// Generates a random, non-zero trace id.
+    pub fn random() -> Self {
+        Self(Uuid::new_v4().into_bytes())
+    }
+
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn to_bytes(self) -> [u8; 16] {
+        self.0
+    }
+
+    /// Parses 32 lowercase hex digits, rejecting the invalid all-zero id.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let mut bytes = [0; 16];
+        decode_hex(hex, &mut bytes)?;
+        (bytes != [0; 16]).then_some(Self(bytes))
+    }
+}
+
+impl SpanId {
+    /// Generates a random, non-zero span id.
+    pub fn random() -> Self {
+        let mut bytes = [0; 8];
+        // The version nibble of a v4 UUID sits in its first half, so that half is never zero.
+        bytes.copy_from_slice(&Uuid::new_v4().as_bytes()[..8]);
+        Self(bytes)
+    }
+
+    pub fn from_bytes(bytes: [u8; 8]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn to_bytes(self) -> [u8; 8] {
+        self.0
+    }
+
+    /// Parses 16 lowercase hex digits, rejecting the invalid all-zero id.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let mut bytes = [0; 8];
+        decode_hex(hex, &mut bytes)?;
+        (bytes != [0; 8]).then_some(Self(bytes))
+    }
+}
+
+impl From<Uuid> for TraceId {
+    fn from(value: Uuid) -> Self {
+        Self(value.into_bytes())
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+impl fmt::Display for SpanId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+impl Serialize for TraceId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl Serialize for SpanId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A parsed `traceparent` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: TraceId,
+    /// The caller's span, which becomes the parent of ours.
+    pub parent_id: SpanId,
+    pub flags: u8,
+}
+
+impl TraceParent {
+    /// Parses a `traceparent` value. Versions above `00` are accepted as long as they start with
+    /// the `00` fields, as the specification requires; anything malformed yields `None` so the
+    /// request starts a new trace.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (version, rest) = value.split_once('-')?;
+        if version.len() != 2 || version == "ff" || !is_lower_hex(version) {
+            return None;
+        }
+
+        let mut fields = rest.splitn(4, '-');
+        let trace_id = TraceId::from_hex(fields.next()?)?;
+        let parent_id = SpanId::from_hex(fields.next()?)?;
+        let flags = fields.next()?;
+        let mut flag_bytes = [0; 1];
+        decode_hex(flags, &mut flag_bytes)?;
+        if fields.next().is_some() && version == VERSION {
+            // Version 00 has exactly four fields.
+            return None;
+        }
+
+        Some(Self {
+            trace_id,
+            parent_id,
+            flags: flag_bytes[0],
+        })
+    }
+}
+
+/// Trace identifiers for a request: ours, the caller's, and what to propagate downstream.
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceContext {
+    pub request_id: Uuid,
+    pub trace_id: TraceId,
+    /// The span representing this request; downstream calls use it as their parent.
+    pub span_id: SpanId,
+    /// The caller's span, when the request carried a valid `traceparent`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_span_id: Option<SpanId>,
+    pub trace_flags: u8,
+    /// The caller's `tracestate`, forwarded as received.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tracestate: Option<String>,
+    /// `traceparent` value to send with outgoing requests made on behalf of this one.
+    pub traceparent: String,
+}
+
+impl TraceContext {
+    /// Continues the caller's trace when `parent` is present and starts a new, sampled one
+    /// otherwise. `tracestate` is only kept alongside a valid `traceparent`.
+    pub fn new(
+        request_id: Uuid,
+        parent: Option<TraceParent>,
+        tracestate: Option<String>,
+        fallback_trace_id: Option<TraceId>,
+    ) -> Self {
+        let (trace_id, parent_span_id, trace_flags, tracestate) = match parent {
+            Some(parent) => (
+                parent.trace_id,
+                Some(parent.parent_id),
+                parent.flags,
+                tracestate.filter(|state| !state.trim().is_empty()),
+            ),
+            None => (
+                fallback_trace_id.unwrap_or_else(TraceId::random),
+                None,
+                SAMPLED,
+                None,
+            ),
+        };
+        let span_id = SpanId::random();
+        Self {
+            request_id,
+            trace_id,
+            span_id,
+            parent_span_id,
+            trace_flags,
+            tracestate,
+            traceparent: format_traceparent(trace_id, span_id, trace_flags),
+        }
+    }
+
+    /// Whether the trace is sampled, so spans for it should be recorded.
+    pub fn sampled(&self) -> bool {
+        self.trace_flags & SAMPLED != 0
+    }
+
+    /// Replaces our trace and span ids, for example with the ones a tracing exporter assigned
+    /// to the request span, keeping `traceparent` in step.
+    pub fn with_span(mut self, trace_id: TraceId, span_id: SpanId, trace_flags: u8) -> Self {
+        self.trace_id = trace_id;
+        self.span_id = span_id;
+        self.trace_flags = trace_flags;
+        self.traceparent = format_traceparent(trace_id, span_id, trace_flags);
+        self
+    }
+}
+
+fn format_traceparent(trace_id: TraceId, span_id: SpanId, flags: u8) -> String {
+    format!("{VERSION}-{trace_id}-{span_id}-{flags:02x}")
+}
+
+fn is_lower_hex(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Decodes exactly `out.len() * 2` lowercase hex digits into `out`.
+fn decode_hex(hex: &str, out: &mut [u8]) -> Option<()> {
+    if hex.len() != out.len() * 2 || !is_lower_hex(hex) {
+        return None;
+    }
+    for (index, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn valid_traceparents_are_parsed() {
+        let parent = TraceParent::parse(PARENT).expect("valid traceparent");
+        assert_eq!(
+            parent.trace_id.to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(parent.parent_id.to_string(), "00f067aa0ba902b7");
+        assert_eq!(parent.flags, 1);
+
+        // Later versions may append fields after the ones version 00 defines.
+        let future = "cc-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra";
+        assert_eq!(TraceParent::parse(future).expect("future version").flags, 0);
+    }
+
+    #[test]
+    fn malformed_traceparents_are_rejected() {
+        for value in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+        ] {
+            assert!(TraceParent::parse(value).is_none(), "{value}");
+        }
+    }
+
+    #[test]
+    fn contexts_continue_the_callers_trace_with_a_new_span() {
+        let parent = TraceParent::parse(PARENT);
+        let context = TraceContext::new(Uuid::new_v4(), parent, Some("vendor=x".into()), None);
+
+        assert_eq!(
+            context.trace_id.to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(
+            context.parent_span_id,
+            parent.map(|parent| parent.parent_id)
+        );
+        assert_ne!(Some(context.span_id), context.parent_span_id);
+        assert_eq!(context.tracestate.as_deref(), Some("vendor=x"));
+        assert_eq!(
+            context.traceparent,
+            format!("00-4bf92f3577b34da6a3ce929d0e0e4736-{}-01", context.span_id)
+        );
+        assert!(TraceParent::parse(&context.traceparent).is_some());
+    }
+
+    #[test]
+    fn contexts_without_a_parent_start_a_sampled_trace() {
+        let context = TraceContext::new(Uuid::new_v4(), None, Some("vendor=x".into()), None);
+        assert!(context.parent_span_id.is_none());
+        assert!(context.tracestate.is_none());
+        assert!(context.sampled());
+        assert!(TraceParent::parse(&context.traceparent).is_some());
+    }
+}
@@ -0,0 +1,86 @@
+//! Compares warming an engine pool by compiling the bundle with booting it from a snapshot.
+//!
+//! Run with `cargo bench -p runtime --bench boot`. Uses the bundle in `RSENGINE_TEST_BUNDLE`
+//! when set (for example the React sample built by `cargo xtask bundle`), otherwise a generated
+//! bundle with a few thousand functions.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use runtime::{write_snapshot, PoolConfig, RenderRuntime, RuntimeConfig};
+use tempfile::TempDir;
+
+const ENGINES: usize = 4;
+const ROUNDS: u32 = 10;
+
+fn main() {
+    let dir = TempDir::new().expect("bench dir");
+    let bundle = match env::var_os("RSENGINE_TEST_BUNDLE") {
+        Some(path) => PathBuf::from(path),
+        None => generated_bundle(dir.path()),
+    };
+    let snapshot = dir.path().join("bundle.snapshot");
+    let config = RuntimeConfig::new(&bundle).with_pool(PoolConfig {
+        min_size: ENGINES,
+        max_size: ENGINES,
+        ..PoolConfig::default()
+    });
+
+    let info = write_snapshot(&config, &snapshot).expect("write snapshot");
+    println!(
+        "bundle {} ({} bytes), snapshot {} bytes, {ENGINES} engines per pool",
+        bundle.display(),
+        fs::metadata(&bundle).map(|meta| meta.len()).unwrap_or(0),
+        info.bytes,
+    );
+
+    let cold = measure(&config);
+    let warm = measure(&config.clone().with_snapshot(&snapshot));
+    report("cold", cold, cold);
+    report("snapshot", warm, cold);
+}
+
+/// Median time to create a runtime, which evaluates the bundle in every pooled engine.
+fn measure(config: &RuntimeConfig) -> Duration {
+    let mut samples: Vec<Duration> = (0..ROUNDS)
+        .map(|_| {
+            let started = Instant::now();
+            let runtime = RenderRuntime::try_new(config.clone()).expect("runtime");
+            let elapsed = started.elapsed();
+            drop(runtime);
+            elapsed
+        })
+        .collect();
+    samples.sort();
+    samples[samples.len() / 2]
+}
+
+fn report(label: &str, median: Duration, baseline: Duration) {
+    println!(
+        "{label:>9}: {:>8.2}ms per pool, {:>7.2}ms per engine ({:.0}% of cold)",
+        median.as_secs_f64() * 1000.0,
+        median.as_secs_f64() * 1000.0 / ENGINES as f64,
+        median.as_secs_f64() / baseline.as_secs_f64() * 100.0,
+    );
+}
+
+/// A bundle whose size is dominated by code that is compiled but mostly not run at startup,
+/// like a typical application bundle.
+fn generated_bundle(dir: &Path) -> PathBuf {
+    let mut source = String::new();
+    for index in 0..4000 {
+        source.push_str(&format!(
+            "function component{index}(props) {{\n  const items = props.items.map((item, i) => `<li data-i=\"${{i}}\">${{item.name}}</li>`);\n  return `<section id=\"c{index}\">${{items.join('')}}</section>`;\n}}\n"
+        ));
+    }
+    source.push_str(
+        "const registry = { component0, component1, component2 };\n\
+         export function stream(context) {\n  context.write(registry.component0({ items: [] }));\n}\n",
+    );
+    let path = dir.join("generated.bundle.js");
+    fs::write(&path, source).expect("write bundle");
+    path
+}
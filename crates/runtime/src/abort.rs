@@ -0,0 +1,82 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+/// Cancellation flag shared between the host and a render running on a blocking thread.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AbortSignal(Arc<(Mutex<bool>, Condvar)>);
+
+impl AbortSignal {
+    /// Requests cancellation and wakes the render if it is waiting on a timer.
+    pub(crate) fn abort(&self) {
+        let (aborted, wake) = &*self.0;
+        *aborted.lock().expect("abort signal mutex poisoned") = true;
+        wake.notify_all();
+    }
+
+    pub(crate) fn is_aborted(&self) -> bool {
+        *self.0 .0.lock().expect("abort signal mutex poisoned")
+    }
+
+    /// Sleeps for `duration` unless aborted first; returns whether the signal fired.
+    pub(crate) fn sleep(&self, duration: Duration) -> bool {
+        let (aborted, wake) = &*self.0;
+        let guard = aborted.lock().expect("abort signal mutex poisoned");
+        let (guard, _) = wake
+            .wait_timeout_while(guard, duration, |aborted| !*aborted)
+            .expect("abort signal mutex poisoned");
+        *guard
+    }
+
+    /// Returns a guard that aborts the signal when dropped.
+    pub(crate) fn abort_on_drop(&self) -> AbortOnDrop {
+        AbortOnDrop(self.clone())
+    }
+}
+
+/// Aborts its signal when dropped, covering callers that abandon a render future midway.
+#[derive(Debug)]
+pub(crate) struct AbortOnDrop(AbortSignal);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Instant};
+
+    use super::*;
+
+    #[test]
+    fn sleep_returns_early_when_aborted() {
+        let signal = AbortSignal::default();
+        let remote = signal.clone();
+        let aborter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            remote.abort();
+        });
+
+        let started = Instant::now();
+        assert!(signal.sleep(Duration::from_secs(30)));
+        assert!(started.elapsed() < Duration::from_secs(30));
+        aborter.join().expect("aborter thread");
+    }
+
+    #[test]
+    fn dropping_the_guard_aborts() {
+        let signal = AbortSignal::default();
+        drop(signal.abort_on_drop());
+        assert!(signal.is_aborted());
+    }
+
+    #[test]
+    fn sleep_runs_to_completion_without_abort() {
+        let signal = AbortSignal::default();
+        assert!(!signal.sleep(Duration::from_millis(1)));
+        assert!(!signal.is_aborted());
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::AppError;
+use rquickjs::{CatchResultExt, Coerced, Ctx, Exception, Function, Object, Value};
+use tokio::sync::mpsc;
+
+use crate::{engine::js_error, limits::Budget};
+
+/// Messages a render sends to the host while it runs.
+#[derive(Debug)]
+pub(crate) enum RenderEvent {
+    /// `context.write(chunk)`.
+    Chunk(String),
+    /// `context.flush()`.
+    Flush,
+    /// `context.close()`.
+    Close,
+    /// `context.onError(error)`.
+    Error(ErrorReport),
+}
+
+/// An error the bundle reported through `context.onError`.
+#[derive(Debug)]
+pub(crate) struct ErrorReport {
+    pub(crate) message: String,
+    pub(crate) stack: Option<String>,
+}
+
+/// Host side of the `context` object, shared by the native functions installed on it.
+#[derive(Clone)]
+pub(crate) struct Bridge {
+    sink: mpsc::Sender<RenderEvent>,
+    budget: Arc<Budget>,
+    cpu_time_slice: Option<Duration>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Bridge {
+    pub(crate) fn new(
+        sink: mpsc::Sender<RenderEvent>,
+        budget: Arc<Budget>,
+        cpu_time_slice: Option<Duration>,
+    ) -> Self {
+        Self {
+            sink,
+            budget,
+            cpu_time_slice,
+            closed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Delivers `event` to the host, returning `false` when the host has stopped listening.
+    fn post(&self, event: RenderEvent) -> bool {
+        // Waiting on a slow client is not charged against the CPU slice.
+        self.budget.leave_js();
+        let sent = self.sink.blocking_send(event).is_ok();
+        self.budget.enter_js(self.cpu_time_slice);
+        sent
+    }
+
+    fn send<'js>(&self, ctx: &Ctx<'js>, event: RenderEvent) -> rquickjs::Result<()> {
+        if self.post(event) {
+            Ok(())
+        } else {
+            Err(Exception::throw_message(ctx, "response stream is closed"))
+        }
+    }
+
+    fn ensure_open<'js>(&self, ctx: &Ctx<'js>) -> rquickjs::Result<()> {
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(Exception::throw_message(
+                ctx,
+                "response stream was closed by the bundle",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Builds the `context` object handed to the bundle's `stream` export.
+pub(crate) fn stream_context<'js>(
+    ctx: &Ctx<'js>,
+    hooks: &Object<'js>,
+    request: &serde_json::Value,
+    data: &serde_json::Value,
+    bridge: Bridge,
+) -> Result<Object<'js>, AppError> {
+    let build = || -> rquickjs::Result<Object<'js>> {
+        let context = Object::new(ctx.clone())?;
+        context.set("request", ctx.json_parse(request.to_string())?)?;
+        context.set("data", ctx.json_parse(data.to_string())?)?;
+
+        let write = bridge.clone();
+        context.set(
+            "write",
+            Function::new(
+                ctx.clone(),
+                move |ctx: Ctx<'js>, chunk: String| -> rquickjs::Result<()> {
+                    write.ensure_open(&ctx)?;
+                    write.send(&ctx, RenderEvent::Chunk(chunk))
+                },
+            )?,
+        )?;
+
+        let flush = bridge.clone();
+        context.set(
+            "flush",
+            Function::new(ctx.clone(), move |ctx: Ctx<'js>| -> rquickjs::Result<()> {
+                flush.ensure_open(&ctx)?;
+                flush.send(&ctx, RenderEvent::Flush)
+            })?,
+        )?;
+
+        let close = bridge.clone();
+        context.set(
+            "close",
+            Function::new(ctx.clone(), move |ctx: Ctx<'js>| -> rquickjs::Result<()> {
+                // Closing twice is harmless; React may close from both `final` and `end`.
+                if close.closed.swap(true, Ordering::Relaxed) {
+                    return Ok(());
+                }
+                close.send(&ctx, RenderEvent::Close)
+            })?,
+        )?;
+
+        let report = bridge;
+        context.set(
+            "onError",
+            Function::new(ctx.clone(), move |error: Value<'js>| {
+                // Reports after the client went away are dropped rather than thrown back.
+                report.post(RenderEvent::Error(error_report(&error)));
+            })?,
+        )?;
+
+        context.set("registerAbort", hooks.get::<_, Function>("registerAbort")?)?;
+        Ok(context)
+    };
+
+    build()
+        .catch(ctx)
+        .map_err(|err| js_error("failed to build stream context", err))
+}
+
+fn error_report(error: &Value<'_>) -> ErrorReport {
+    let stack = error
+        .as_object()
+        .and_then(|object| object.get::<_, Option<String>>("stack").ok().flatten());
+    let message = error
+        .get::<Coerced<String>>()
+        .map(|message| message.0)
+        .unwrap_or_else(|_| "unknown error".to_string());
+
+    ErrorReport { message, stack }
+}
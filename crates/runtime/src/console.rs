@@ -0,0 +1,194 @@
+//! `console.*` for bundles, forwarded to `tracing` instead of stdout.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use tracing::{debug, error, info, warn};
+
+use crate::source_map::SourceMapper;
+
+/// Target of events raised by bundle `console.*` calls.
+pub const CONSOLE_TARGET: &str = "console";
+
+/// Limits on what bundles can log through `console.*`.
+#[derive(Debug, Clone)]
+pub struct ConsoleConfig {
+    /// Sustained rate of console messages per bundle, across every engine in the pool. `0`
+    /// discards all console output.
+    pub messages_per_second: u32,
+    /// Messages allowed in a burst above the sustained rate.
+    pub burst: u32,
+    /// Longest a single argument may be once formatted; longer ones are cut at this length.
+    pub max_arg_bytes: usize,
+}
+
+impl Default for ConsoleConfig {
+    fn default() -> Self {
+        Self {
+            messages_per_second: 100,
+            burst: 200,
+            max_arg_bytes: 8 * 1024,
+        }
+    }
+}
+
+/// Writes a bundle's console messages to `tracing`, shared by every engine running it.
+#[derive(Debug)]
+pub(crate) struct ConsoleLog {
+    bundle: String,
+    max_arg_bytes: usize,
+    bucket: Mutex<TokenBucket>,
+    /// Points logged stack traces at the original sources.
+    source_map: Option<Arc<SourceMapper>>,
+}
+
+impl ConsoleLog {
+    pub(crate) fn new(
+        bundle: impl Into<String>,
+        config: &ConsoleConfig,
+        source_map: Option<Arc<SourceMapper>>,
+    ) -> Self {
+        Self {
+            source_map,
+            bundle: bundle.into(),
+            max_arg_bytes: config.max_arg_bytes,
+            bucket: Mutex::new(TokenBucket::new(
+                config.messages_per_second,
+                config.burst,
+                Instant::now(),
+            )),
+        }
+    }
+
+    /// Emits one `console.<method>(...args)` call, with its arguments already formatted by the
+    /// prelude. Calls over the rate limit are dropped and reported with the next one admitted.
+    pub(crate) fn emit(&self, method: &str, args: Vec<String>, request_id: Option<&str>) {
+        let admitted = self
+            .bucket
+            .lock()
+            .expect("console rate limiter mutex poisoned")
+            .admit(Instant::now());
+        let Some(dropped) = admitted else {
+            return;
+        };
+
+        let bundle = self.bundle.as_str();
+        if dropped > 0 {
+            warn!(
+                target: CONSOLE_TARGET,
+                request_id,
+                bundle,
+                dropped,
+                "bundle console messages were dropped by the rate limit"
+            );
+        }
+
+        let mut message = args
+            .iter()
+            .map(|arg| truncate(arg, self.max_arg_bytes))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if let Some(source_map) = &self.source_map {
+            message = source_map.remap(&message);
+        }
+        match method {
+            "error" => error!(target: CONSOLE_TARGET, request_id, bundle, "{message}"),
+            "warn" => warn!(target: CONSOLE_TARGET, request_id, bundle, "{message}"),
+            "debug" | "trace" => debug!(target: CONSOLE_TARGET, request_id, bundle, "{message}"),
+            _ => info!(target: CONSOLE_TARGET, request_id, bundle, "{message}"),
+        }
+    }
+}
+
+/// Cuts `arg` to at most `max_bytes` on a character boundary, noting how much was removed.
+fn truncate(arg: &str, max_bytes: usize) -> std::borrow::Cow<'_, str> {
+    if arg.len() <= max_bytes {
+        return arg.into();
+    }
+    let mut end = max_bytes;
+    while !arg.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}… [{} bytes truncated]", &arg[..end], arg.len() - end).into()
+}
+
+/// Admits up to `burst` messages at once, refilled at `per_second`.
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    per_second: f64,
+    refilled: Instant,
+    dropped: u64,
+}
+
+impl TokenBucket {
+    fn new(per_second: u32, burst: u32, now: Instant) -> Self {
+        let capacity = if per_second == 0 {
+            0.0
+        } else {
+            f64::from(burst.max(1))
+        };
+        Self {
+            tokens: capacity,
+            capacity,
+            per_second: f64::from(per_second),
+            refilled: now,
+            dropped: 0,
+        }
+    }
+
+    /// Takes a token, returning how many messages were dropped since the last admitted one, or
+    /// `None` when the message must be dropped.
+    fn admit(&mut self, now: Instant) -> Option<u64> {
+        let elapsed = now.saturating_duration_since(self.refilled);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.per_second).min(self.capacity);
+        self.refilled = now;
+
+        if self.tokens < 1.0 {
+            self.dropped += 1;
+            return None;
+        }
+        self.tokens -= 1.0;
+        Some(std::mem::take(&mut self.dropped))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn bursts_are_admitted_then_limited_to_the_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10, 3, start);
+
+        for _ in 0..3 {
+            assert_eq!(bucket.admit(start), Some(0));
+        }
+        assert_eq!(bucket.admit(start), None);
+        assert_eq!(bucket.admit(start), None);
+
+        // The next admitted message reports the two that were dropped.
+        let later = start + Duration::from_millis(100);
+        assert_eq!(bucket.admit(later), Some(2));
+        assert_eq!(bucket.admit(later), None);
+    }
+
+    #[test]
+    fn a_zero_rate_drops_everything() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(0, 100, start);
+        assert_eq!(bucket.admit(start + Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn long_arguments_are_truncated_on_a_character_boundary() {
+        assert_eq!(truncate("short", 8), "short");
+        assert_eq!(truncate("ééé", 3), "é… [4 bytes truncated]");
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    cell::Cell,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use common::{AppError, ErrorCode};
+use rquickjs::{
+    promise::PromiseState, CatchResultExt, CaughtError, Context, Ctx, Function, Module, Object,
+    Promise, Runtime, Value,
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    abort::AbortSignal,
+    bridge::{stream_context, Bridge, RenderEvent},
+    console::ConsoleLog,
+    heap::{HeapMeter, MeteredAllocator},
+    limits::{Budget, EngineLimits, Overrun},
+    source_map::SourceMapper,
+};
+
+const PRELUDE: &str = include_str!("js/prelude.js");
+
+/// Bundle source shared by every engine in a pool.
+#[derive(Debug)]
+pub(crate) struct Bundle {
+    pub(crate) name: String,
+    pub(crate) source: String,
+    /// The source compiled ahead of time, from a snapshot built for this bundle.
+    pub(crate) bytecode: Option<Vec<u8>>,
+    /// Where the bundle's `console.*` calls go.
+    pub(crate) console: Arc<ConsoleLog>,
+    /// Maps stack traces back to the bundle's original sources.
+    pub(crate) source_map: Option<Arc<SourceMapper>>,
+}
+
+/// A JavaScript engine instance with the bundle evaluated and its `stream` export resolved.
+pub(crate) struct Engine {
+    context: Context,
+    budget: Arc<Budget>,
+    limits: EngineLimits,
+    poisoned: Cell<bool>,
+    /// Request being rendered, attached to console output.
+    request_id: Arc<Mutex<Option<String>>>,
+}
+
+impl Engine {
+    /// Creates a fresh engine, installs the host prelude and evaluates the bundle as an ES module.
+    pub(crate) fn new(bundle: &Bundle, limits: EngineLimits) -> Result<Self, AppError> {
+        let module_name = bundle.name.as_str();
+        let heap = HeapMeter::default();
+        let runtime =
+            Runtime::new_with_alloc(MeteredAllocator::new(heap.clone(), limits.heap_limit_bytes))
+                .map_err(|err| {
+                AppError::new(ErrorCode::Internal, "failed to create JavaScript runtime")
+                    .with_source(err)
+            })?;
+        let context = Context::full(&runtime).map_err(|err| {
+            AppError::new(ErrorCode::Internal, "failed to create JavaScript context")
+                .with_source(err)
+        })?;
+
+        let budget = Arc::new(Budget::new(heap, &limits));
+        let interrupt_budget = Arc::clone(&budget);
+        runtime.set_interrupt_handler(Some(Box::new(move || interrupt_budget.should_interrupt())));
+
+        let request_id = Arc::new(Mutex::new(None));
+        budget.start_render(limits.render_timeout);
+        budget.enter_js(limits.cpu_time_slice);
+        let evaluated = context.with(|ctx| -> Result<(), AppError> {
+            install_console(&ctx, Arc::clone(&bundle.console), Arc::clone(&request_id))?;
+            ctx.eval::<(), _>(PRELUDE)
+                .catch(&ctx)
+                .map_err(|err| js_error("failed to install host prelude", err))?;
+
+            let declared = match &bundle.bytecode {
+                // SAFETY: QuickJS does not validate bytecode, so malformed input is undefined
+                // behaviour. `snapshot::read` only returns bytecode whose SHA-256 matches the
+                // one recorded when `write_snapshot` compiled it, from this bundle's name and
+                // contents, by the same QuickJS version. A file crafted to pass those checks is
+                // trusted like the bundle itself.
+                Some(bytecode) => unsafe { Module::load(ctx.clone(), bytecode) },
+                None => Module::declare(ctx.clone(), module_name, &*bundle.source),
+            };
+            let (module, evaluated) = declared
+                .and_then(|module| module.eval())
+                .catch(&ctx)
+                .map_err(|err| evaluation_error(module_name, err))?;
+            evaluated
+                .finish::<()>()
+                .catch(&ctx)
+                .map_err(|err| evaluation_error(module_name, err))?;
+
+            let handler: Value = module.get("stream").catch(&ctx).map_err(|err| {
+                js_error(
+                    &format!("bundle '{module_name}' exports could not be read"),
+                    err,
+                )
+            })?;
+            if !handler.is_function() {
+                return Err(AppError::new(
+                    ErrorCode::BadRequest,
+                    format!("bundle '{module_name}' is missing a `stream` export"),
+                ));
+            }
+
+            host_hooks(&ctx)?
+                .set("handler", handler)
+                .catch(&ctx)
+                .map_err(|err| js_error("failed to register `stream` handler", err))
+        });
+        budget.finish_render();
+        detect_out_of_memory(&budget, &evaluated);
+        if let Some(overrun) = budget.overrun() {
+            return Err(overrun.into_error(&limits));
+        }
+        evaluated?;
+
+        Ok(Self {
+            context,
+            budget,
+            limits,
+            poisoned: Cell::new(false),
+            request_id,
+        })
+    }
+
+    /// Whether a render left this engine in a state that must not be reused.
+    pub(crate) fn is_poisoned(&self) -> bool {
+        self.poisoned.get()
+    }
+
+    /// Invokes the bundle's `stream` export and drives the event loop until its promise settles.
+    ///
+    /// Calls on the `context` bridge are forwarded through `sink`; this blocks when the sink is
+    /// full, so it must run on a thread that may block. Aborting `signal` invokes the callbacks
+    /// the bundle passed to `context.registerAbort`. Console output is tagged with `request_id`.
+    pub(crate) fn render(
+        &self,
+        request_id: String,
+        request: &serde_json::Value,
+        data: &serde_json::Value,
+        sink: mpsc::Sender<RenderEvent>,
+        signal: &AbortSignal,
+    ) -> Result<(), AppError> {
+        *self.current_request() = Some(request_id);
+        self.budget.start_render(self.limits.render_timeout);
+        let result = self.context.with(|ctx| {
+            let hooks = host_hooks(&ctx)?;
+            let handler: Function = hooks
+                .get("handler")
+                .catch(&ctx)
+                .map_err(|err| js_error("`stream` handler is not registered", err))?;
+            let bridge = Bridge::new(sink, Arc::clone(&self.budget), self.limits.cpu_time_slice);
+            let bridge = stream_context(&ctx, &hooks, request, data, bridge)?;
+
+            self.budget.enter_js(self.limits.cpu_time_slice);
+            hooks
+                .get::<_, Function>("reset")
+                .and_then(|reset| reset.call::<_, ()>(()))
+                .catch(&ctx)
+                .map_err(|err| js_error("failed to reset host state", err))?;
+            let outcome: Value = handler
+                .call((bridge,))
+                .catch(&ctx)
+                .map_err(|err| js_error("bundle `stream` handler threw", err))?;
+            let Some(promise) = outcome.as_promise().cloned() else {
+                return Ok(());
+            };
+
+            self.run_until_settled(&ctx, &hooks, &promise, signal)
+        });
+        self.budget.finish_render();
+        *self.current_request() = None;
+        detect_out_of_memory(&self.budget, &result);
+
+        if let Some(overrun) = self.budget.overrun() {
+            // Interrupted code may leave half-applied state and queued jobs behind.
+            self.poisoned.set(true);
+            return Err(overrun.into_error(&self.limits));
+        }
+
+        // A bundle that retains memory across renders would fail the next one; retire it now.
+        if self.budget.heap_exhausted() {
+            self.context.runtime().run_gc();
+            if self.budget.heap_exhausted() {
+                self.poisoned.set(true);
+            }
+        }
+
+        result
+    }
+
+    fn current_request(&self) -> std::sync::MutexGuard<'_, Option<String>> {
+        self.request_id
+            .lock()
+            .expect("engine request id mutex poisoned")
+    }
+
+    /// Runs jobs and timers until `promise` settles, sleeping while only future timers remain.
+    ///
+    /// Once `signal` fires the bundle's abort callbacks run and the render gets one more turn of
+    /// jobs to settle before it is abandoned.
+    fn run_until_settled<'js>(
+        &self,
+        ctx: &Ctx<'js>,
+        hooks: &Object<'js>,
+        promise: &Promise<'js>,
+        signal: &AbortSignal,
+    ) -> Result<(), AppError> {
+        let next_timer_delay: Function = hooks
+            .get("nextTimerDelay")
+            .catch(ctx)
+            .map_err(|err| js_error("host prelude is missing `nextTimerDelay`", err))?;
+        let run_due_timers: Function = hooks
+            .get("runDueTimers")
+            .catch(ctx)
+            .map_err(|err| js_error("host prelude is missing `runDueTimers`", err))?;
+        let abort: Function = hooks
+            .get("abort")
+            .catch(ctx)
+            .map_err(|err| js_error("host prelude is missing `abort`", err))?;
+        let mut aborted = false;
+
+        loop {
+            self.budget.enter_js(self.limits.cpu_time_slice);
+            while ctx.execute_pending_job() {}
+
+            match promise.state() {
+                PromiseState::Resolved => return Ok(()),
+                PromiseState::Rejected => {
+                    return promise
+                        .finish::<()>()
+                        .catch(ctx)
+                        .map_err(|err| js_error("bundle `stream` handler rejected", err));
+                }
+                PromiseState::Pending => {}
+            }
+
+            if aborted {
+                return Err(AppError::new(ErrorCode::Internal, "render was aborted"));
+            }
+            if signal.is_aborted() {
+                aborted = true;
+                abort
+                    .call::<_, ()>(())
+                    .catch(ctx)
+                    .map_err(|err| js_error("abort callback threw", err))?;
+                continue;
+            }
+
+            let delay: f64 = next_timer_delay
+                .call(())
+                .catch(ctx)
+                .map_err(|err| js_error("failed to query pending timers", err))?;
+            if delay < 0.0 {
+                return Err(AppError::new(
+                    ErrorCode::Internal,
+                    "bundle `stream` handler returned a promise that never settled",
+                ));
+            }
+
+            self.budget.leave_js();
+            let delay = Duration::from_millis(delay.ceil() as u64);
+            let remaining = self.budget.render_remaining();
+            let wait = remaining.map_or(delay, |remaining| remaining.min(delay));
+            if !wait.is_zero() && signal.sleep(wait) {
+                continue;
+            }
+            if remaining.is_some_and(|remaining| remaining < delay) {
+                self.budget.trip(Overrun::RenderTimeout);
+                return Err(Overrun::RenderTimeout.into_error(&self.limits));
+            }
+
+            self.budget.enter_js(self.limits.cpu_time_slice);
+            run_due_timers
+                .call::<_, ()>(())
+                .catch(ctx)
+                .map_err(|err| js_error("timer callback threw", err))?;
+        }
+    }
+}
+
+/// QuickJS reports a failed allocation as a regular `InternalError`, indistinguishable from one
+/// the bundle throws; record a failed render as a heap overrun only if the allocator refused an
+/// allocation during it.
+fn detect_out_of_memory<T>(budget: &Budget, result: &Result<T, AppError>) {
+    if result.is_err() && budget.allocation_failed() {
+        budget.trip(Overrun::HeapLimit);
+    }
+}
+
+/// Exposes `console` output to the prelude, which formats the arguments and removes the global.
+fn install_console(
+    ctx: &Ctx<'_>,
+    console: Arc<ConsoleLog>,
+    request_id: Arc<Mutex<Option<String>>>,
+) -> Result<(), AppError> {
+    let emit = move |method: String, args: Vec<String>| {
+        let request_id = request_id.lock().expect("engine request id mutex poisoned");
+        console.emit(&method, args, request_id.as_deref());
+    };
+    Function::new(ctx.clone(), emit)
+        .and_then(|emit| ctx.globals().set("__rsengineConsole", emit))
+        .catch(ctx)
+        .map_err(|err| js_error("failed to install console", err))
+}
+
+fn host_hooks<'js>(ctx: &Ctx<'js>) -> Result<Object<'js>, AppError> {
+    ctx.globals()
+        .get("__rsengine")
+        .catch(ctx)
+        .map_err(|err| js_error("host prelude is not installed", err))
+}
+
+pub(crate) fn js_error(message: &str, err: CaughtError<'_>) -> AppError {
+    AppError::new(ErrorCode::Internal, format!("{message}: {err}"))
+}
+
+fn evaluation_error(module_name: &str, err: CaughtError<'_>) -> AppError {
+    AppError::new(
+        ErrorCode::BadRequest,
+        format!("bundle '{module_name}' failed to evaluate: {err}"),
+    )
+}
@@ -0,0 +1,120 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+
+use rquickjs::allocator::{Allocator, RustAllocator};
+
+/// Live byte count of an engine's heap, readable from outside the engine lock, and whether an
+/// allocation has failed since it was last cleared.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HeapMeter {
+    used: Arc<AtomicUsize>,
+    failed: Arc<AtomicBool>,
+}
+
+impl HeapMeter {
+    pub(crate) fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Whether an allocation has been refused since the last [`clear_failure`](Self::clear_failure).
+    pub(crate) fn allocation_failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn clear_failure(&self) {
+        self.failed.store(false, Ordering::Relaxed);
+    }
+
+    fn add(&self, bytes: usize) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn sub(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    fn fail(&self) -> *mut u8 {
+        self.failed.store(true, Ordering::Relaxed);
+        std::ptr::null_mut()
+    }
+}
+
+/// QuickJS allocator that records every allocation in a [`HeapMeter`] and enforces the heap
+/// limit.
+///
+/// The limit is enforced here rather than with QuickJS's own memory limit so that every refused
+/// allocation is recorded: QuickJS reports it as an ordinary `InternalError` that a bundle can
+/// also throw itself. The meter also lets the interrupt handler notice that a render is
+/// approaching the limit without taking the runtime lock.
+pub(crate) struct MeteredAllocator {
+    inner: RustAllocator,
+    meter: HeapMeter,
+    limit: usize,
+}
+
+impl MeteredAllocator {
+    pub(crate) fn new(meter: HeapMeter, limit: Option<usize>) -> Self {
+        Self {
+            inner: RustAllocator,
+            meter,
+            limit: limit.unwrap_or(usize::MAX),
+        }
+    }
+
+    fn fits(&self, additional: usize) -> bool {
+        self.meter.used().saturating_add(additional) <= self.limit
+    }
+}
+
+// SAFETY: every call is forwarded to `RustAllocator`; the meter only observes sizes of
+// allocations that allocator produced.
+unsafe impl Allocator for MeteredAllocator {
+    fn alloc(&mut self, size: usize) -> *mut u8 {
+        if !self.fits(size) {
+            return self.meter.fail();
+        }
+        let ptr = self.inner.alloc(size);
+        if ptr.is_null() {
+            return self.meter.fail();
+        }
+        self.meter.add(unsafe { RustAllocator::usable_size(ptr) });
+        ptr
+    }
+
+    fn calloc(&mut self, count: usize, size: usize) -> *mut u8 {
+        if !self.fits(count.saturating_mul(size)) {
+            return self.meter.fail();
+        }
+        let ptr = self.inner.calloc(count, size);
+        if ptr.is_null() {
+            return self.meter.fail();
+        }
+        self.meter.add(unsafe { RustAllocator::usable_size(ptr) });
+        ptr
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8) {
+        self.meter.sub(RustAllocator::usable_size(ptr));
+        self.inner.dealloc(ptr);
+    }
+
+    unsafe fn realloc(&mut self, ptr: *mut u8, new_size: usize) -> *mut u8 {
+        let old_size = RustAllocator::usable_size(ptr);
+        if new_size > old_size && !self.fits(new_size - old_size) {
+            return self.meter.fail();
+        }
+        let new_ptr = self.inner.realloc(ptr, new_size);
+        if new_ptr.is_null() {
+            return self.meter.fail();
+        }
+        self.meter.sub(old_size);
+        self.meter.add(RustAllocator::usable_size(new_ptr));
+        new_ptr
+    }
+
+    unsafe fn usable_size(ptr: *mut u8) -> usize {
+        RustAllocator::usable_size(ptr)
+    }
+}
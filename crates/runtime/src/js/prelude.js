@@ -0,0 +1,109 @@
+// Host prelude evaluated before the bundle. It provides the minimal timer
+// and console globals bundles expect and exposes hooks the Rust event loop
+// drives.
+(() => {
+  const timers = new Map();
+  let nextTimerId = 1;
+  let abortHandlers = [];
+
+  const schedule = (callback, delay, args) => {
+    const id = nextTimerId++;
+    const due = Date.now() + Math.max(0, Number(delay) || 0);
+    timers.set(id, { callback, args, due });
+    return id;
+  };
+
+  globalThis.setTimeout = (callback, delay, ...args) => schedule(callback, delay, args);
+  globalThis.clearTimeout = (id) => {
+    timers.delete(id);
+  };
+  globalThis.setImmediate = (callback, ...args) => schedule(callback, 0, args);
+  globalThis.clearImmediate = globalThis.clearTimeout;
+  globalThis.queueMicrotask = (callback) => {
+    Promise.resolve().then(callback);
+  };
+
+  // Installed by the host just before the prelude runs.
+  const emit = globalThis.__rsengineConsole;
+  delete globalThis.__rsengineConsole;
+
+  const format = (value) => {
+    if (typeof value === "string") {
+      return value;
+    }
+    if (value instanceof Error) {
+      return value.stack ? `${value}\n${value.stack}` : String(value);
+    }
+    if (typeof value === "object" && value !== null) {
+      try {
+        return JSON.stringify(value);
+      } catch {
+        // Cyclic or otherwise unserialisable values.
+        return String(value);
+      }
+    }
+    return String(value);
+  };
+  const forward = (method) => (...args) => {
+    emit(method, args.map(format));
+  };
+  globalThis.console = {
+    log: forward("log"),
+    info: forward("info"),
+    debug: forward("debug"),
+    trace: forward("trace"),
+    warn: forward("warn"),
+    error: forward("error"),
+  };
+
+  Object.defineProperty(globalThis, "__rsengine", {
+    value: Object.seal({
+      // The bundle's `stream` export, installed by the host after evaluation.
+      handler: null,
+      // Drops timers and abort callbacks left behind by a previous render.
+      reset() {
+        timers.clear();
+        abortHandlers = [];
+      },
+      // Backs `context.registerAbort`.
+      registerAbort(abort) {
+        if (typeof abort !== "function") {
+          throw new TypeError("registerAbort expects a function");
+        }
+        abortHandlers.push(abort);
+      },
+      // Invokes every registered abort callback once.
+      abort() {
+        const handlers = abortHandlers;
+        abortHandlers = [];
+        for (const abort of handlers) {
+          abort();
+        }
+      },
+      // Milliseconds until the next timer is due, or -1 when none are pending.
+      nextTimerDelay() {
+        let next = -1;
+        for (const timer of timers.values()) {
+          if (next === -1 || timer.due < next) {
+            next = timer.due;
+          }
+        }
+        return next === -1 ? -1 : Math.max(0, next - Date.now());
+      },
+      // Runs every timer that is due and returns how many fired.
+      runDueTimers() {
+        const now = Date.now();
+        let fired = 0;
+        for (const [id, timer] of timers) {
+          if (timer.due <= now) {
+            timers.delete(id);
+            fired += 1;
+            timer.callback(...timer.args);
+          }
+        }
+        return fired;
+      },
+    }),
+    enumerable: false,
+  });
+})();
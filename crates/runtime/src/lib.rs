@@ -2,13 +2,43 @@ use std::{
     fs,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 use async_trait::async_trait;
 use common::{AppError, ErrorCode, RequestContext};
-use serde_json::json;
-use tokio::fs as tokio_fs;
-use tracing::debug;
+use metrics::increment_counter;
+use tokio::{sync::mpsc, task, time};
+use tracing::{debug, dispatcher, warn, Dispatch, Span};
+
+mod abort;
+mod bridge;
+mod console;
+mod engine;
+mod heap;
+mod limits;
+mod pool;
+pub mod registry;
+mod snapshot;
+mod source_map;
+mod stats;
+
+use abort::AbortSignal;
+use bridge::{ErrorReport, RenderEvent};
+use console::ConsoleLog;
+use engine::{Bundle, Engine};
+use limits::EngineLimits;
+use pool::EnginePool;
+use source_map::SourceMapper;
+use stats::RenderStats;
+
+pub use console::{ConsoleConfig, CONSOLE_TARGET};
+pub use pool::{PoolConfig, PoolStats};
+pub use registry::{BuildMetadata, BundleRegistry, BundleVersion, FsBundleRegistry};
+pub use snapshot::{write_snapshot, SnapshotInfo};
+
+/// Number of rendered chunks buffered between the engine and the response writer.
+const CHUNK_BUFFER: usize = 16;
 
 /// Configuration parameters for the render runtime.
 #[derive(Debug, Clone)]
@@ -17,6 +47,22 @@ pub struct RuntimeConfig {
     pub name: String,
     /// Path to the JavaScript bundle that exposes the `stream` handler.
     pub bundle_path: PathBuf,
+    /// Sizing and recycling parameters for the engine pool.
+    pub pool: PoolConfig,
+    /// Wall-clock limit for a single render, including time spent waiting on timers.
+    pub render_timeout: Option<Duration>,
+    /// Longest stretch of JavaScript allowed to run without yielding to the host.
+    pub cpu_time_slice: Option<Duration>,
+    /// Heap ceiling for each engine; renders approaching it are aborted.
+    pub heap_limit_bytes: Option<usize>,
+    /// Rate limit and truncation applied to the bundle's `console.*` output.
+    pub console: ConsoleConfig,
+    /// Source map used to report stack traces against the original sources. When unset, the
+    /// bundle's `sourceMappingURL` comment or a `<bundle>.map` file next to it is used if present.
+    pub source_map_path: Option<PathBuf>,
+    /// Snapshot written by [`write_snapshot`]; engines boot from its bytecode instead of
+    /// compiling the bundle when it was built from the same bundle.
+    pub snapshot_path: Option<PathBuf>,
 }
 
 impl RuntimeConfig {
@@ -25,6 +71,13 @@ impl RuntimeConfig {
         Self {
             name: "default".to_string(),
             bundle_path: bundle_path.into(),
+            pool: PoolConfig::default(),
+            render_timeout: Some(Duration::from_secs(10)),
+            cpu_time_slice: Some(Duration::from_secs(2)),
+            heap_limit_bytes: Some(256 * 1024 * 1024),
+            console: ConsoleConfig::default(),
+            source_map_path: None,
+            snapshot_path: None,
         }
     }
 
@@ -33,34 +86,141 @@ impl RuntimeConfig {
         self.name = name.into();
         self
     }
+
+    /// Overrides the engine pool parameters.
+    pub fn with_pool(mut self, pool: PoolConfig) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Overrides the wall-clock limit for a single render.
+    pub fn with_render_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.render_timeout = timeout;
+        self
+    }
+
+    /// Overrides the longest stretch of JavaScript allowed to run without yielding.
+    pub fn with_cpu_time_slice(mut self, slice: Option<Duration>) -> Self {
+        self.cpu_time_slice = slice;
+        self
+    }
+
+    /// Overrides the heap ceiling for each engine.
+    pub fn with_heap_limit_bytes(mut self, limit: Option<usize>) -> Self {
+        self.heap_limit_bytes = limit;
+        self
+    }
+
+    /// Overrides the limits on the bundle's console output.
+    pub fn with_console(mut self, console: ConsoleConfig) -> Self {
+        self.console = console;
+        self
+    }
+
+    /// Loads the bundle's source map from `path` instead of discovering it.
+    pub fn with_source_map(mut self, path: impl Into<PathBuf>) -> Self {
+        self.source_map_path = Some(path.into());
+        self
+    }
+
+    /// Boots engines from the snapshot at `path`.
+    pub fn with_snapshot(mut self, path: impl Into<PathBuf>) -> Self {
+        self.snapshot_path = Some(path.into());
+        self
+    }
+
+    /// Serves `version` from a [`BundleRegistry`], keeping every other setting except the
+    /// snapshot, which belongs to the previous bundle.
+    pub fn with_bundle_version(mut self, version: &BundleVersion) -> Self {
+        self.bundle_path = version.bundle_path.clone();
+        self.source_map_path = version.source_map_path.clone();
+        self.snapshot_path = None;
+        self
+    }
+
+    fn engine_limits(&self) -> EngineLimits {
+        EngineLimits {
+            render_timeout: self.render_timeout,
+            cpu_time_slice: self.cpu_time_slice,
+            heap_limit_bytes: self.heap_limit_bytes,
+        }
+    }
 }
 
-/// Thin wrapper around an SSR runtime bundle that can stream responses.
+/// SSR runtime that renders requests on a pool of engines loaded with the bundle.
 #[derive(Debug, Clone)]
 pub struct RenderRuntime {
     config: Arc<RuntimeConfig>,
+    pool: EnginePool,
+    source_map: Option<Arc<SourceMapper>>,
 }
 
 impl RenderRuntime {
-    /// Validates the bundle and constructs a new runtime.
+    /// Validates the bundle and warms up the engine pool.
     pub fn try_new(config: RuntimeConfig) -> Result<Self, AppError> {
-        validate_bundle(&config.bundle_path)?;
+        let limits = config.engine_limits();
+        let (bundle, seed) = load_bundle(&config, limits)?;
+        let source_map = bundle.source_map.clone();
+        let pool = EnginePool::new(config.pool.clone(), bundle, limits, seed)?;
         Ok(Self {
             config: Arc::new(config),
+            pool,
+            source_map,
         })
     }
 
+    /// Friendly name used to tag logs and metrics for this runtime.
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// The configuration this runtime was created from.
+    pub fn config(&self) -> &RuntimeConfig {
+        &self.config
+    }
+
     /// Returns the canonical bundle path currently loaded.
     pub fn bundle_path(&self) -> &Path {
         &self.config.bundle_path
     }
 
-    /// Produces HTML fragments that will be streamed to the client.
+    /// Returns the current engine pool counters.
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool.stats()
+    }
+
+    /// Whether the pool holds at least `min_size` engines, so renders do not wait on a cold
+    /// start. Dips below while retired engines are being replaced.
+    pub fn is_warm(&self) -> bool {
+        let stats = self.pool.stats();
+        stats.idle + stats.in_use >= self.config.pool.min_size
+    }
+
+    /// Executes the bundle's `stream` export and forwards every written chunk to `writer`.
+    ///
+    /// Records render time, time to first chunk, and the chunks and bytes produced, labelled
+    /// by route id and runtime name. Stack traces in errors point at the original sources when
+    /// the bundle has a source map.
     pub async fn stream_response<W>(
         &self,
         context: &RequestContext,
         writer: &mut W,
     ) -> Result<(), AppError>
+    where
+        W: ResponseWriter,
+    {
+        let mut stats = RenderStats::start();
+        let result = self.render(context, writer, &mut stats).await;
+        stats.finish(context, &self.config.name, &result);
+        result.map_err(|err| remap_error(self.source_map.as_deref(), err))
+    }
+
+    async fn render<W>(
+        &self,
+        context: &RequestContext,
+        writer: &mut W,
+        stats: &mut RenderStats,
+    ) -> Result<(), AppError>
     where
         W: ResponseWriter,
     {
@@ -71,75 +231,179 @@ impl RenderRuntime {
             "render runtime invoked",
         );
 
-        let script = tokio_fs::read_to_string(&self.config.bundle_path)
-            .await
-            .map_err(|err| {
-                AppError::new(
-                    ErrorCode::Internal,
-                    format!(
-                        "failed to read bundle '{}'",
-                        self.config.bundle_path.display()
-                    ),
-                )
+        let serialise = |err| {
+            AppError::new(ErrorCode::Internal, "failed to serialise request context")
                 .with_source(err)
-            })?;
+        };
+        let request = serde_json::to_value(context).map_err(serialise)?;
+        let data = serde_json::to_value(&context.data).map_err(serialise)?;
 
-        if !script.contains("stream") {
-            return Err(AppError::new(
-                ErrorCode::BadRequest,
-                "bundle does not define a `stream` handler",
-            ));
-        }
+        let request_id = context.trace.request_id.to_string();
+        let mut engine = self.pool.checkout().await?;
+        let (sender, mut receiver) = mpsc::channel::<RenderEvent>(CHUNK_BUFFER);
+        let signal = AbortSignal::default();
+        let render_signal = signal.clone();
+        // Runs on every exit, including when the caller drops this future because the client
+        // went away, so the bundle always gets to cancel outstanding work.
+        let _abort = signal.abort_on_drop();
+        // Console output from the bundle belongs to the caller's subscriber and request span.
+        let dispatch = dispatcher::get_default(Dispatch::clone);
+        let span = Span::current();
+        let mut render = task::spawn_blocking(move || {
+            dispatcher::with_default(&dispatch, || {
+                let _entered = span.enter();
+                let result = engine.render(request_id, &request, &data, sender, &render_signal);
+                engine.record_render();
+                result
+            })
+        });
+
+        let forward = async {
+            let mut closed = false;
+            loop {
+                let event = tokio::select! {
+                    event = receiver.recv() => event,
+                    _ = writer.disconnected() => {
+                        return Err(self.cancel_render(context, &signal, receiver, render).await);
+                    }
+                };
+                let Some(event) = event else {
+                    break;
+                };
 
-        let request_snapshot = serde_json::to_string_pretty(&json!({
-            "method": context.method,
-            "path": context.path,
-            "headers": context.headers,
-            "cookies": context.cookies,
-            "trace": {
-                "request_id": context.trace.request_id,
-                "trace_id": context.trace.trace_id,
-                "parent_trace_id": context.trace.parent_trace_id,
+                match event {
+                    RenderEvent::Chunk(chunk) => {
+                        stats.record_chunk(chunk.len());
+                        writer.write(chunk).await?
+                    }
+                    RenderEvent::Flush => writer.flush().await?,
+                    RenderEvent::Close => {
+                        writer.close().await?;
+                        closed = true;
+                    }
+                    RenderEvent::Error(report) => self.record_bundle_error(context, report),
+                }
+
+                // Hand everything written so far to the client while the engine is busy elsewhere.
+                if !closed && receiver.is_empty() {
+                    writer.flush().await?;
+                }
             }
-        }))
-        .map_err(|err| {
-            AppError::new(ErrorCode::Internal, "failed to serialise request context")
-                .with_source(err)
-        })?;
-
-        let chunks = [
-            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">".to_string(),
-            format!("<title>{}</title></head><body>", self.config.name),
-            format!(
-                "<h1>Streaming SSR response</h1><p>Bundle: {}</p>",
-                self.config.bundle_path.display()
-            ),
-            format!(
-                "<section><h2>Request Context</h2><pre>{}</pre></section>",
-                request_snapshot
-            ),
-            "<section><h2>Bundle Source</h2><pre>".to_string(),
-            html_escape::encode_text(&script).to_string(),
-            "</pre></section><script>// stream handler executed inside V8 in future milestones</script>"
-                .to_string(),
-            "</body></html>".to_string(),
-        ];
-
-        for chunk in chunks {
-            writer.write(chunk).await?;
+
+            // The bundle may drop its `context` and keep awaiting timers; keep watching the client.
+            let joined = tokio::select! {
+                joined = &mut render => joined,
+                _ = writer.disconnected() => {
+                    return Err(self.cancel_render(context, &signal, receiver, render).await);
+                }
+            };
+            joined.map_err(|err| {
+                AppError::new(ErrorCode::Internal, "render task terminated unexpectedly")
+                    .with_source(err)
+            })??;
+
+            if !closed {
+                writer.close().await?;
+            }
+            Ok(())
+        };
+
+        // The engine enforces its own deadline; this one also covers a writer that stalls.
+        match self.config.render_timeout {
+            Some(limit) => time::timeout(limit, forward).await.unwrap_or_else(|_| {
+                Err(AppError::new(
+                    ErrorCode::RenderTimeout,
+                    format!("render exceeded the {}ms time limit", limit.as_millis()),
+                ))
+            }),
+            None => forward.await,
         }
+    }
 
-        Ok(())
+    fn record_bundle_error(&self, context: &RequestContext, mut report: ErrorReport) {
+        if let Some(source_map) = &self.source_map {
+            report.message = source_map.remap(&report.message);
+            report.stack = report.stack.map(|stack| source_map.remap(&stack));
+        }
+        warn!(
+            request_id = %context.trace.request_id,
+            runtime = %self.config.name,
+            error = %report.message,
+            stack = report.stack.as_deref().unwrap_or_default(),
+            "bundle reported a render error",
+        );
+        increment_counter!("render_bundle_errors_total", "runtime" => self.config.name.clone());
+    }
+
+    /// Stops a render whose client went away and waits for its engine to return to the pool.
+    async fn cancel_render(
+        &self,
+        context: &RequestContext,
+        signal: &AbortSignal,
+        receiver: mpsc::Receiver<RenderEvent>,
+        render: task::JoinHandle<Result<(), AppError>>,
+    ) -> AppError {
+        // Aborting wakes timer waits; dropping the receiver fails any write the bundle is blocked on.
+        signal.abort();
+        drop(receiver);
+        let _ = render.await;
+
+        debug!(
+            request_id = %context.trace.request_id,
+            runtime = %self.config.name,
+            "client disconnected; render cancelled",
+        );
+        increment_counter!("render_cancelled_total", "runtime" => self.config.name.clone());
+        AppError::new(
+            ErrorCode::ClientClosed,
+            "client disconnected before the render completed",
+        )
     }
 }
+
 /// Abstraction over a streaming sink that receives rendered HTML chunks.
 #[async_trait]
-pub trait ResponseWriter: Send {
+pub trait ResponseWriter: Send + Sync {
     /// Writes the provided chunk to the underlying sink.
     async fn write(&mut self, chunk: String) -> Result<(), AppError>;
+
+    /// Pushes buffered chunks to the client. Writers that do not buffer can keep the default.
+    async fn flush(&mut self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    /// Signals that the response is complete; no chunks are written afterwards.
+    async fn close(&mut self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    /// Resolves once the client has gone away. Writers that cannot tell never resolve.
+    async fn disconnected(&self) {
+        std::future::pending().await
+    }
+}
+
+/// Validates the bundle on disk and evaluates it once, returning the first engine for the pool.
+fn load_bundle(config: &RuntimeConfig, limits: EngineLimits) -> Result<(Bundle, Engine), AppError> {
+    let mut bundle = load_source(config)?;
+    if let Some(snapshot) = &config.snapshot_path {
+        bundle.bytecode = snapshot::read(snapshot, &bundle)?;
+        if bundle.bytecode.is_some() {
+            debug!(
+                bundle = %bundle.name,
+                snapshot = %snapshot.display(),
+                "booting engines from the bundle snapshot"
+            );
+        }
+    }
+    let engine = Engine::new(&bundle, limits)
+        .map_err(|err| remap_error(bundle.source_map.as_deref(), err))?;
+    Ok((bundle, engine))
 }
 
-fn validate_bundle(path: &Path) -> Result<(), AppError> {
+/// Reads the bundle and its source map from disk.
+fn load_source(config: &RuntimeConfig) -> Result<Bundle, AppError> {
+    let path = config.bundle_path.as_path();
     let metadata = fs::metadata(path).map_err(|err| {
         AppError::new(
             ErrorCode::BadRequest,
@@ -163,14 +427,31 @@ fn validate_bundle(path: &Path) -> Result<(), AppError> {
         .with_source(err)
     })?;
 
-    if !contents.contains("stream") {
-        return Err(AppError::new(
-            ErrorCode::BadRequest,
-            format!("bundle '{}' is missing a `stream` export", path.display()),
-        ));
-    }
+    let name = path.display().to_string();
+    let source_map = SourceMapper::load(path, &name, &contents, config.source_map_path.as_deref())?
+        .map(Arc::new);
+    Ok(Bundle {
+        console: Arc::new(ConsoleLog::new(
+            name.clone(),
+            &config.console,
+            source_map.clone(),
+        )),
+        name,
+        source: contents,
+        bytecode: None,
+        source_map,
+    })
+}
 
-    Ok(())
+/// Points the stack trace in `err`, if any, at the original sources.
+fn remap_error(source_map: Option<&SourceMapper>, err: AppError) -> AppError {
+    match source_map {
+        Some(source_map) => {
+            let message = source_map.remap(err.message());
+            err.with_message(message)
+        }
+        None => err,
+    }
 }
 
 #[cfg(test)]
@@ -197,6 +478,49 @@ mod tests {
         }
     }
 
+    /// Records the sequence of bridge calls that reached the writer.
+    #[derive(Default)]
+    struct RecordingWriter {
+        calls: Vec<String>,
+        fail_writes: bool,
+        hang_up_after_write: bool,
+    }
+
+    #[async_trait]
+    impl ResponseWriter for RecordingWriter {
+        async fn write(&mut self, chunk: String) -> Result<(), AppError> {
+            if self.fail_writes {
+                return Err(AppError::new(ErrorCode::Internal, "client went away"));
+            }
+            self.calls.push(format!("write:{chunk}"));
+            Ok(())
+        }
+
+        async fn flush(&mut self) -> Result<(), AppError> {
+            if self.calls.last().map(String::as_str) != Some("flush") {
+                self.calls.push("flush".to_string());
+            }
+            Ok(())
+        }
+
+        async fn close(&mut self) -> Result<(), AppError> {
+            self.calls.push("close".to_string());
+            Ok(())
+        }
+
+        async fn disconnected(&self) {
+            if !self.hang_up_after_write || self.calls.is_empty() {
+                std::future::pending().await
+            }
+        }
+    }
+
+    fn write_bundle(source: &str) -> NamedTempFile {
+        let mut bundle = NamedTempFile::new().expect("tmp file");
+        std::io::Write::write_all(&mut bundle, source.as_bytes()).expect("write bundle");
+        bundle
+    }
+
     #[tokio::test]
     async fn runtime_streams_chunks() {
         let mut bundle = NamedTempFile::new().expect("tmp file");
@@ -216,10 +540,377 @@ mod tests {
             .await
             .expect("chunks");
 
-        assert!(writer
-            .chunks
-            .iter()
-            .any(|chunk| chunk.contains("Streaming SSR response")));
+        assert_eq!(writer.chunks, vec!["<div>hello</div>".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn fetched_data_is_handed_to_the_bundle() {
+        let bundle = write_bundle(
+            "export function stream(ctx) {
+                ctx.write(JSON.stringify(ctx.data) + ' ' + ('data' in ctx.request));
+            }",
+        );
+        let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle.path())).expect("runtime");
+        let context = RequestContext::from_http_parts(&Method::GET, "/", &HeaderMap::new())
+            .with_data([("product".to_string(), serde_json::json!({ "id": 7 }))].into());
+
+        let mut writer = CollectingWriter::new();
+        runtime
+            .stream_response(&context, &mut writer)
+            .await
+            .expect("render");
+
+        assert_eq!(
+            writer.chunks,
+            vec![r#"{"product":{"id":7}} false"#.to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn console_calls_do_not_reach_the_response() {
+        let bundle = write_bundle(
+            "console.info('bundle loaded');
+            export function stream(ctx) {
+                const cyclic = { name: 'cyclic' };
+                cyclic.self = cyclic;
+                console.log('rendering', ctx.request.path, { n: 1 }, cyclic, new Error('shown'));
+                console.warn('x'.repeat(100000));
+                for (let i = 0; i < 1000; i++) console.debug(i);
+                ctx.write(typeof console.error);
+            }",
+        );
+        let config = RuntimeConfig::new(bundle.path()).with_console(ConsoleConfig {
+            messages_per_second: 1,
+            burst: 1,
+            max_arg_bytes: 16,
+        });
+        let runtime = RenderRuntime::try_new(config).expect("runtime");
+        let context = RequestContext::from_http_parts(&Method::GET, "/", &HeaderMap::new());
+
+        let mut writer = CollectingWriter::new();
+        runtime
+            .stream_response(&context, &mut writer)
+            .await
+            .expect("console output is not an error");
+        assert_eq!(writer.chunks, vec!["function".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn runtime_awaits_async_handlers() {
+        let mut bundle = NamedTempFile::new().expect("tmp file");
+        std::io::Write::write_all(
+            &mut bundle,
+            b"export async function stream(ctx) {
+                ctx.write(`<p>${ctx.request.path}</p>`);
+                await new Promise((resolve) => setTimeout(resolve, 5));
+                ctx.write('<p>done</p>');
+            }",
+        )
+        .expect("write bundle");
+
+        let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle.path())).expect("runtime");
+        let context = RequestContext::from_http_parts(&Method::GET, "/async", &HeaderMap::new());
+
+        let mut writer = CollectingWriter::new();
+        runtime
+            .stream_response(&context, &mut writer)
+            .await
+            .expect("chunks");
+
+        assert_eq!(writer.chunks, vec!["<p>/async</p>", "<p>done</p>"]);
+    }
+
+    #[tokio::test]
+    async fn runtime_surfaces_rejections() {
+        let mut bundle = NamedTempFile::new().expect("tmp file");
+        std::io::Write::write_all(
+            &mut bundle,
+            b"export async function stream() { throw new Error('boom'); }",
+        )
+        .expect("write bundle");
+
+        let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle.path())).expect("runtime");
+        let context = RequestContext::from_http_parts(&Method::GET, "/", &HeaderMap::new());
+
+        let err = runtime
+            .stream_response(&context, &mut CollectingWriter::new())
+            .await
+            .expect_err("render should fail");
+        assert!(err.message().contains("boom"));
+    }
+
+    #[tokio::test]
+    async fn runaway_loops_hit_the_cpu_slice() {
+        let mut bundle = NamedTempFile::new().expect("tmp file");
+        std::io::Write::write_all(&mut bundle, b"export function stream() { for (;;) {} }")
+            .expect("write bundle");
+
+        let config =
+            RuntimeConfig::new(bundle.path()).with_cpu_time_slice(Some(Duration::from_millis(50)));
+        let runtime = RenderRuntime::try_new(config).expect("runtime");
+        let context = RequestContext::from_http_parts(&Method::GET, "/", &HeaderMap::new());
+
+        let err = runtime
+            .stream_response(&context, &mut CollectingWriter::new())
+            .await
+            .expect_err("render should be interrupted");
+        assert!(matches!(err.code(), ErrorCode::RenderTimeout));
+        assert_eq!(runtime.pool_stats().retired, 1);
+
+        // The retired engine is replaced in the background.
+        let started = std::time::Instant::now();
+        while !runtime.is_warm() {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "pool never re-warmed"
+            );
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_renders_hit_the_render_timeout() {
+        let mut bundle = NamedTempFile::new().expect("tmp file");
+        std::io::Write::write_all(
+            &mut bundle,
+            b"export function stream() { return new Promise((resolve) => setTimeout(resolve, 60000)); }",
+        )
+        .expect("write bundle");
+
+        let config =
+            RuntimeConfig::new(bundle.path()).with_render_timeout(Some(Duration::from_millis(50)));
+        let runtime = RenderRuntime::try_new(config).expect("runtime");
+        let context = RequestContext::from_http_parts(&Method::GET, "/", &HeaderMap::new());
+
+        let err = runtime
+            .stream_response(&context, &mut CollectingWriter::new())
+            .await
+            .expect_err("render should time out");
+        assert!(matches!(err.code(), ErrorCode::RenderTimeout));
+    }
+
+    #[tokio::test]
+    async fn leaking_renders_hit_the_heap_limit() {
+        let mut bundle = NamedTempFile::new().expect("tmp file");
+        std::io::Write::write_all(
+            &mut bundle,
+            b"const leaked = [];
+            export function stream(ctx) {
+                if (ctx.request.path === '/leak') {
+                    for (;;) { leaked.push(new Array(1024).fill(leaked.length)); }
+                }
+                ctx.write('ok');
+            }",
+        )
+        .expect("write bundle");
+
+        let config =
+            RuntimeConfig::new(bundle.path()).with_heap_limit_bytes(Some(16 * 1024 * 1024));
+        let runtime = RenderRuntime::try_new(config).expect("runtime");
+
+        let leak = RequestContext::from_http_parts(&Method::GET, "/leak", &HeaderMap::new());
+        let err = runtime
+            .stream_response(&leak, &mut CollectingWriter::new())
+            .await
+            .expect_err("render should exhaust the heap");
+        assert!(matches!(err.code(), ErrorCode::OutOfMemory));
+        assert_eq!(runtime.pool_stats().retired, 1);
+
+        let healthy = RequestContext::from_http_parts(&Method::GET, "/", &HeaderMap::new());
+        let mut writer = CollectingWriter::new();
+        runtime
+            .stream_response(&healthy, &mut writer)
+            .await
+            .expect("replacement engine renders");
+        assert_eq!(writer.chunks, vec!["ok"]);
+    }
+
+    #[tokio::test]
+    async fn only_refused_allocations_count_as_out_of_memory() {
+        let bundle = write_bundle(
+            "export function stream(ctx) {
+                if (ctx.request.path === '/huge') {
+                    ctx.write(String(new ArrayBuffer(64 * 1024 * 1024).byteLength));
+                }
+                throw new Error('catalog service out of memory');
+            }",
+        );
+        let config =
+            RuntimeConfig::new(bundle.path()).with_heap_limit_bytes(Some(16 * 1024 * 1024));
+        let runtime = RenderRuntime::try_new(config).expect("runtime");
+
+        let thrown = RequestContext::from_http_parts(&Method::GET, "/", &HeaderMap::new());
+        let err = runtime
+            .stream_response(&thrown, &mut CollectingWriter::new())
+            .await
+            .expect_err("bundle throws");
+        assert!(matches!(err.code(), ErrorCode::Internal), "{err}");
+        assert_eq!(runtime.pool_stats().retired, 0);
+
+        let huge = RequestContext::from_http_parts(&Method::GET, "/huge", &HeaderMap::new());
+        let err = runtime
+            .stream_response(&huge, &mut CollectingWriter::new())
+            .await
+            .expect_err("allocation exceeds the heap limit");
+        assert!(matches!(err.code(), ErrorCode::OutOfMemory), "{err}");
+        assert_eq!(runtime.pool_stats().retired, 1);
+    }
+
+    #[tokio::test]
+    async fn bridge_forwards_flush_and_close() {
+        let bundle = write_bundle(
+            "export function stream(ctx) {
+                ctx.write('a');
+                ctx.flush();
+                ctx.write('b');
+                ctx.onError(new Error('recoverable'));
+                ctx.close();
+                ctx.close();
+            }",
+        );
+        let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle.path())).expect("runtime");
+        let context = RequestContext::from_http_parts(&Method::GET, "/", &HeaderMap::new());
+
+        let mut writer = RecordingWriter::default();
+        runtime
+            .stream_response(&context, &mut writer)
+            .await
+            .expect("render");
+
+        // The runtime may add its own flushes whenever the engine falls behind the writer.
+        assert_eq!(writer.calls[..2], ["write:a", "flush"]);
+        let mut calls = writer.calls;
+        calls.retain(|call| call != "flush");
+        assert_eq!(calls, vec!["write:a", "write:b", "close"]);
+    }
+
+    #[tokio::test]
+    async fn writes_after_close_are_rejected() {
+        let bundle = write_bundle(
+            "export function stream(ctx) {
+                ctx.close();
+                ctx.write('late');
+            }",
+        );
+        let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle.path())).expect("runtime");
+        let context = RequestContext::from_http_parts(&Method::GET, "/", &HeaderMap::new());
+
+        let err = runtime
+            .stream_response(&context, &mut RecordingWriter::default())
+            .await
+            .expect_err("write after close should fail");
+        assert!(err.message().contains("closed by the bundle"));
+    }
+
+    #[tokio::test]
+    async fn writer_failures_invoke_registered_abort() {
+        let bundle = write_bundle(
+            "export let aborted = false;
+            export function stream(ctx) {
+                return new Promise((resolve) => {
+                    const timer = setTimeout(resolve, 60000);
+                    ctx.registerAbort(() => {
+                        aborted = true;
+                        clearTimeout(timer);
+                        resolve();
+                    });
+                    ctx.write('shell');
+                });
+            }",
+        );
+        let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle.path())).expect("runtime");
+        let context = RequestContext::from_http_parts(&Method::GET, "/", &HeaderMap::new());
+
+        let mut writer = RecordingWriter {
+            fail_writes: true,
+            ..RecordingWriter::default()
+        };
+        let started = std::time::Instant::now();
+        runtime
+            .stream_response(&context, &mut writer)
+            .await
+            .expect_err("writer failure should surface");
+
+        // The engine is released once the abort callback settles the render.
+        drop(runtime.pool.checkout().await.expect("engine returned"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(runtime.pool_stats().retired, 0);
+    }
+
+    #[tokio::test]
+    async fn client_disconnects_cancel_the_render() {
+        let bundle = write_bundle(
+            "export function stream(ctx) {
+                return new Promise((resolve) => {
+                    const timer = setTimeout(resolve, 60000);
+                    ctx.registerAbort(() => {
+                        clearTimeout(timer);
+                        resolve();
+                    });
+                    ctx.write('shell');
+                });
+            }",
+        );
+        let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle.path())).expect("runtime");
+        let context = RequestContext::from_http_parts(&Method::GET, "/", &HeaderMap::new());
+
+        let mut writer = RecordingWriter {
+            hang_up_after_write: true,
+            ..RecordingWriter::default()
+        };
+        let started = std::time::Instant::now();
+        let err = runtime
+            .stream_response(&context, &mut writer)
+            .await
+            .expect_err("disconnect should cancel the render");
+
+        assert!(matches!(err.code(), ErrorCode::ClientClosed), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let stats = runtime.pool_stats();
+        assert_eq!((stats.in_use, stats.idle, stats.retired), (0, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn render_errors_point_at_the_original_sources() {
+        let dir = tempfile::tempdir().expect("tmp dir");
+        let bundle = dir.path().join("server.js");
+        fs::write(
+            &bundle,
+            "export function stream(ctx) {\n  throw new Error('boom');\n}\n",
+        )
+        .expect("write bundle");
+        // Maps all of bundle line 2 to line 42, column 3 of the original source.
+        let mut builder = sourcemap::SourceMapBuilder::new(Some("server.js"));
+        builder.add(1, 0, 41, 2, Some("../src/entry-server.tsx"), None, false);
+        let mut map = Vec::new();
+        builder
+            .into_sourcemap()
+            .to_writer(&mut map)
+            .expect("encode map");
+        fs::write(dir.path().join("server.js.map"), map).expect("write map");
+
+        let runtime = RenderRuntime::try_new(RuntimeConfig::new(&bundle)).expect("runtime");
+        let context = RequestContext::from_http_parts(&Method::GET, "/", &HeaderMap::new());
+        let err = runtime
+            .stream_response(&context, &mut CollectingWriter::new())
+            .await
+            .expect_err("bundle throws");
+
+        assert!(
+            err.message()
+                .contains("at stream (../src/entry-server.tsx:42:3)"),
+            "{err}"
+        );
+        assert!(!err.message().contains("server.js:"), "{err}");
+    }
+
+    #[test]
+    fn an_explicit_source_map_must_exist() {
+        let bundle = write_bundle("export function stream(ctx) {}");
+        let config =
+            RuntimeConfig::new(bundle.path()).with_source_map("/nonexistent/server.js.map");
+        let err = RenderRuntime::try_new(config).expect_err("missing map");
+        assert!(err.message().contains("could not be read"), "{err}");
     }
 
     #[test]
@@ -0,0 +1,237 @@
+use std::{
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+    time::{Duration, Instant},
+};
+
+use common::{AppError, ErrorCode};
+
+use crate::heap::HeapMeter;
+
+/// Fraction of the heap limit at which a render is aborted before QuickJS runs out of memory.
+const NEAR_HEAP_LIMIT: f64 = 0.9;
+
+/// Resource limits applied to every engine in a pool.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct EngineLimits {
+    pub(crate) render_timeout: Option<Duration>,
+    pub(crate) cpu_time_slice: Option<Duration>,
+    pub(crate) heap_limit_bytes: Option<usize>,
+}
+
+impl EngineLimits {
+    /// Heap usage at which the engine is considered close to exhausting its limit.
+    pub(crate) fn heap_threshold(&self) -> Option<usize> {
+        self.heap_limit_bytes
+            .map(|limit| (limit as f64 * NEAR_HEAP_LIMIT) as usize)
+    }
+}
+
+/// Why the interrupt handler stopped the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Overrun {
+    RenderTimeout,
+    CpuTimeSlice,
+    HeapLimit,
+}
+
+const NO_DEADLINE: u64 = u64::MAX;
+const NOT_TRIPPED: u8 = 0;
+
+/// Deadlines shared between an engine and its interrupt handler.
+///
+/// Deadlines are stored as nanoseconds since `anchor` so they can be updated without locking
+/// from the render thread while QuickJS polls them.
+#[derive(Debug)]
+pub(crate) struct Budget {
+    anchor: Instant,
+    render_deadline: AtomicU64,
+    slice_deadline: AtomicU64,
+    tripped: AtomicU8,
+    heap: HeapMeter,
+    heap_threshold: usize,
+}
+
+impl Budget {
+    pub(crate) fn new(heap: HeapMeter, limits: &EngineLimits) -> Self {
+        Self {
+            anchor: Instant::now(),
+            render_deadline: AtomicU64::new(NO_DEADLINE),
+            slice_deadline: AtomicU64::new(NO_DEADLINE),
+            tripped: AtomicU8::new(NOT_TRIPPED),
+            heap,
+            heap_threshold: limits.heap_threshold().unwrap_or(usize::MAX),
+        }
+    }
+
+    /// Arms the wall-clock deadline for a render and clears any previous overrun.
+    pub(crate) fn start_render(&self, timeout: Option<Duration>) {
+        self.tripped.store(NOT_TRIPPED, Ordering::Relaxed);
+        self.heap.clear_failure();
+        self.render_deadline
+            .store(self.deadline_after(timeout), Ordering::Relaxed);
+    }
+
+    /// Disarms every deadline once the render has finished.
+    pub(crate) fn finish_render(&self) {
+        self.render_deadline.store(NO_DEADLINE, Ordering::Relaxed);
+        self.slice_deadline.store(NO_DEADLINE, Ordering::Relaxed);
+    }
+
+    /// Arms the CPU slice deadline before control is handed to JavaScript.
+    pub(crate) fn enter_js(&self, slice: Option<Duration>) {
+        self.slice_deadline
+            .store(self.deadline_after(slice), Ordering::Relaxed);
+    }
+
+    /// Disarms the CPU slice deadline while the host is idle or blocked.
+    pub(crate) fn leave_js(&self) {
+        self.slice_deadline.store(NO_DEADLINE, Ordering::Relaxed);
+    }
+
+    /// Time left before the render deadline, if one is armed.
+    pub(crate) fn render_remaining(&self) -> Option<Duration> {
+        let deadline = self.render_deadline.load(Ordering::Relaxed);
+        (deadline != NO_DEADLINE)
+            .then(|| Duration::from_nanos(deadline.saturating_sub(self.elapsed_nanos())))
+    }
+
+    /// Records an overrun detected by the host rather than the interrupt handler.
+    pub(crate) fn trip(&self, overrun: Overrun) {
+        self.tripped.store(overrun as u8 + 1, Ordering::Relaxed);
+    }
+
+    /// Returns the overrun that stopped the current render, if any.
+    pub(crate) fn overrun(&self) -> Option<Overrun> {
+        match self.tripped.load(Ordering::Relaxed) {
+            1 => Some(Overrun::RenderTimeout),
+            2 => Some(Overrun::CpuTimeSlice),
+            3 => Some(Overrun::HeapLimit),
+            _ => None,
+        }
+    }
+
+    /// Whether the heap limit refused an allocation during the current render.
+    pub(crate) fn allocation_failed(&self) -> bool {
+        self.heap.allocation_failed()
+    }
+
+    /// Whether the engine heap has grown close to its limit.
+    pub(crate) fn heap_exhausted(&self) -> bool {
+        self.heap.used() >= self.heap_threshold
+    }
+
+    /// Polled by the QuickJS interrupt handler; returns `true` to terminate execution.
+    pub(crate) fn should_interrupt(&self) -> bool {
+        if self.heap_exhausted() {
+            self.trip(Overrun::HeapLimit);
+            return true;
+        }
+        let now = self.elapsed_nanos();
+        if now >= self.render_deadline.load(Ordering::Relaxed) {
+            self.trip(Overrun::RenderTimeout);
+            return true;
+        }
+        if now >= self.slice_deadline.load(Ordering::Relaxed) {
+            self.trip(Overrun::CpuTimeSlice);
+            return true;
+        }
+        false
+    }
+
+    fn deadline_after(&self, duration: Option<Duration>) -> u64 {
+        duration
+            .map(|duration| {
+                self.elapsed_nanos()
+                    .saturating_add(duration.as_nanos().try_into().unwrap_or(NO_DEADLINE))
+            })
+            .unwrap_or(NO_DEADLINE)
+    }
+
+    fn elapsed_nanos(&self) -> u64 {
+        self.anchor
+            .elapsed()
+            .as_nanos()
+            .try_into()
+            .unwrap_or(NO_DEADLINE)
+    }
+}
+
+impl Overrun {
+    /// Converts the overrun into the error surfaced to callers.
+    pub(crate) fn into_error(self, limits: &EngineLimits) -> AppError {
+        match self {
+            Overrun::RenderTimeout => AppError::new(
+                ErrorCode::RenderTimeout,
+                format!(
+                    "render exceeded the {}ms time limit",
+                    limits.render_timeout.unwrap_or_default().as_millis()
+                ),
+            ),
+            Overrun::CpuTimeSlice => AppError::new(
+                ErrorCode::RenderTimeout,
+                format!(
+                    "render ran JavaScript for more than {}ms without yielding",
+                    limits.cpu_time_slice.unwrap_or_default().as_millis()
+                ),
+            ),
+            Overrun::HeapLimit => AppError::new(
+                ErrorCode::OutOfMemory,
+                format!(
+                    "render exhausted the {} byte heap limit",
+                    limits.heap_limit_bytes.unwrap_or_default()
+                ),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_without_deadlines_never_interrupts() {
+        let budget = Budget::new(HeapMeter::default(), &EngineLimits::default());
+        budget.start_render(None);
+        budget.enter_js(None);
+
+        assert!(!budget.should_interrupt());
+        assert_eq!(budget.render_remaining(), None);
+    }
+
+    #[test]
+    fn expired_slice_trips_cpu_overrun() {
+        let budget = Budget::new(HeapMeter::default(), &EngineLimits::default());
+        budget.start_render(Some(Duration::from_secs(60)));
+        budget.enter_js(Some(Duration::ZERO));
+
+        assert!(budget.should_interrupt());
+        assert_eq!(budget.overrun(), Some(Overrun::CpuTimeSlice));
+
+        budget.start_render(Some(Duration::from_secs(60)));
+        assert_eq!(budget.overrun(), None);
+    }
+
+    #[test]
+    fn heap_near_limit_trips_heap_overrun() {
+        let limits = EngineLimits {
+            heap_limit_bytes: Some(0),
+            ..EngineLimits::default()
+        };
+        let budget = Budget::new(HeapMeter::default(), &limits);
+        budget.start_render(None);
+
+        assert!(budget.should_interrupt());
+        assert_eq!(budget.overrun(), Some(Overrun::HeapLimit));
+    }
+
+    #[test]
+    fn idle_host_is_not_charged_for_cpu() {
+        let budget = Budget::new(HeapMeter::default(), &EngineLimits::default());
+        budget.start_render(None);
+        budget.enter_js(Some(Duration::ZERO));
+        budget.leave_js();
+
+        assert!(!budget.should_interrupt());
+    }
+}
@@ -0,0 +1,518 @@
+use std::{
+    fmt,
+    ops::Deref,
+    sync::{Arc, Mutex, MutexGuard, Weak},
+    time::{Duration, Instant},
+};
+
+use common::{AppError, ErrorCode};
+use serde::Serialize;
+use tokio::{
+    runtime::Handle,
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task, time,
+};
+use tracing::warn;
+
+use crate::{
+    engine::{Bundle, Engine},
+    limits::EngineLimits,
+};
+
+/// Sizing and recycling parameters for the engine pool.
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// Engines kept warm even when idle.
+    pub min_size: usize,
+    /// Upper bound on engines alive at once; further checkouts wait for a release.
+    pub max_size: usize,
+    /// Idle engines above `min_size` are evicted once unused for this long.
+    pub idle_timeout: Duration,
+    /// Engines are discarded after serving this many renders, if set.
+    pub max_renders_per_engine: Option<u64>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            min_size: 1,
+            max_size: 4,
+            idle_timeout: Duration::from_secs(300),
+            max_renders_per_engine: Some(1_000),
+        }
+    }
+}
+
+/// Point-in-time counters describing the engine pool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PoolStats {
+    /// Engines waiting to be checked out.
+    pub idle: usize,
+    /// Engines currently serving a render.
+    pub in_use: usize,
+    /// Engines created since the pool started.
+    pub created: u64,
+    /// Engines discarded because they sat idle past `idle_timeout`, reached
+    /// `max_renders_per_engine`, or were left unusable by a render that overran its time or heap
+    /// limit.
+    pub retired: u64,
+}
+
+struct IdleEngine {
+    engine: Engine,
+    renders: u64,
+    idle_since: Instant,
+}
+
+struct PoolState {
+    idle: Vec<IdleEngine>,
+    /// Replacement engines currently being started.
+    replacing: usize,
+    stats: PoolStats,
+}
+
+struct PoolInner {
+    config: PoolConfig,
+    bundle: Bundle,
+    limits: EngineLimits,
+    state: Mutex<PoolState>,
+}
+
+/// Shortest pause between idle sweeps, however soon the next engine expires.
+const MIN_REAP_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Pool of pre-initialised engines that renders check out and return.
+#[derive(Clone)]
+pub(crate) struct EnginePool {
+    inner: Arc<PoolInner>,
+    permits: Arc<Semaphore>,
+}
+
+impl fmt::Debug for EnginePool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnginePool")
+            .field("config", &self.inner.config)
+            .field("bundle", &self.inner.bundle.name)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl EnginePool {
+    /// Creates a pool seeded with `seed` and warmed up to `min_size` engines.
+    pub(crate) fn new(
+        config: PoolConfig,
+        bundle: Bundle,
+        limits: EngineLimits,
+        seed: Engine,
+    ) -> Result<Self, AppError> {
+        if config.max_size == 0 {
+            return Err(AppError::new(
+                ErrorCode::BadRequest,
+                "engine pool max_size must be positive",
+            ));
+        }
+        if config.min_size > config.max_size {
+            return Err(AppError::new(
+                ErrorCode::BadRequest,
+                format!(
+                    "engine pool min_size {} exceeds max_size {}",
+                    config.min_size, config.max_size
+                ),
+            ));
+        }
+
+        let mut idle = vec![IdleEngine::new(seed, 0)];
+        while idle.len() < config.min_size {
+            idle.push(IdleEngine::new(Engine::new(&bundle, limits)?, 0));
+        }
+
+        let stats = PoolStats {
+            idle: idle.len(),
+            created: idle.len() as u64,
+            ..PoolStats::default()
+        };
+        let permits = Arc::new(Semaphore::new(config.max_size));
+        let inner = Arc::new(PoolInner {
+            config,
+            bundle,
+            limits,
+            state: Mutex::new(PoolState {
+                idle,
+                replacing: 0,
+                stats,
+            }),
+        });
+        spawn_reaper(&inner);
+
+        Ok(Self { inner, permits })
+    }
+
+    /// Checks out an idle engine, creating one when none is available.
+    ///
+    /// Waits while `max_size` engines are already in use.
+    pub(crate) async fn checkout(&self) -> Result<EngineLease, AppError> {
+        let permit = Arc::clone(&self.permits)
+            .acquire_owned()
+            .await
+            .map_err(|err| {
+                AppError::new(ErrorCode::Internal, "engine pool is closed").with_source(err)
+            })?;
+
+        let reused = {
+            let mut state = self.inner.lock();
+            self.inner.evict_idle(&mut state);
+            let reused = state.idle.pop();
+            if reused.is_some() {
+                state.stats.idle -= 1;
+                state.stats.in_use += 1;
+            }
+            reused
+        };
+
+        let (engine, renders) = match reused {
+            Some(idle) => (idle.engine, idle.renders),
+            None => {
+                let inner = Arc::clone(&self.inner);
+                let engine = task::spawn_blocking(move || Engine::new(&inner.bundle, inner.limits))
+                    .await
+                    .map_err(|err| {
+                        AppError::new(ErrorCode::Internal, "engine start-up task failed")
+                            .with_source(err)
+                    })??;
+
+                let mut state = self.inner.lock();
+                state.stats.created += 1;
+                state.stats.in_use += 1;
+                (engine, 0)
+            }
+        };
+
+        Ok(EngineLease {
+            engine: Some(engine),
+            renders,
+            pool: Arc::clone(&self.inner),
+            _permit: permit,
+        })
+    }
+
+    /// Returns the current pool counters.
+    pub(crate) fn stats(&self) -> PoolStats {
+        self.inner.lock().stats
+    }
+}
+
+impl PoolInner {
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().expect("engine pool mutex poisoned")
+    }
+
+    fn evict_idle(&self, state: &mut PoolState) {
+        let now = Instant::now();
+        let min_size = self.config.min_size;
+        // Idle engines are kept oldest first, so the stalest ones are evicted first.
+        while state.idle.len() > min_size
+            && now.duration_since(state.idle[0].idle_since) >= self.config.idle_timeout
+        {
+            state.idle.remove(0);
+            state.stats.idle -= 1;
+            state.stats.retired += 1;
+        }
+    }
+
+    /// Evicts expired idle engines and returns how long until the next one could expire.
+    fn reap(&self) -> Duration {
+        let mut state = self.lock();
+        self.evict_idle(&mut state);
+        let next = if state.idle.len() > self.config.min_size {
+            self.config
+                .idle_timeout
+                .saturating_sub(state.idle[0].idle_since.elapsed())
+        } else {
+            // An engine checked in from now on expires one timeout later at the earliest.
+            self.config.idle_timeout
+        };
+        next.max(MIN_REAP_INTERVAL)
+    }
+
+    fn checkin(self: &Arc<Self>, engine: Engine, renders: u64) {
+        let mut state = self.lock();
+        state.stats.in_use -= 1;
+
+        let exhausted = self
+            .config
+            .max_renders_per_engine
+            .is_some_and(|limit| renders >= limit);
+        if exhausted || engine.is_poisoned() {
+            state.stats.retired += 1;
+        } else {
+            state.idle.push(IdleEngine::new(engine, renders));
+            state.stats.idle += 1;
+        }
+
+        self.evict_idle(&mut state);
+
+        let alive = state.idle.len() + state.stats.in_use + state.replacing;
+        let deficit = self.config.min_size.saturating_sub(alive);
+        state.replacing += deficit;
+        drop(state);
+
+        if deficit > 0 {
+            self.replace(deficit);
+        }
+    }
+
+    /// Starts `count` engines to stand in for retired ones, off the caller's thread if possible.
+    fn replace(self: &Arc<Self>, count: usize) {
+        let pool = Arc::clone(self);
+        let refill = move || {
+            for _ in 0..count {
+                let engine = Engine::new(&pool.bundle, pool.limits);
+                let mut state = pool.lock();
+                state.replacing -= 1;
+                match engine {
+                    Ok(engine) => {
+                        state.idle.push(IdleEngine::new(engine, 0));
+                        state.stats.idle += 1;
+                        state.stats.created += 1;
+                    }
+                    Err(err) => warn!(error = %err, "failed to replace retired engine"),
+                }
+            }
+        };
+
+        match Handle::try_current() {
+            Ok(handle) => drop(handle.spawn_blocking(refill)),
+            Err(_) => refill(),
+        }
+    }
+}
+
+/// Evicts idle engines in the background, so a pool that goes quiet after a burst shrinks back
+/// to `min_size` without waiting for another checkout. Stops once the pool is dropped. Pools
+/// created outside a Tokio runtime, or with no idle timeout, only evict on checkout and checkin.
+fn spawn_reaper(inner: &Arc<PoolInner>) {
+    let Ok(handle) = Handle::try_current() else {
+        return;
+    };
+    if inner.config.idle_timeout.is_zero() || inner.config.min_size == inner.config.max_size {
+        return;
+    }
+    let pool: Weak<PoolInner> = Arc::downgrade(inner);
+    let mut wait = inner.config.idle_timeout;
+    handle.spawn(async move {
+        loop {
+            time::sleep(wait).await;
+            let Some(pool) = pool.upgrade() else {
+                return;
+            };
+            wait = pool.reap();
+        }
+    });
+}
+
+impl IdleEngine {
+    fn new(engine: Engine, renders: u64) -> Self {
+        Self {
+            engine,
+            renders,
+            idle_since: Instant::now(),
+        }
+    }
+}
+
+/// Exclusive handle on a pooled engine; returns the engine to the pool when dropped.
+pub(crate) struct EngineLease {
+    engine: Option<Engine>,
+    renders: u64,
+    pool: Arc<PoolInner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl EngineLease {
+    /// Records a completed render against the engine's recycle budget.
+    pub(crate) fn record_render(&mut self) {
+        self.renders += 1;
+    }
+}
+
+impl Deref for EngineLease {
+    type Target = Engine;
+
+    fn deref(&self) -> &Engine {
+        self.engine.as_ref().expect("engine lease already released")
+    }
+}
+
+impl Drop for EngineLease {
+    fn drop(&mut self) {
+        if let Some(engine) = self.engine.take() {
+            self.pool.checkin(engine, self.renders);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::{ConsoleConfig, ConsoleLog};
+
+    const SOURCE: &str = "export function stream(ctx) { ctx.write('ok'); }";
+
+    fn pool(config: PoolConfig) -> EnginePool {
+        let bundle = Bundle {
+            name: "test.js".to_string(),
+            source: SOURCE.to_string(),
+            bytecode: None,
+            console: Arc::new(ConsoleLog::new("test.js", &ConsoleConfig::default(), None)),
+            source_map: None,
+        };
+        let seed = Engine::new(&bundle, EngineLimits::default()).expect("engine");
+        EnginePool::new(config, bundle, EngineLimits::default(), seed).expect("pool")
+    }
+
+    #[test]
+    fn pool_warms_up_to_min_size() {
+        let pool = pool(PoolConfig {
+            min_size: 3,
+            ..PoolConfig::default()
+        });
+
+        assert_eq!(pool.stats().idle, 3);
+        assert_eq!(pool.stats().created, 3);
+    }
+
+    #[test]
+    fn pool_rejects_inverted_bounds() {
+        let bundle = Bundle {
+            name: "test.js".to_string(),
+            source: SOURCE.to_string(),
+            bytecode: None,
+            console: Arc::new(ConsoleLog::new("test.js", &ConsoleConfig::default(), None)),
+            source_map: None,
+        };
+        let seed = Engine::new(&bundle, EngineLimits::default()).expect("engine");
+        let config = PoolConfig {
+            min_size: 5,
+            max_size: 2,
+            ..PoolConfig::default()
+        };
+
+        assert!(EnginePool::new(config, bundle, EngineLimits::default(), seed).is_err());
+    }
+
+    #[tokio::test]
+    async fn engines_are_reused_between_checkouts() {
+        let pool = pool(PoolConfig::default());
+
+        drop(pool.checkout().await.expect("first checkout"));
+        drop(pool.checkout().await.expect("second checkout"));
+
+        let stats = pool.stats();
+        assert_eq!(stats.created, 1);
+        assert_eq!(stats.idle, 1);
+        assert_eq!(stats.in_use, 0);
+    }
+
+    #[tokio::test]
+    async fn engines_are_recycled_after_render_limit() {
+        let pool = pool(PoolConfig {
+            min_size: 0,
+            max_renders_per_engine: Some(2),
+            ..PoolConfig::default()
+        });
+
+        for _ in 0..2 {
+            let mut lease = pool.checkout().await.expect("checkout");
+            lease.record_render();
+        }
+
+        let stats = pool.stats();
+        assert_eq!(stats.retired, 1);
+        assert_eq!(stats.idle, 0);
+
+        drop(pool.checkout().await.expect("fresh engine"));
+        assert_eq!(pool.stats().created, 2);
+    }
+
+    #[tokio::test]
+    async fn checkouts_wait_for_capacity() {
+        let pool = pool(PoolConfig {
+            max_size: 1,
+            ..PoolConfig::default()
+        });
+
+        let lease = pool.checkout().await.expect("checkout");
+        let waiting = tokio::time::timeout(Duration::from_millis(20), pool.checkout()).await;
+        assert!(waiting.is_err(), "second checkout should wait");
+
+        drop(lease);
+        let lease = tokio::time::timeout(Duration::from_secs(1), pool.checkout())
+            .await
+            .expect("checkout after release");
+        assert!(lease.is_ok());
+    }
+
+    #[test]
+    fn retired_engines_are_replaced_up_to_min_size() {
+        let pool = pool(PoolConfig {
+            min_size: 1,
+            max_renders_per_engine: Some(1),
+            ..PoolConfig::default()
+        });
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("runtime");
+        let mut lease = runtime.block_on(pool.checkout()).expect("checkout");
+        lease.record_render();
+        drop(runtime);
+        drop(lease);
+
+        let stats = pool.stats();
+        assert_eq!(stats.retired, 1);
+        assert_eq!(stats.idle, 1);
+        assert_eq!(stats.created, 2);
+    }
+
+    #[tokio::test]
+    async fn idle_engines_are_reaped_without_further_checkouts() {
+        let pool = pool(PoolConfig {
+            min_size: 1,
+            max_size: 3,
+            idle_timeout: Duration::from_millis(50),
+            ..PoolConfig::default()
+        });
+
+        let leases = [
+            pool.checkout().await.expect("first"),
+            pool.checkout().await.expect("second"),
+            pool.checkout().await.expect("third"),
+        ];
+        drop(leases);
+        assert_eq!(pool.stats().idle, 3);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let stats = pool.stats();
+        assert_eq!(stats.idle, 1);
+        assert_eq!(stats.retired, 2);
+    }
+
+    #[tokio::test]
+    async fn idle_engines_above_min_size_are_evicted() {
+        let pool = pool(PoolConfig {
+            min_size: 1,
+            idle_timeout: Duration::ZERO,
+            ..PoolConfig::default()
+        });
+
+        let first = pool.checkout().await.expect("first");
+        let second = pool.checkout().await.expect("second");
+        drop(first);
+        drop(second);
+
+        let stats = pool.stats();
+        assert_eq!(stats.idle, 1);
+        assert_eq!(stats.retired, 1);
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+use common::{AppError, ErrorCode};
+use sha2::{Digest, Sha256};
+
+use super::{BuildMetadata, BundleRegistry, BundleVersion, DEFAULT_BUNDLE};
+
+/// Bundle file name used when a version's `metadata.json` does not name one.
+const DEFAULT_ENTRY: &str = "bundle.js";
+const METADATA_FILE: &str = "metadata.json";
+const VERSIONS_DIR: &str = "versions";
+/// Holds the name of the active version.
+const CURRENT_FILE: &str = "current";
+/// Versions activated so far, one per line and newest last; rollbacks pop from it.
+const HISTORY_FILE: &str = "history";
+
+/// A registry kept in a local directory, laid out as
+///
+/// ```text
+/// <root>/<bundle>/versions/<version>/bundle.js      (plus bundle.js.map and metadata.json)
+/// <root>/<bundle>/current                           name of the active version
+/// <root>/<bundle>/history                           previously active versions
+/// ```
+///
+/// A route uses the bundle directory named after its id, or `default` when there is none.
+#[derive(Debug)]
+pub struct FsBundleRegistry {
+    root: PathBuf,
+    /// Serialises pointer updates made through this registry.
+    writes: Mutex<()>,
+}
+
+impl FsBundleRegistry {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            writes: Mutex::new(()),
+        }
+    }
+
+    /// The directory holding the bundle for `route_id`.
+    fn bundle_dir(&self, route_id: &str) -> Result<PathBuf, AppError> {
+        check_name("route id", route_id)?;
+        let own = self.root.join(route_id);
+        if own.join(VERSIONS_DIR).is_dir() {
+            return Ok(own);
+        }
+        let default = self.root.join(DEFAULT_BUNDLE);
+        if default.join(VERSIONS_DIR).is_dir() {
+            return Ok(default);
+        }
+        Err(AppError::new(
+            ErrorCode::NotFound,
+            format!(
+                "registry '{}' has no bundle for route '{route_id}' and no default bundle",
+                self.root.display()
+            ),
+        ))
+    }
+
+    fn load_version(&self, dir: &Path, version: &str) -> Result<BundleVersion, AppError> {
+        check_name("version", version)?;
+        let version_dir = dir.join(VERSIONS_DIR).join(version);
+        if !version_dir.is_dir() {
+            return Err(AppError::new(
+                ErrorCode::NotFound,
+                format!(
+                    "bundle version '{version}' does not exist in '{}'",
+                    dir.display()
+                ),
+            ));
+        }
+
+        let metadata_path = version_dir.join(METADATA_FILE);
+        let metadata: BuildMetadata = match fs::read(&metadata_path) {
+            Ok(contents) => serde_json::from_slice(&contents).map_err(|err| {
+                AppError::new(
+                    ErrorCode::BadRequest,
+                    format!("'{}' is invalid: {err}", metadata_path.display()),
+                )
+            })?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BuildMetadata::default(),
+            Err(err) => return Err(io_error(&metadata_path, err)),
+        };
+
+        let entry = metadata.entry.as_deref().unwrap_or(DEFAULT_ENTRY);
+        check_name("bundle entry", entry)?;
+        let bundle_path = version_dir.join(entry);
+        let contents = fs::read(&bundle_path).map_err(|err| io_error(&bundle_path, err))?;
+        let mut source_map_path = bundle_path.clone().into_os_string();
+        source_map_path.push(".map");
+        let source_map_path = PathBuf::from(source_map_path);
+
+        Ok(BundleVersion {
+            version: version.to_string(),
+            source_map_path: source_map_path.is_file().then_some(source_map_path),
+            bundle_path,
+            content_hash: hex::encode(Sha256::digest(&contents)),
+            metadata,
+        })
+    }
+
+    /// The activation history with the current version last.
+    fn history(&self, dir: &Path) -> Result<Vec<String>, AppError> {
+        let current = read_pointer(&dir.join(CURRENT_FILE))?.ok_or_else(|| {
+            AppError::new(
+                ErrorCode::NotFound,
+                format!("'{}' has no active version", dir.display()),
+            )
+        })?;
+        let mut history: Vec<String> = read_pointer(&dir.join(HISTORY_FILE))?
+            .map(|history| history.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        // `current` may have been written by hand; it wins over the history.
+        if history.last() != Some(&current) {
+            history.push(current);
+        }
+        Ok(history)
+    }
+
+    fn write_pointers(&self, dir: &Path, history: &[String]) -> Result<(), AppError> {
+        let current = history.last().map(String::as_str).unwrap_or_default();
+        replace_file(&dir.join(HISTORY_FILE), &history.join("\n"))?;
+        replace_file(&dir.join(CURRENT_FILE), current)
+    }
+}
+
+#[async_trait]
+impl BundleRegistry for FsBundleRegistry {
+    async fn resolve(&self, route_id: &str) -> Result<BundleVersion, AppError> {
+        let dir = self.bundle_dir(route_id)?;
+        let history = self.history(&dir)?;
+        let current = history
+            .last()
+            .expect("history ends with the current version");
+        self.load_version(&dir, current)
+    }
+
+    async fn versions(&self, route_id: &str) -> Result<Vec<BundleVersion>, AppError> {
+        let dir = self.bundle_dir(route_id)?;
+        let versions_dir = dir.join(VERSIONS_DIR);
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&versions_dir).map_err(|err| io_error(&versions_dir, err))? {
+            let entry = entry.map_err(|err| io_error(&versions_dir, err))?;
+            if entry.path().is_dir() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        names
+            .iter()
+            .map(|name| self.load_version(&dir, name))
+            .collect()
+    }
+
+    async fn version(&self, route_id: &str, version: &str) -> Result<BundleVersion, AppError> {
+        let dir = self.bundle_dir(route_id)?;
+        self.load_version(&dir, version)
+    }
+
+    async fn previous(&self, route_id: &str) -> Result<Option<BundleVersion>, AppError> {
+        let dir = self.bundle_dir(route_id)?;
+        let mut history = self.history(&dir)?;
+        history.pop();
+        history
+            .last()
+            .map(|previous| self.load_version(&dir, previous))
+            .transpose()
+    }
+
+    async fn activate(&self, route_id: &str, version: &str) -> Result<BundleVersion, AppError> {
+        let dir = self.bundle_dir(route_id)?;
+        let activated = self.load_version(&dir, version)?;
+
+        let _guard = self.writes.lock().expect("registry write mutex poisoned");
+        let mut history = match self.history(&dir) {
+            Ok(history) => history,
+            Err(err) if matches!(err.code(), ErrorCode::NotFound) => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        if history.last().map(String::as_str) != Some(version) {
+            history.push(version.to_string());
+            self.write_pointers(&dir, &history)?;
+        }
+        Ok(activated)
+    }
+
+    async fn rollback(&self, route_id: &str) -> Result<BundleVersion, AppError> {
+        let dir = self.bundle_dir(route_id)?;
+
+        let _guard = self.writes.lock().expect("registry write mutex poisoned");
+        let mut history = self.history(&dir)?;
+        let current = history
+            .pop()
+            .expect("history ends with the current version");
+        let Some(previous) = history.last() else {
+            return Err(AppError::new(
+                ErrorCode::BadRequest,
+                format!("version '{current}' has no earlier version to roll back to"),
+            ));
+        };
+        let restored = self.load_version(&dir, previous)?;
+        self.write_pointers(&dir, &history)?;
+        Ok(restored)
+    }
+}
+
+/// Rejects names that would escape the registry directory.
+fn check_name(kind: &str, name: &str) -> Result<(), AppError> {
+    let valid = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\\'])
+        && !name.contains('\0');
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::new(
+            ErrorCode::BadRequest,
+            format!("{kind} '{name}' is not a valid name"),
+        ))
+    }
+}
+
+/// Reads a pointer file, trimmed; `None` when it does not exist or is empty.
+fn read_pointer(path: &Path) -> Result<Option<String>, AppError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => {
+            let contents = contents.trim();
+            Ok((!contents.is_empty()).then(|| contents.to_string()))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(io_error(path, err)),
+    }
+}
+
+/// Replaces `path` with `contents` atomically, so readers never see a partial pointer.
+fn replace_file(path: &Path, contents: &str) -> Result<(), AppError> {
+    let mut staged = path.as_os_str().to_owned();
+    staged.push(".tmp");
+    let staged = PathBuf::from(staged);
+    fs::write(&staged, format!("{contents}\n")).map_err(|err| io_error(&staged, err))?;
+    fs::rename(&staged, path).map_err(|err| io_error(path, err))
+}
+
+fn io_error(path: &Path, err: io::Error) -> AppError {
+    let code = if err.kind() == io::ErrorKind::NotFound {
+        ErrorCode::NotFound
+    } else {
+        ErrorCode::Internal
+    };
+    AppError::new(code, format!("failed to access '{}'", path.display())).with_source(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn add_version(root: &Path, bundle: &str, version: &str, source: &str) {
+        let dir = root.join(bundle).join(VERSIONS_DIR).join(version);
+        fs::create_dir_all(&dir).expect("version dir");
+        fs::write(dir.join(DEFAULT_ENTRY), source).expect("bundle");
+    }
+
+    fn registry() -> (TempDir, FsBundleRegistry) {
+        let root = TempDir::new().expect("registry dir");
+        add_version(
+            root.path(),
+            DEFAULT_BUNDLE,
+            "v1",
+            "export function stream() {}",
+        );
+        add_version(
+            root.path(),
+            DEFAULT_BUNDLE,
+            "v2",
+            "export function stream() { }",
+        );
+        fs::write(root.path().join(DEFAULT_BUNDLE).join(CURRENT_FILE), "v1\n").expect("current");
+        let registry = FsBundleRegistry::new(root.path());
+        (root, registry)
+    }
+
+    #[tokio::test]
+    async fn routes_resolve_to_the_current_version() {
+        let (root, registry) = registry();
+        let version = registry.resolve("home").await.expect("resolve");
+
+        assert_eq!(version.version, "v1");
+        assert_eq!(version.content_hash.len(), 64);
+        assert!(version.source_map_path.is_none());
+        assert!(version.bundle_path.starts_with(root.path()));
+
+        let versions = registry.versions("home").await.expect("versions");
+        let names: Vec<_> = versions
+            .iter()
+            .map(|version| version.version.as_str())
+            .collect();
+        assert_eq!(names, ["v1", "v2"]);
+        assert_ne!(versions[0].content_hash, versions[1].content_hash);
+    }
+
+    #[tokio::test]
+    async fn routes_with_their_own_directory_use_it() {
+        let (root, registry) = registry();
+        add_version(root.path(), "checkout", "c1", "export function stream() {}");
+        let dir = root.path().join("checkout");
+        fs::write(dir.join(CURRENT_FILE), "c1").expect("current");
+        fs::write(
+            dir.join(VERSIONS_DIR).join("c1").join(METADATA_FILE),
+            r#"{"commit": "abc123", "built_at": "2024-05-01T12:00:00Z", "pipeline": 42}"#,
+        )
+        .expect("metadata");
+
+        let version = registry.resolve("checkout").await.expect("resolve");
+        assert_eq!(version.version, "c1");
+        assert_eq!(version.metadata.commit.as_deref(), Some("abc123"));
+        assert_eq!(version.metadata.extra["pipeline"], 42);
+        assert_eq!(
+            registry.resolve("home").await.expect("resolve").version,
+            "v1"
+        );
+    }
+
+    #[tokio::test]
+    async fn activation_and_rollback_move_the_current_pointer() {
+        let (root, registry) = registry();
+        assert!(registry.previous("home").await.expect("previous").is_none());
+
+        assert_eq!(
+            registry
+                .activate("home", "v2")
+                .await
+                .expect("activate")
+                .version,
+            "v2"
+        );
+        assert_eq!(
+            registry.resolve("home").await.expect("resolve").version,
+            "v2"
+        );
+        let current = fs::read_to_string(root.path().join(DEFAULT_BUNDLE).join(CURRENT_FILE));
+        assert_eq!(current.expect("current").trim(), "v2");
+        assert_eq!(
+            registry
+                .previous("home")
+                .await
+                .expect("previous")
+                .map(|version| version.version),
+            Some("v1".to_string())
+        );
+
+        assert_eq!(
+            registry.rollback("home").await.expect("rollback").version,
+            "v1"
+        );
+        assert_eq!(
+            registry.resolve("home").await.expect("resolve").version,
+            "v1"
+        );
+        let err = registry
+            .rollback("home")
+            .await
+            .expect_err("nothing left to roll back to");
+        assert!(matches!(err.code(), ErrorCode::BadRequest), "{err}");
+    }
+
+    #[tokio::test]
+    async fn unknown_and_unsafe_versions_are_rejected() {
+        let (_root, registry) = registry();
+        let err = registry
+            .activate("home", "v9")
+            .await
+            .expect_err("unknown version");
+        assert!(matches!(err.code(), ErrorCode::NotFound), "{err}");
+        let err = registry
+            .activate("home", "../v1")
+            .await
+            .expect_err("unsafe version");
+        assert!(matches!(err.code(), ErrorCode::BadRequest), "{err}");
+        assert_eq!(
+            registry.resolve("home").await.expect("resolve").version,
+            "v1"
+        );
+    }
+}
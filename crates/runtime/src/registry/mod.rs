@@ -0,0 +1,70 @@
+//! Where bundles come from: versioned builds, one of which is active per route.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use async_trait::async_trait;
+use common::AppError;
+use serde::{Deserialize, Serialize};
+
+mod filesystem;
+
+pub use filesystem::FsBundleRegistry;
+
+/// Bundle id used by routes that do not have a bundle of their own.
+pub const DEFAULT_BUNDLE: &str = "default";
+
+/// One immutable build of a bundle.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BundleVersion {
+    /// Name of the build, unique within its bundle.
+    pub version: String,
+    pub bundle_path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_map_path: Option<PathBuf>,
+    /// Hex-encoded SHA-256 of the bundle file.
+    pub content_hash: String,
+    pub metadata: BuildMetadata,
+}
+
+/// Details the build recorded about itself; every field is optional.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuildMetadata {
+    /// Bundle file within the version, when it is not the registry's default name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+    /// When the bundle was built, as the build wrote it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub built_at: Option<String>,
+    /// Source revision the bundle was built from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Anything else the build recorded.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+/// Resolves routes to versioned bundles and switches which version is active.
+///
+/// Switching versions only moves the registry's pointer. Callers look a version up with
+/// [`version`](Self::version) or [`previous`](Self::previous) and load it into a runtime first,
+/// so a bundle that fails to load is never activated.
+#[async_trait]
+pub trait BundleRegistry: Send + Sync {
+    /// The active version of the bundle serving `route_id`.
+    async fn resolve(&self, route_id: &str) -> Result<BundleVersion, AppError>;
+
+    /// Every available version of the bundle serving `route_id`, sorted by version name.
+    async fn versions(&self, route_id: &str) -> Result<Vec<BundleVersion>, AppError>;
+
+    /// A specific version of the bundle serving `route_id`, active or not.
+    async fn version(&self, route_id: &str, version: &str) -> Result<BundleVersion, AppError>;
+
+    /// The version [`rollback`](Self::rollback) would activate, if there is one.
+    async fn previous(&self, route_id: &str) -> Result<Option<BundleVersion>, AppError>;
+
+    /// Makes `version` the active version for `route_id`.
+    async fn activate(&self, route_id: &str, version: &str) -> Result<BundleVersion, AppError>;
+
+    /// Re-activates the version that was active before the current one.
+    async fn rollback(&self, route_id: &str) -> Result<BundleVersion, AppError>;
+}
@@ -0,0 +1,288 @@
+//! Bundle snapshots: the bundle compiled to QuickJS bytecode ahead of time, so engines boot
+//! without parsing and compiling the source.
+//!
+//! QuickJS cannot serialise a heap, so each engine still runs the bundle's top-level code; the
+//! snapshot removes the parse and compile step, which dominates boot time for large bundles.
+//! A snapshot is tied to the bundle it was built from by name and content hash, and to the
+//! QuickJS version that wrote it. QuickJS does not validate bytecode as it loads it, so the
+//! bytecode is hashed as well and checked before an engine sees it. A stale or damaged snapshot
+//! is ignored with a warning and the engines compile the source instead.
+
+use std::{
+    ffi::{CStr, OsString},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use common::{AppError, ErrorCode};
+use rquickjs::{qjs, CatchResultExt, Context, Module, Runtime};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::{
+    engine::{Bundle, Engine},
+    load_source, RuntimeConfig,
+};
+
+/// Identifies a snapshot file and the layout of its header.
+const MAGIC: &[u8; 8] = b"RSESNAP2";
+/// Bytecode is written in the byte order of the machine that built it.
+const NATIVE_ENDIAN: u8 = if cfg!(target_endian = "little") { 1 } else { 2 };
+const HASH_LEN: usize = 32;
+
+/// What [`write_snapshot`] produced.
+#[derive(Debug, Clone)]
+pub struct SnapshotInfo {
+    pub path: PathBuf,
+    /// SHA-256 of the bundle source, hex encoded.
+    pub content_hash: String,
+    /// Version of the QuickJS engine the bytecode was compiled by, and must be loaded by.
+    pub engine: String,
+    /// Size of the snapshot file.
+    pub bytes: usize,
+}
+
+/// Compiles the bundle named by `config` to bytecode, checks that an engine boots from it and
+/// exports `stream`, and writes it to `output`.
+///
+/// The snapshot is only used for the bundle at the same path with the same contents, so build
+/// it from the path the server will load the bundle from.
+pub fn write_snapshot(config: &RuntimeConfig, output: &Path) -> Result<SnapshotInfo, AppError> {
+    let config = RuntimeConfig {
+        snapshot_path: None,
+        ..config.clone()
+    };
+    let mut bundle = load_source(&config)?;
+    let bytecode = compile(&bundle.name, &bundle.source)?;
+    bundle.bytecode = Some(bytecode.clone());
+    Engine::new(&bundle, config.engine_limits())?;
+
+    let engine = engine_version();
+    let content_hash = Sha256::digest(bundle.source.as_bytes());
+    let mut contents =
+        Vec::with_capacity(MAGIC.len() + engine.len() + bundle.name.len() + bytecode.len() + 80);
+    contents.extend_from_slice(MAGIC);
+    contents.push(NATIVE_ENDIAN);
+    contents.extend_from_slice(&(engine.len() as u32).to_le_bytes());
+    contents.extend_from_slice(engine.as_bytes());
+    contents.extend_from_slice(&(bundle.name.len() as u32).to_le_bytes());
+    contents.extend_from_slice(bundle.name.as_bytes());
+    contents.extend_from_slice(&content_hash);
+    contents.extend_from_slice(&Sha256::digest(&bytecode));
+    contents.extend_from_slice(&bytecode);
+
+    let mut staged = OsString::from(output.as_os_str());
+    staged.push(".tmp");
+    let staged = PathBuf::from(staged);
+    fs::write(&staged, &contents)
+        .and_then(|()| fs::rename(&staged, output))
+        .map_err(|err| {
+            AppError::new(
+                ErrorCode::Internal,
+                format!("failed to write snapshot '{}'", output.display()),
+            )
+            .with_source(err)
+        })?;
+
+    Ok(SnapshotInfo {
+        path: output.to_path_buf(),
+        content_hash: hex::encode(content_hash),
+        engine,
+        bytes: contents.len(),
+    })
+}
+
+/// Reads the bytecode in the snapshot at `path` if it was built from `bundle` by this version
+/// of QuickJS and is intact, so it is safe to hand to `Module::load`.
+pub(crate) fn read(path: &Path, bundle: &Bundle) -> Result<Option<Vec<u8>>, AppError> {
+    let contents = fs::read(path).map_err(|err| {
+        AppError::new(
+            ErrorCode::BadRequest,
+            format!("snapshot '{}' could not be read", path.display()),
+        )
+        .with_source(err)
+    })?;
+    let header = parse_header(&contents).ok_or_else(|| {
+        AppError::new(
+            ErrorCode::BadRequest,
+            format!("'{}' is not a bundle snapshot", path.display()),
+        )
+    })?;
+
+    let stale = if header.endian != NATIVE_ENDIAN {
+        Some("it was built on a machine with a different byte order")
+    } else if header.engine != engine_version().as_bytes() {
+        Some("it was built by a different version of QuickJS")
+    } else if header.bytecode_hash != Sha256::digest(header.bytecode).as_slice() {
+        Some("its bytecode is truncated or corrupted")
+    } else if header.name != bundle.name.as_bytes() {
+        Some("it was built from a bundle at another path")
+    } else if header.content_hash != Sha256::digest(bundle.source.as_bytes()).as_slice() {
+        Some("the bundle has changed since it was built")
+    } else {
+        None
+    };
+    match stale {
+        Some(reason) => {
+            warn!(
+                snapshot = %path.display(),
+                bundle = %bundle.name,
+                reason,
+                "ignoring unusable bundle snapshot; engines compile the bundle source instead"
+            );
+            Ok(None)
+        }
+        None => Ok(Some(header.bytecode.to_vec())),
+    }
+}
+
+struct Header<'a> {
+    endian: u8,
+    engine: &'a [u8],
+    name: &'a [u8],
+    content_hash: &'a [u8],
+    bytecode_hash: &'a [u8],
+    bytecode: &'a [u8],
+}
+
+fn parse_header(contents: &[u8]) -> Option<Header<'_>> {
+    let rest = contents.strip_prefix(MAGIC.as_slice())?;
+    let (&endian, rest) = rest.split_first()?;
+    let (engine, rest) = split_prefixed(rest)?;
+    let (name, rest) = split_prefixed(rest)?;
+    let (content_hash, rest) = split(rest, HASH_LEN)?;
+    let (bytecode_hash, bytecode) = split(rest, HASH_LEN)?;
+    Some(Header {
+        endian,
+        engine,
+        name,
+        content_hash,
+        bytecode_hash,
+        bytecode,
+    })
+}
+
+fn split(bytes: &[u8], at: usize) -> Option<(&[u8], &[u8])> {
+    (bytes.len() >= at).then(|| bytes.split_at(at))
+}
+
+/// Splits off a field written as its little-endian `u32` length followed by its bytes.
+fn split_prefixed(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = split(bytes, 4)?;
+    split(rest, u32::from_le_bytes(len.try_into().ok()?) as usize)
+}
+
+/// The version of the QuickJS engine linked into this build; bytecode is only readable by the
+/// version that wrote it.
+fn engine_version() -> String {
+    // SAFETY: `JS_GetVersion` returns a pointer to a static, NUL-terminated string.
+    let version = unsafe { CStr::from_ptr(qjs::JS_GetVersion()) };
+    format!("QuickJS {}", version.to_string_lossy())
+}
+
+/// Compiles `source` as the ES module `name` without evaluating it.
+fn compile(name: &str, source: &str) -> Result<Vec<u8>, AppError> {
+    let runtime = Runtime::new().map_err(|err| {
+        AppError::new(ErrorCode::Internal, "failed to create JavaScript runtime").with_source(err)
+    })?;
+    let context = Context::full(&runtime).map_err(|err| {
+        AppError::new(ErrorCode::Internal, "failed to create JavaScript context").with_source(err)
+    })?;
+    context.with(|ctx| {
+        Module::declare(ctx.clone(), name, source)
+            .and_then(|module| module.write(false))
+            .catch(&ctx)
+            .map_err(|err| {
+                AppError::new(
+                    ErrorCode::BadRequest,
+                    format!("bundle '{name}' failed to compile: {err}"),
+                )
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::{NamedTempFile, TempDir};
+
+    use super::*;
+
+    fn bundle(source: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().expect("bundle");
+        file.write_all(source.as_bytes()).expect("write bundle");
+        file
+    }
+
+    #[test]
+    fn engines_boot_from_a_snapshot_of_the_same_bundle() {
+        let source = bundle("export function stream(context) { context.write('ok'); }");
+        let dir = TempDir::new().expect("snapshot dir");
+        let output = dir.path().join("bundle.snapshot");
+        let config = RuntimeConfig::new(source.path());
+
+        let info = write_snapshot(&config, &output).expect("snapshot");
+        assert_eq!(info.bytes, fs::read(&output).unwrap().len());
+
+        let loaded = load_source(&config).expect("bundle");
+        let bytecode = read(&output, &loaded)
+            .expect("read")
+            .expect("fresh snapshot");
+        assert!(!bytecode.is_empty());
+
+        fs::write(
+            source.path(),
+            "export function stream(context) { context.write('changed'); }",
+        )
+        .unwrap();
+        let changed = load_source(&config).expect("bundle");
+        assert!(read(&output, &changed).expect("read").is_none());
+    }
+
+    #[test]
+    fn bundles_without_a_stream_export_are_not_snapshotted() {
+        let source = bundle("export const value = 1;");
+        let dir = TempDir::new().expect("snapshot dir");
+        let output = dir.path().join("bundle.snapshot");
+
+        let err = write_snapshot(&RuntimeConfig::new(source.path()), &output)
+            .expect_err("missing export");
+        assert!(err.message().contains("`stream` export"), "{err}");
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn damaged_snapshots_are_not_loaded() {
+        let source = bundle("export function stream(context) { context.write('ok'); }");
+        let dir = TempDir::new().expect("snapshot dir");
+        let output = dir.path().join("bundle.snapshot");
+        let config = RuntimeConfig::new(source.path());
+        write_snapshot(&config, &output).expect("snapshot");
+        let loaded = load_source(&config).expect("bundle");
+        let written = fs::read(&output).unwrap();
+
+        fs::write(&output, &written[..written.len() - 1]).unwrap();
+        assert!(read(&output, &loaded).expect("read").is_none());
+
+        let mut corrupted = written.clone();
+        *corrupted.last_mut().unwrap() ^= 0xff;
+        fs::write(&output, &corrupted).unwrap();
+        assert!(read(&output, &loaded).expect("read").is_none());
+
+        // The last byte of the engine version, after the magic, byte order and length.
+        let mut other_engine = written;
+        other_engine[MAGIC.len() + 4 + engine_version().len()] ^= 1;
+        fs::write(&output, &other_engine).unwrap();
+        assert!(read(&output, &loaded).expect("read").is_none());
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let source = bundle("export function stream() {}");
+        let config = RuntimeConfig::new(source.path());
+        let loaded = load_source(&config).expect("bundle");
+        let err = read(source.path(), &loaded).expect_err("not a snapshot");
+        assert!(err.message().contains("is not a bundle snapshot"), "{err}");
+    }
+}
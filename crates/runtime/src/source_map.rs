@@ -0,0 +1,204 @@
+//! Maps bundle positions in JavaScript stack traces back to the sources the bundle was built
+//! from.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use common::{AppError, ErrorCode};
+use sourcemap::DecodedMap;
+use tracing::warn;
+
+/// Comment bundlers append to point at the bundle's source map.
+const SOURCE_MAPPING_URL: &str = "//# sourceMappingURL=";
+
+/// A bundle's source map, used to rewrite `bundle:line:column` locations in stack traces.
+pub(crate) struct SourceMapper {
+    /// The name stack traces use for the bundle, which is its module name.
+    bundle: String,
+    map: DecodedMap,
+}
+
+impl std::fmt::Debug for SourceMapper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SourceMapper")
+            .field("bundle", &self.bundle)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SourceMapper {
+    /// Loads the map at `explicit`, failing when it cannot be read. Otherwise falls back to the
+    /// bundle's `sourceMappingURL` comment (a file next to the bundle or an inline `data:` URL)
+    /// and then to `<bundle>.map`; a map found this way that cannot be used only logs a warning.
+    pub(crate) fn load(
+        bundle_path: &Path,
+        bundle_name: &str,
+        source: &str,
+        explicit: Option<&Path>,
+    ) -> Result<Option<Self>, AppError> {
+        let mapper = |map| Self {
+            bundle: bundle_name.to_string(),
+            map,
+        };
+
+        if let Some(path) = explicit {
+            return read_map(path).map(|map| Some(mapper(map)));
+        }
+
+        let decoded = match source_mapping_url(source) {
+            Some(url) if url.starts_with("data:") => Some(
+                sourcemap::decode_data_url(url)
+                    .map_err(|err| format!("inline source map is invalid: {err}")),
+            ),
+            Some(url) => {
+                let path = bundle_path
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join(url);
+                path.is_file()
+                    .then(|| read_map(&path).map_err(|err| err.message().to_string()))
+            }
+            None => {
+                let mut path = bundle_path.as_os_str().to_owned();
+                path.push(".map");
+                let path = PathBuf::from(path);
+                path.is_file()
+                    .then(|| read_map(&path).map_err(|err| err.message().to_string()))
+            }
+        };
+
+        match decoded {
+            Some(Ok(map)) => Ok(Some(mapper(map))),
+            Some(Err(message)) => {
+                warn!(bundle = bundle_name, error = %message, "ignoring the bundle's source map");
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Rewrites every `bundle:line:column` location in `text` that the map covers to the
+    /// original `source:line:column`, leaving the rest of the text untouched.
+    pub(crate) fn remap(&self, text: &str) -> String {
+        let mut remapped = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find(self.bundle.as_str()) {
+            let after = &rest[start + self.bundle.len()..];
+            remapped.push_str(&rest[..start]);
+            match self.original_location(after) {
+                Some((location, consumed)) => {
+                    remapped.push_str(&location);
+                    rest = &after[consumed..];
+                }
+                None => {
+                    remapped.push_str(&self.bundle);
+                    rest = after;
+                }
+            }
+        }
+        remapped.push_str(rest);
+        remapped
+    }
+
+    /// Resolves a `:line:column` suffix (1-based, as QuickJS prints them), returning the
+    /// original location and how many bytes of `suffix` it replaces.
+    fn original_location(&self, suffix: &str) -> Option<(String, usize)> {
+        let (line, line_len) = leading_number(suffix.strip_prefix(':')?)?;
+        let (column, column_len) = leading_number(suffix[1 + line_len..].strip_prefix(':')?)?;
+        let consumed = 1 + line_len + 1 + column_len;
+
+        let line = line.checked_sub(1)?;
+        let token = self.map.lookup_token(line, column.saturating_sub(1))?;
+        // The closest token may sit on an earlier line, which says nothing about this one.
+        if token.get_dst_line() != line {
+            return None;
+        }
+        let source = token.get_source()?;
+        let location = format!(
+            "{source}:{}:{}",
+            token.get_src_line() + 1,
+            token.get_src_col() + 1
+        );
+        Some((location, consumed))
+    }
+}
+
+fn read_map(path: &Path) -> Result<DecodedMap, AppError> {
+    let contents = fs::read(path).map_err(|err| {
+        AppError::new(
+            ErrorCode::BadRequest,
+            format!("source map '{}' could not be read", path.display()),
+        )
+        .with_source(err)
+    })?;
+    sourcemap::decode_slice(&contents).map_err(|err| {
+        AppError::new(
+            ErrorCode::BadRequest,
+            format!("source map '{}' is invalid: {err}", path.display()),
+        )
+    })
+}
+
+/// The URL in the bundle's last `sourceMappingURL` comment.
+fn source_mapping_url(source: &str) -> Option<&str> {
+    source
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .and_then(|line| line.strip_prefix(SOURCE_MAPPING_URL))
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+}
+
+/// Parses the decimal digits `text` starts with, returning the value and its length.
+fn leading_number(text: &str) -> Option<(u32, usize)> {
+    let len = text.bytes().take_while(u8::is_ascii_digit).count();
+    text[..len].parse().ok().map(|value| (value, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use sourcemap::SourceMapBuilder;
+
+    use super::*;
+
+    /// Maps bundle line 3 column 5 (0-based: 2, 4) to `src/app.ts` line 10 column 7.
+    fn mapper() -> SourceMapper {
+        let mut builder = SourceMapBuilder::new(None);
+        builder.add(2, 4, 9, 6, Some("src/app.ts"), None, false);
+        let mut json = Vec::new();
+        builder
+            .into_sourcemap()
+            .to_writer(&mut json)
+            .expect("encode map");
+        SourceMapper {
+            bundle: "/srv/bundle.js".to_string(),
+            map: sourcemap::decode_slice(&json).expect("decode map"),
+        }
+    }
+
+    #[test]
+    fn mapped_locations_are_rewritten() {
+        let stack = "Error: boom\n    at render (/srv/bundle.js:3:9)\n    at /srv/bundle.js:3:5\n";
+        assert_eq!(
+            mapper().remap(stack),
+            "Error: boom\n    at render (src/app.ts:10:7)\n    at src/app.ts:10:7\n"
+        );
+    }
+
+    #[test]
+    fn unmapped_locations_are_kept() {
+        let stack = "at a (/srv/bundle.js:7:1) at b (/srv/bundle.js) at c (/srv/other.js:3:5)";
+        assert_eq!(mapper().remap(stack), stack);
+    }
+
+    #[test]
+    fn the_last_source_mapping_url_comment_wins() {
+        let source = "//# sourceMappingURL=old.map\ncode();\n//# sourceMappingURL=bundle.js.map\n";
+        assert_eq!(source_mapping_url(source), Some("bundle.js.map"));
+        assert_eq!(source_mapping_url("code();\n"), None);
+    }
+}
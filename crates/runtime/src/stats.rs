@@ -0,0 +1,81 @@
+use std::time::{Duration, Instant};
+
+use common::{AppError, RequestContext};
+use metrics::histogram;
+
+/// Per-render counters reported once the render finishes.
+pub(crate) struct RenderStats {
+    started: Instant,
+    first_chunk: Option<Duration>,
+    chunks: u64,
+    bytes: u64,
+}
+
+impl RenderStats {
+    /// Starts the clock; time to first chunk and render time are measured from here.
+    pub(crate) fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            first_chunk: None,
+            chunks: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Counts a chunk produced by the bundle.
+    pub(crate) fn record_chunk(&mut self, len: usize) {
+        self.first_chunk
+            .get_or_insert_with(|| self.started.elapsed());
+        self.chunks += 1;
+        self.bytes += len as u64;
+    }
+
+    /// Emits the render metrics, labelled by route id and runtime name.
+    pub(crate) fn finish(
+        self,
+        context: &RequestContext,
+        runtime: &str,
+        result: &Result<(), AppError>,
+    ) {
+        let route = route_label(context);
+        let outcome = match result {
+            Ok(()) => "ok",
+            Err(err) => err.code().as_str(),
+        };
+        histogram!(
+            "render_duration_seconds",
+            self.started.elapsed().as_secs_f64(),
+            "route" => route.clone(),
+            "runtime" => runtime.to_string(),
+            "outcome" => outcome,
+        );
+        if let Some(first_chunk) = self.first_chunk {
+            histogram!(
+                "render_time_to_first_chunk_seconds",
+                first_chunk.as_secs_f64(),
+                "route" => route.clone(),
+                "runtime" => runtime.to_string(),
+            );
+        }
+        histogram!(
+            "render_chunks",
+            self.chunks as f64,
+            "route" => route.clone(),
+            "runtime" => runtime.to_string(),
+        );
+        histogram!(
+            "render_bytes",
+            self.bytes as f64,
+            "route" => route,
+            "runtime" => runtime.to_string(),
+        );
+    }
+}
+
+/// Route id used to label render metrics; renders outside the route table are `unrouted`.
+fn route_label(context: &RequestContext) -> String {
+    context
+        .route
+        .as_ref()
+        .map_or_else(|| "unrouted".to_string(), |route| route.id.clone())
+}
@@ -0,0 +1,114 @@
+//! One log line per request, written once the response body has been sent.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use axum::{body::Body, extract::Request, middleware::Next, response::Response};
+use bytes::Bytes;
+use http::{Method, StatusCode};
+use http_body::{Body as _, Frame, SizeHint};
+use tracing::{info, Span};
+
+/// Target of access log events, so they can be filtered separately, such as `access_log=off`.
+pub const TARGET: &str = "access_log";
+
+/// Logs the method, path, status, body size and duration of each request. Streamed responses
+/// are logged when their last chunk is sent or the client goes away, not when headers are sent.
+pub async fn access_log(request: Request, next: Next) -> Response {
+    let entry = Entry {
+        method: request.method().clone(),
+        path: request.uri().path().to_string(),
+        started: Instant::now(),
+        // The request span, so the line carries its request and trace ids.
+        span: Span::current(),
+    };
+    let response = next.run(request).await;
+    let status = response.status();
+    response.map(|body| {
+        Body::new(LoggedBody {
+            inner: body,
+            entry: Some(entry),
+            status,
+            bytes: 0,
+        })
+    })
+}
+
+struct Entry {
+    method: Method,
+    path: String,
+    started: Instant,
+    span: Span,
+}
+
+/// Counts the bytes of a response body and writes the access log line when it ends.
+struct LoggedBody {
+    inner: Body,
+    entry: Option<Entry>,
+    status: StatusCode,
+    bytes: u64,
+}
+
+impl LoggedBody {
+    fn log(&mut self, completed: bool) {
+        let Some(entry) = self.entry.take() else {
+            return;
+        };
+        let _enter = entry.span.enter();
+        info!(
+            target: TARGET,
+            method = %entry.method,
+            path = %entry.path,
+            status = self.status.as_u16(),
+            bytes = self.bytes,
+            duration_ms = entry.started.elapsed().as_secs_f64() * 1000.0,
+            completed,
+            "request finished"
+        );
+    }
+}
+
+impl http_body::Body for LoggedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_frame(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    self.bytes += data.len() as u64;
+                }
+                if self.inner.is_end_stream() {
+                    self.log(true);
+                }
+            }
+            Poll::Ready(Some(Err(_))) => self.log(false),
+            Poll::Ready(None) => self.log(true),
+            Poll::Pending => {}
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        // Dropped before the end: the client disconnected or the response was discarded.
+        let completed = self.inner.is_end_stream();
+        self.log(completed);
+    }
+}
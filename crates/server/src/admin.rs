@@ -0,0 +1,225 @@
+//! Management API: bundle versions, cache purging, pool statistics and the log filter.
+//!
+//! Every endpoint requires `Authorization: Bearer <token>`. The router is merged under `/admin`
+//! on the main listener or served on its own address, like the metrics router.
+
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::header,
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use common::{AppError, ErrorCode};
+use runtime::{registry::DEFAULT_BUNDLE, BundleVersion, PoolStats};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    app::{unknown_bundle, AppState},
+    cache,
+    errors::{HandlerResult, HttpError},
+    reload, telemetry,
+};
+
+/// Serves the admin endpoints under `/admin`, rejecting requests that do not present `token`.
+pub fn admin_router(state: AppState, token: impl Into<String>) -> Router {
+    let token: Arc<str> = Arc::from(token.into());
+    Router::new()
+        .route("/admin/bundles", get(list_bundles))
+        .route("/admin/bundles/rollback", post(rollback_bundle))
+        .route("/admin/bundles/:version/activate", post(activate_bundle))
+        .route("/admin/cache/purge", post(purge_cache))
+        .route("/admin/runtime", get(runtime_stats))
+        .route("/admin/log-filter", get(get_log_filter).put(put_log_filter))
+        .route_layer(middleware::from_fn_with_state(token, authorize))
+        .layer(Extension(state))
+}
+
+async fn authorize(
+    State(token): State<Arc<str>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, HttpError> {
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(presented) if constant_time_eq(presented.as_bytes(), token.as_bytes()) => {
+            Ok(next.run(request).await)
+        }
+        _ => {
+            warn!(
+                method = %request.method(),
+                path = %request.uri().path(),
+                "rejected admin request without a valid token"
+            );
+            Err(AppError::new(ErrorCode::Unauthorized, "a valid admin token is required").into())
+        }
+    }
+}
+
+/// Compares without stopping at the first difference, so response timing does not reveal how
+/// much of the token matched.
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len()
+        && left
+            .iter()
+            .zip(right)
+            .fold(0, |diff, (left, right)| diff | (left ^ right))
+            == 0
+}
+
+/// Selects the bundle a versions endpoint acts on; the default bundle when unset.
+#[derive(Deserialize)]
+struct BundleQuery {
+    bundle: Option<String>,
+}
+
+impl BundleQuery {
+    fn bundle(&self) -> &str {
+        self.bundle.as_deref().unwrap_or(DEFAULT_BUNDLE)
+    }
+}
+
+#[derive(Serialize)]
+struct BundleList {
+    active: BundleVersion,
+    versions: Vec<BundleVersion>,
+}
+
+async fn list_bundles(
+    Extension(state): Extension<AppState>,
+    Query(query): Query<BundleQuery>,
+) -> HandlerResult<Json<BundleList>> {
+    let bundle = query.bundle();
+    if state.bundle_runtime(bundle).is_none() {
+        return Err(unknown_bundle(bundle).into());
+    }
+    let registry = reload::require_registry(&state)?;
+    Ok(Json(BundleList {
+        active: registry.resolve(bundle).await?,
+        versions: registry.versions(bundle).await?,
+    }))
+}
+
+async fn activate_bundle(
+    Extension(state): Extension<AppState>,
+    Path(version): Path<String>,
+    Query(query): Query<BundleQuery>,
+) -> HandlerResult<Json<BundleVersion>> {
+    Ok(Json(
+        reload::activate_version(&state, query.bundle(), &version).await?,
+    ))
+}
+
+async fn rollback_bundle(
+    Extension(state): Extension<AppState>,
+    Query(query): Query<BundleQuery>,
+) -> HandlerResult<Json<BundleVersion>> {
+    Ok(Json(
+        reload::rollback_version(&state, query.bundle()).await?,
+    ))
+}
+
+/// Selects cache entries by raw key prefix or by route id; exactly one must be given.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PurgeRequest {
+    prefix: Option<String>,
+    route: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PurgeResponse {
+    prefix: String,
+    purged: u64,
+}
+
+async fn purge_cache(
+    Extension(state): Extension<AppState>,
+    Json(request): Json<PurgeRequest>,
+) -> HandlerResult<Json<PurgeResponse>> {
+    let prefix = match (request.prefix, request.route) {
+        (Some(prefix), None) => prefix,
+        (None, Some(route)) => {
+            if !state.routes().routes().any(|existing| existing.id == route) {
+                return Err(
+                    AppError::new(ErrorCode::NotFound, format!("unknown route '{route}'")).into(),
+                );
+            }
+            cache::route_key_prefix(&route)
+        }
+        _ => {
+            return Err(AppError::new(
+                ErrorCode::BadRequest,
+                "set exactly one of 'prefix' or 'route'",
+            )
+            .into())
+        }
+    };
+
+    let purged = state.cache().purge_prefix(&prefix).await?;
+    info!(prefix = %prefix, purged, "purged render cache entries");
+    Ok(Json(PurgeResponse { prefix, purged }))
+}
+
+#[derive(Serialize)]
+struct RuntimeStats {
+    bundles: BTreeMap<String, BundleStats>,
+}
+
+#[derive(Serialize)]
+struct BundleStats {
+    runtime: String,
+    bundle: PathBuf,
+    warm: bool,
+    engine_pool: PoolStats,
+}
+
+async fn runtime_stats(Extension(state): Extension<AppState>) -> Json<RuntimeStats> {
+    let bundles = state
+        .bundles()
+        .into_iter()
+        .map(|(bundle, runtime)| {
+            let stats = BundleStats {
+                runtime: runtime.name().to_string(),
+                bundle: runtime.bundle_path().to_path_buf(),
+                warm: runtime.is_warm(),
+                engine_pool: runtime.pool_stats(),
+            };
+            (bundle, stats)
+        })
+        .collect();
+    Json(RuntimeStats { bundles })
+}
+
+#[derive(Serialize, Deserialize)]
+struct LogFilter {
+    filter: String,
+}
+
+async fn get_log_filter() -> HandlerResult<Json<LogFilter>> {
+    let filter = telemetry::log_filter().ok_or_else(log_filter_unavailable)?;
+    Ok(Json(LogFilter { filter }))
+}
+
+async fn put_log_filter(Json(request): Json<LogFilter>) -> HandlerResult<Json<LogFilter>> {
+    telemetry::log_filter().ok_or_else(log_filter_unavailable)?;
+    telemetry::set_log_filter(&request.filter)
+        .map_err(|err| AppError::new(ErrorCode::BadRequest, format!("{err:#}")))?;
+    info!(filter = %request.filter, "log filter replaced");
+    Ok(Json(request))
+}
+
+fn log_filter_unavailable() -> AppError {
+    AppError::new(
+        ErrorCode::NotFound,
+        "the log filter cannot be changed because tracing was not initialised by the server",
+    )
+}
@@ -1,8 +1,12 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
-use axum::{http::Request, response::Response, routing::get, Router};
+use arc_swap::ArcSwap;
+use axum::{http::Request, middleware, response::Response, routing::get, Router};
+use common::{AppError, DataProvider, ErrorCode, RenderMode, RouteConfig, RouteTable};
 use metrics::{histogram, increment_counter};
-use runtime::RenderRuntime;
+use metrics_exporter_prometheus::PrometheusHandle;
+use runtime::{registry::DEFAULT_BUNDLE, BundleRegistry, RenderRuntime};
+use tokio::sync::{Mutex, OwnedMutexGuard};
 use tower::ServiceBuilder;
 use tower_http::{
     classify::ServerErrorsFailureClass,
@@ -11,10 +15,45 @@ use tower_http::{
 };
 use tracing::{info_span, Span};
 
-use crate::handlers;
+use crate::{
+    access_log, admin,
+    cache::{MemoryCache, RenderCache},
+    coalesce::RenderFlights,
+    data::DataProviders,
+    handlers,
+    health::{self, HealthState},
+    telemetry,
+};
 
 #[derive(Clone)]
-pub struct AppState(Arc<RenderRuntime>);
+pub struct AppState {
+    /// One runtime per bundle id, each swapped independently on reload.
+    runtimes: Arc<BTreeMap<String, Arc<BundleSlot>>>,
+    routes: Arc<RouteTable>,
+    cache: Arc<dyn RenderCache>,
+    data: Arc<DataProviders>,
+    flights: Arc<RenderFlights>,
+    health: Arc<HealthState>,
+    metrics: Option<PrometheusHandle>,
+    registry: Option<Arc<dyn BundleRegistry>>,
+    admin_token: Option<Arc<str>>,
+    expose_render_errors: bool,
+}
+
+/// The runtime serving a bundle, and the lock that serialises switching it.
+struct BundleSlot {
+    runtime: ArcSwap<RenderRuntime>,
+    switching: Arc<Mutex<()>>,
+}
+
+impl BundleSlot {
+    fn new(runtime: RenderRuntime) -> Arc<Self> {
+        Arc::new(Self {
+            runtime: ArcSwap::from_pointee(runtime),
+            switching: Arc::new(Mutex::new(())),
+        })
+    }
+}
 
 #[allow(dead_code)]
 fn _assert_app_state_send_sync() {
@@ -31,15 +70,207 @@ fn _assert_router_service(router: Router) {
 }
 
 impl AppState {
+    /// Serves the runtime as the default bundle on the default streaming `/stream` route.
     pub fn new(runtime: RenderRuntime) -> Self {
-        Self(Arc::new(runtime))
+        let route = RouteConfig::new("stream", "/stream").with_render_mode(RenderMode::Streaming);
+        let runtimes = BTreeMap::from([(DEFAULT_BUNDLE.to_string(), BundleSlot::new(runtime))]);
+        Self {
+            runtimes: Arc::new(runtimes),
+            routes: Arc::new(RouteTable::new(vec![route]).expect("default route is valid")),
+            cache: Arc::new(MemoryCache::default()),
+            data: Arc::new(DataProviders::default()),
+            flights: Arc::new(RenderFlights::default()),
+            health: Arc::new(HealthState::default()),
+            metrics: None,
+            registry: None,
+            admin_token: None,
+            expose_render_errors: false,
+        }
+    }
+
+    /// Registers `runtime` as the bundle `bundle`, rendering the routes that name it.
+    pub fn with_bundle(mut self, bundle: impl Into<String>, runtime: RenderRuntime) -> Self {
+        Arc::make_mut(&mut self.runtimes).insert(bundle.into(), BundleSlot::new(runtime));
+        self
+    }
+
+    /// Replaces the routes served by the runtime.
+    pub fn with_routes(mut self, routes: Vec<RouteConfig>) -> Result<Self, AppError> {
+        self.routes = Arc::new(RouteTable::new(routes)?);
+        Ok(self)
+    }
+
+    /// Replaces the cache used for routes with a `cache_ttl_seconds`.
+    pub fn with_cache(mut self, cache: impl RenderCache + 'static) -> Self {
+        self.cache = Arc::new(cache);
+        self
+    }
+
+    /// Makes `provider` available to routes that declare data from `name`.
+    pub fn with_data_provider(
+        mut self,
+        name: impl Into<String>,
+        provider: impl DataProvider + 'static,
+    ) -> Self {
+        Arc::make_mut(&mut self.data).register(name, Arc::new(provider));
+        self
+    }
+
+    /// Serves `handle` at `/metrics` on the main router.
+    pub fn with_metrics(mut self, handle: PrometheusHandle) -> Self {
+        self.metrics = Some(handle);
+        self
+    }
+
+    /// Serves the admin API under `/admin` on the main router, for requests presenting `token`.
+    pub fn with_admin(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = Some(Arc::from(token.into()));
+        self
+    }
+
+    /// Shows clients the full error of a failed render or data fetch, remapped stack included,
+    /// instead of only the request id to look it up in the logs by. Meant for development.
+    pub fn with_exposed_render_errors(mut self) -> Self {
+        self.expose_render_errors = true;
+        self
     }
 
+    /// Switches bundle versions through `registry`; the runtime should serve its active version.
+    pub fn with_registry(mut self, registry: impl BundleRegistry + 'static) -> Self {
+        self.registry = Some(Arc::new(registry));
+        self
+    }
+
+    /// The runtime currently serving the default bundle. Callers keep rendering with the
+    /// returned runtime even if it is replaced meanwhile.
     pub fn runtime(&self) -> Arc<RenderRuntime> {
-        Arc::clone(&self.0)
+        self.runtimes[DEFAULT_BUNDLE].runtime.load_full()
+    }
+
+    /// The runtime currently serving `bundle`, if it is registered.
+    pub fn bundle_runtime(&self, bundle: &str) -> Option<Arc<RenderRuntime>> {
+        self.runtimes
+            .get(bundle)
+            .map(|slot| slot.runtime.load_full())
+    }
+
+    /// The runtime rendering `route`.
+    pub fn route_runtime(&self, route: &RouteConfig) -> Result<Arc<RenderRuntime>, AppError> {
+        let bundle = route.bundle.as_deref().unwrap_or(DEFAULT_BUNDLE);
+        self.bundle_runtime(bundle).ok_or_else(|| {
+            AppError::new(
+                ErrorCode::Internal,
+                format!("route '{}' uses unknown bundle '{bundle}'", route.id),
+            )
+        })
+    }
+
+    /// Every registered bundle id with its current runtime, the default bundle included.
+    pub fn bundles(&self) -> Vec<(String, Arc<RenderRuntime>)> {
+        self.runtimes
+            .iter()
+            .map(|(bundle, slot)| (bundle.clone(), slot.runtime.load_full()))
+            .collect()
+    }
+
+    /// Serves subsequent renders of the default bundle with `runtime`; see
+    /// [`replace_bundle_runtime`](Self::replace_bundle_runtime).
+    pub fn replace_runtime(&self, runtime: RenderRuntime) -> Arc<RenderRuntime> {
+        self.runtimes[DEFAULT_BUNDLE]
+            .runtime
+            .swap(Arc::new(runtime))
+    }
+
+    /// Serves subsequent renders of `bundle` with `runtime`, returning the one it replaces.
+    /// Renders already in flight finish on the previous runtime, whose engines are freed once
+    /// they complete.
+    pub fn replace_bundle_runtime(
+        &self,
+        bundle: &str,
+        runtime: RenderRuntime,
+    ) -> Result<Arc<RenderRuntime>, AppError> {
+        let slot = self
+            .runtimes
+            .get(bundle)
+            .ok_or_else(|| unknown_bundle(bundle))?;
+        Ok(slot.runtime.swap(Arc::new(runtime)))
+    }
+
+    /// Waits until no other reload, activation or rollback of `bundle` is running, and keeps
+    /// the others waiting until the returned guard is dropped. Holding it from loading a
+    /// runtime until it is swapped in keeps the served runtime in step with the registry.
+    pub(crate) async fn lock_bundle(&self, bundle: &str) -> Result<OwnedMutexGuard<()>, AppError> {
+        let slot = self
+            .runtimes
+            .get(bundle)
+            .ok_or_else(|| unknown_bundle(bundle))?;
+        Ok(Arc::clone(&slot.switching).lock_owned().await)
+    }
+
+    /// Checks that every data source the routes declare names a registered provider, so a
+    /// misconfigured route is caught at startup rather than failing its requests.
+    pub fn check_data_providers(&self) -> Result<(), AppError> {
+        let missing: Vec<String> = self
+            .routes
+            .routes()
+            .flat_map(|route| {
+                route
+                    .data
+                    .iter()
+                    .filter(|source| !self.data.contains(&source.provider))
+                    .map(move |source| format!("route '{}': '{}'", route.id, source.provider))
+            })
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(AppError::new(
+            ErrorCode::BadRequest,
+            format!(
+                "routes use data providers that are not registered: {}",
+                missing.join(", ")
+            ),
+        ))
+    }
+
+    pub fn routes(&self) -> Arc<RouteTable> {
+        Arc::clone(&self.routes)
+    }
+
+    pub fn cache(&self) -> Arc<dyn RenderCache> {
+        Arc::clone(&self.cache)
+    }
+
+    /// The providers routes fetch data from before rendering.
+    pub fn data_providers(&self) -> Arc<DataProviders> {
+        Arc::clone(&self.data)
+    }
+
+    /// Whether clients see the details of failed renders.
+    pub fn exposes_render_errors(&self) -> bool {
+        self.expose_render_errors
+    }
+
+    /// Renders of cacheable routes that are currently in progress.
+    pub fn flights(&self) -> Arc<RenderFlights> {
+        Arc::clone(&self.flights)
+    }
+
+    /// The registry bundle versions are activated through, when one is configured.
+    pub fn registry(&self) -> Option<Arc<dyn BundleRegistry>> {
+        self.registry.clone()
+    }
+
+    /// Readiness conditions reported by `/readyz` beyond the runtime's own state.
+    pub fn health(&self) -> Arc<HealthState> {
+        Arc::clone(&self.health)
     }
 }
 
+pub(crate) fn unknown_bundle(bundle: &str) -> AppError {
+    AppError::new(ErrorCode::NotFound, format!("unknown bundle '{bundle}'"))
+}
+
 pub fn build_router(state: AppState) -> Router {
     let trace_layer = TraceLayer::new_for_http()
         .make_span_with(|request: &Request<_>| {
@@ -50,14 +281,19 @@ pub fn build_router(state: AppState) -> Router {
                 .unwrap_or("unknown")
                 .to_string();
 
-            info_span!(
+            let span = info_span!(
                 "http_request",
                 method = %request.method(),
                 uri = %request.uri(),
                 request_id = %request_id,
+                trace_id = tracing::field::Empty,
+                route = tracing::field::Empty,
+                runtime = tracing::field::Empty,
                 status = tracing::field::Empty,
                 latency_ms = tracing::field::Empty,
-            )
+            );
+            telemetry::set_remote_parent(&span, request.headers());
+            span
         })
         .on_request(|_request: &Request<_>, _span: &Span| {
             tracing::debug!("request started");
@@ -85,19 +321,33 @@ pub fn build_router(state: AppState) -> Router {
             tracing::warn!(status = %error_label, latency_ms = latency.as_secs_f64() * 1000.0, "request failed");
         });
 
+    // The request id is assigned before the request span is created so the span records it.
     let service_stack = ServiceBuilder::new()
-        .layer(trace_layer)
-        .layer(PropagateRequestIdLayer::new(
-            http::header::HeaderName::from_static("x-request-id"),
-        ))
         .layer(SetRequestIdLayer::new(
             http::header::HeaderName::from_static("x-request-id"),
             MakeRequestUuid,
         ))
+        .layer(PropagateRequestIdLayer::new(
+            http::header::HeaderName::from_static("x-request-id"),
+        ))
+        .layer(trace_layer)
+        .layer(middleware::from_fn(access_log::access_log))
         .into_inner();
 
-    Router::new()
-        .route("/stream", get(handlers::stream))
+    // Paths are resolved against the route table by the handler rather than by axum, so route
+    // patterns keep their own syntax and precedence rules. Only the probes and metrics are
+    // routed by axum.
+    let mut router = Router::new()
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz));
+    if let Some(handle) = state.metrics.clone() {
+        router = router.merge(telemetry::metrics_router(handle));
+    }
+    if let Some(token) = state.admin_token.clone() {
+        router = router.merge(admin::admin_router(state.clone(), token.as_ref()));
+    }
+    router
+        .fallback_service(get(handlers::render))
         .layer(service_stack)
         .layer(axum::Extension(state))
 }
@@ -0,0 +1,283 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use common::AppError;
+
+use super::{Lookup, RenderCache};
+
+struct Slot {
+    body: Bytes,
+    fresh_until: Instant,
+    expires_at: Instant,
+    /// Position in `CacheState::recency`; larger is more recently used.
+    tick: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    slots: HashMap<String, Slot>,
+    recency: BTreeMap<u64, String>,
+    next_tick: u64,
+    bytes: usize,
+}
+
+impl CacheState {
+    fn touch(&mut self, key: &str) {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        if let Some(slot) = self.slots.get_mut(key) {
+            self.recency.remove(&slot.tick);
+            slot.tick = tick;
+            self.recency.insert(tick, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(slot) = self.slots.remove(key) {
+            self.recency.remove(&slot.tick);
+            self.bytes -= slot.body.len();
+        }
+    }
+
+    fn evict_lru(&mut self) {
+        if let Some((_, key)) = self.recency.pop_first() {
+            if let Some(slot) = self.slots.remove(&key) {
+                self.bytes -= slot.body.len();
+            }
+        }
+    }
+}
+
+/// In-process cache of rendered documents with per-entry TTLs and a byte budget.
+///
+/// When an insert would exceed the budget, the least recently used entries are evicted first.
+pub struct MemoryCache {
+    max_bytes: usize,
+    state: Mutex<CacheState>,
+}
+
+impl MemoryCache {
+    /// Creates a cache holding at most `max_bytes` of rendered HTML; `0` disables caching.
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Number of entries currently stored, including expired ones not yet evicted.
+    pub fn len(&self) -> usize {
+        self.lock().slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.state.lock().expect("render cache mutex poisoned")
+    }
+}
+
+#[async_trait]
+impl RenderCache for MemoryCache {
+    fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    async fn get(&self, key: &str) -> Result<Lookup, AppError> {
+        let mut state = self.lock();
+        let Some(slot) = state.slots.get(key) else {
+            return Ok(Lookup::Miss);
+        };
+        let now = Instant::now();
+        if slot.expires_at <= now {
+            state.remove(key);
+            return Ok(Lookup::Miss);
+        }
+
+        let body = slot.body.clone();
+        let fresh = slot.fresh_until > now;
+        state.touch(key);
+        Ok(if fresh {
+            Lookup::Fresh(body)
+        } else {
+            Lookup::Stale(body)
+        })
+    }
+
+    async fn set(
+        &self,
+        key: &str,
+        body: Bytes,
+        ttl: Duration,
+        stale_for: Duration,
+    ) -> Result<(), AppError> {
+        if body.len() > self.max_bytes || ttl.is_zero() {
+            return Ok(());
+        }
+
+        let mut state = self.lock();
+        state.remove(key);
+        let key = key.to_string();
+        while state.bytes + body.len() > self.max_bytes {
+            state.evict_lru();
+        }
+
+        let tick = state.next_tick;
+        state.next_tick += 1;
+        state.bytes += body.len();
+        state.recency.insert(tick, key.clone());
+        let fresh_until = Instant::now() + ttl;
+        state.slots.insert(
+            key,
+            Slot {
+                body,
+                fresh_until,
+                expires_at: fresh_until + stale_for,
+                tick,
+            },
+        );
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        self.lock().remove(key);
+        Ok(())
+    }
+
+    async fn purge_prefix(&self, prefix: &str) -> Result<u64, AppError> {
+        let mut state = self.lock();
+        let keys: Vec<String> = state
+            .slots
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        for key in &keys {
+            state.remove(key);
+        }
+        Ok(keys.len() as u64)
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>, AppError> {
+        let state = self.lock();
+        let now = Instant::now();
+        Ok(state
+            .slots
+            .get(key)
+            .filter(|slot| slot.expires_at > now)
+            .map(|slot| slot.expires_at - now))
+    }
+}
+
+impl Default for MemoryCache {
+    fn default() -> Self {
+        Self::new(64 * 1024 * 1024)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+    const BRIEF: Duration = Duration::from_nanos(1);
+
+    async fn insert(cache: &MemoryCache, key: &str, body: &'static [u8]) {
+        cache
+            .set(key, Bytes::from_static(body), TTL, Duration::ZERO)
+            .await
+            .expect("set");
+    }
+
+    async fn get(cache: &MemoryCache, key: &str) -> Lookup {
+        cache.get(key).await.expect("get")
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_not_served() {
+        let cache = MemoryCache::new(1024);
+        insert(&cache, "a", b"fresh").await;
+        cache
+            .set("b", Bytes::from_static(b"gone"), BRIEF, Duration::ZERO)
+            .await
+            .expect("set");
+        std::thread::sleep(Duration::from_millis(1));
+
+        assert_eq!(
+            get(&cache, "a").await,
+            Lookup::Fresh(Bytes::from_static(b"fresh"))
+        );
+        assert_eq!(get(&cache, "b").await, Lookup::Miss);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn entries_past_their_ttl_are_stale_until_the_window_ends() {
+        let cache = MemoryCache::new(1024);
+        cache
+            .set("a", Bytes::from_static(b"old"), BRIEF, TTL)
+            .await
+            .expect("set");
+        cache
+            .set("b", Bytes::from_static(b"old"), BRIEF, BRIEF)
+            .await
+            .expect("set");
+        std::thread::sleep(Duration::from_millis(1));
+
+        assert_eq!(
+            get(&cache, "a").await,
+            Lookup::Stale(Bytes::from_static(b"old"))
+        );
+        assert_eq!(get(&cache, "b").await, Lookup::Miss);
+    }
+
+    #[tokio::test]
+    async fn deleted_entries_report_no_ttl() {
+        let cache = MemoryCache::new(1024);
+        insert(&cache, "a", b"doc").await;
+        let ttl = cache.ttl("a").await.expect("ttl").expect("present");
+        assert!(ttl > TTL - Duration::from_secs(1) && ttl <= TTL);
+
+        cache.delete("a").await.expect("delete");
+        assert_eq!(cache.ttl("a").await.expect("ttl"), None);
+        assert_eq!(get(&cache, "a").await, Lookup::Miss);
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test]
+    async fn purging_removes_only_matching_keys() {
+        let cache = MemoryCache::new(1024);
+        insert(&cache, "home|/", b"home").await;
+        insert(&cache, "home|/?page=2", b"page").await;
+        insert(&cache, "product|/p/1", b"product").await;
+
+        assert_eq!(cache.purge_prefix("home|").await.expect("purge"), 2);
+        assert_eq!(get(&cache, "home|/").await, Lookup::Miss);
+        assert_ne!(get(&cache, "product|/p/1").await, Lookup::Miss);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_entries_are_evicted_first() {
+        let cache = MemoryCache::new(10);
+        insert(&cache, "a", b"aaaa").await;
+        insert(&cache, "b", b"bbbb").await;
+        assert_ne!(get(&cache, "a").await, Lookup::Miss);
+
+        insert(&cache, "c", b"cccc").await;
+        assert_ne!(get(&cache, "a").await, Lookup::Miss);
+        assert_eq!(get(&cache, "b").await, Lookup::Miss);
+        assert_ne!(get(&cache, "c").await, Lookup::Miss);
+
+        insert(&cache, "huge", &[0; 11]).await;
+        assert_eq!(get(&cache, "huge").await, Lookup::Miss);
+        assert_eq!(cache.len(), 2);
+    }
+}
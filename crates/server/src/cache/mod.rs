@@ -0,0 +1,117 @@
+//! Storage for rendered documents of cacheable routes.
+//!
+//! The handlers only talk to [`RenderCache`], so the in-process [`MemoryCache`] can be swapped
+//! for a shared backend such as [`RedisCache`] when several servers should reuse each other's
+//! renders.
+
+mod memory;
+mod redis;
+
+use std::{fmt::Write, time::Duration};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use common::{AppError, RequestContext, RouteConfig};
+
+pub use self::{
+    memory::MemoryCache,
+    redis::{RedisCache, DEFAULT_KEY_PREFIX as DEFAULT_REDIS_KEY_PREFIX},
+};
+
+/// Builds the cache key for a render of `route`: the route id, the request path and the values
+/// of the headers and cookies the route varies on.
+pub fn cache_key(route: &RouteConfig, context: &RequestContext) -> String {
+    let mut key = route_key_prefix(&route.id);
+    key.push_str(&context.path);
+    for name in &route.cache_vary_headers {
+        let name = name.to_ascii_lowercase();
+        let value = context.headers.get(&name).map(String::as_str);
+        let _ = write!(key, "|h:{name}={}", value.unwrap_or_default());
+    }
+    for name in &route.cache_vary_cookies {
+        let value = context.cookies.get(name).map(String::as_str);
+        let _ = write!(key, "|c:{name}={}", value.unwrap_or_default());
+    }
+    key
+}
+
+/// The prefix shared by every cache key of the route `route_id`, for purging its renders.
+pub fn route_key_prefix(route_id: &str) -> String {
+    format!("{route_id}|")
+}
+
+/// Result of looking up a key in the cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    /// Within its TTL.
+    Fresh(Bytes),
+    /// Past its TTL but inside the stale window; serve it and refresh it.
+    Stale(Bytes),
+    Miss,
+}
+
+impl Lookup {
+    /// Short name of the outcome, for logs and spans.
+    pub fn status(&self) -> &'static str {
+        match self {
+            Lookup::Fresh(_) => "fresh",
+            Lookup::Stale(_) => "stale",
+            Lookup::Miss => "miss",
+        }
+    }
+}
+
+/// A store of rendered documents with per-entry freshness and a stale window.
+#[async_trait]
+pub trait RenderCache: Send + Sync {
+    /// Looks up `key`; entries past their stale window are reported as misses.
+    async fn get(&self, key: &str) -> Result<Lookup, AppError>;
+
+    /// Stores `body` under `key`, fresh for `ttl` and servable while stale for `stale_for`
+    /// after that. Replaces any previous entry.
+    async fn set(
+        &self,
+        key: &str,
+        body: Bytes,
+        ttl: Duration,
+        stale_for: Duration,
+    ) -> Result<(), AppError>;
+
+    /// Removes `key` if present.
+    async fn delete(&self, key: &str) -> Result<(), AppError>;
+
+    /// Removes every key starting with `prefix`, returning how many were removed.
+    async fn purge_prefix(&self, prefix: &str) -> Result<u64, AppError>;
+
+    /// Time until `key` is evicted, stale window included, or `None` if it is not stored.
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>, AppError>;
+
+    /// Largest document worth capturing for this cache; larger renders are not stored.
+    fn max_bytes(&self) -> usize;
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, Method};
+
+    use super::*;
+
+    #[test]
+    fn keys_include_selected_dimensions_only() {
+        let mut route = RouteConfig::new("home", "/");
+        route.cache_vary_headers = vec!["Accept-Language".into()];
+        route.cache_vary_cookies = vec!["variant".into()];
+
+        let mut headers = HeaderMap::new();
+        headers.insert("accept-language", "de".parse().unwrap());
+        headers.insert("user-agent", "test".parse().unwrap());
+        headers.insert("cookie", "variant=b; session=xyz".parse().unwrap());
+        let context = RequestContext::from_http_parts(&Method::GET, "/", &headers);
+
+        assert_eq!(
+            cache_key(&route, &context),
+            "home|/|h:accept-language=de|c:variant=b"
+        );
+        assert!(cache_key(&route, &context).starts_with(&route_key_prefix("home")));
+    }
+}
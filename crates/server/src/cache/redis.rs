@@ -0,0 +1,443 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use common::{AppError, ErrorCode};
+use redis::{aio::ConnectionManager, Client, FromRedisValue};
+
+use super::{Lookup, RenderCache};
+
+/// Prefix applied to every key unless [`RedisCache::with_key_prefix`] overrides it.
+pub const DEFAULT_KEY_PREFIX: &str = "rsengine:render:";
+
+/// Bytes at the start of each stored value holding the fresh-until timestamp.
+const HEADER_LEN: usize = 8;
+
+/// Keys requested per `SCAN` call while purging.
+const SCAN_COUNT: usize = 500;
+
+/// Render cache shared between servers through any server speaking the Redis protocol.
+///
+/// Each value is the entry's fresh-until time (big-endian Unix milliseconds) followed by the
+/// document; the key expires once the stale window has passed, so the server does the eviction.
+#[derive(Clone)]
+pub struct RedisCache {
+    connection: ConnectionManager,
+    key_prefix: String,
+    max_bytes: usize,
+}
+
+impl RedisCache {
+    /// Connects to `url` (for example `redis://127.0.0.1:6379/0`). Commands that take longer
+    /// than `timeout`, including reconnects, fail instead of holding up the request.
+    pub async fn connect(url: &str, timeout: Duration) -> Result<Self, AppError> {
+        let client = Client::open(url).map_err(|err| {
+            AppError::new(
+                ErrorCode::BadRequest,
+                format!("invalid redis url '{url}': {err}"),
+            )
+        })?;
+        let connection =
+            ConnectionManager::new_with_backoff_and_timeouts(client, 2, 100, 3, timeout, timeout)
+                .await
+                .map_err(|err| cache_error("connect", err))?;
+
+        Ok(Self {
+            connection,
+            key_prefix: DEFAULT_KEY_PREFIX.to_string(),
+            max_bytes: 1024 * 1024,
+        })
+    }
+
+    /// Namespaces every key, so several deployments can share one server.
+    pub fn with_key_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.key_prefix = prefix.into();
+        self
+    }
+
+    /// Largest document that will be stored; defaults to 1 MiB.
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{key}", self.key_prefix)
+    }
+
+    async fn query<T: FromRedisValue>(
+        &self,
+        operation: &'static str,
+        command: &redis::Cmd,
+    ) -> Result<T, AppError> {
+        let mut connection = self.connection.clone();
+        command
+            .query_async(&mut connection)
+            .await
+            .map_err(|err| cache_error(operation, err))
+    }
+}
+
+#[async_trait]
+impl RenderCache for RedisCache {
+    async fn get(&self, key: &str) -> Result<Lookup, AppError> {
+        let value: Option<Vec<u8>> = self
+            .query("GET", redis::cmd("GET").arg(self.key(key)))
+            .await?;
+        let Some(value) = value else {
+            return Ok(Lookup::Miss);
+        };
+        if value.len() < HEADER_LEN {
+            return Err(AppError::new(
+                ErrorCode::UpstreamFailure,
+                format!("render cache entry '{key}' is truncated"),
+            ));
+        }
+
+        let mut fresh_until = [0; HEADER_LEN];
+        fresh_until.copy_from_slice(&value[..HEADER_LEN]);
+        let fresh = u64::from_be_bytes(fresh_until) > unix_millis();
+        let body = Bytes::from(value).slice(HEADER_LEN..);
+        Ok(if fresh {
+            Lookup::Fresh(body)
+        } else {
+            Lookup::Stale(body)
+        })
+    }
+
+    async fn set(
+        &self,
+        key: &str,
+        body: Bytes,
+        ttl: Duration,
+        stale_for: Duration,
+    ) -> Result<(), AppError> {
+        if body.len() > self.max_bytes || ttl.is_zero() {
+            return Ok(());
+        }
+
+        let fresh_until = unix_millis().saturating_add(millis(ttl));
+        let mut value = Vec::with_capacity(HEADER_LEN + body.len());
+        value.extend_from_slice(&fresh_until.to_be_bytes());
+        value.extend_from_slice(&body);
+        let expire_after = millis(ttl.saturating_add(stale_for)).max(1);
+
+        self.query(
+            "SET",
+            redis::cmd("SET")
+                .arg(self.key(key))
+                .arg(value)
+                .arg("PX")
+                .arg(expire_after),
+        )
+        .await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        self.query("DEL", redis::cmd("DEL").arg(self.key(key)))
+            .await
+    }
+
+    async fn purge_prefix(&self, prefix: &str) -> Result<u64, AppError> {
+        let pattern = format!("{}*", glob_escape(&self.key(prefix)));
+        let mut cursor = 0u64;
+        let mut purged = 0;
+        loop {
+            let (next, keys): (u64, Vec<Vec<u8>>) = self
+                .query(
+                    "SCAN",
+                    redis::cmd("SCAN")
+                        .arg(cursor)
+                        .arg("MATCH")
+                        .arg(&pattern)
+                        .arg("COUNT")
+                        .arg(SCAN_COUNT),
+                )
+                .await?;
+            if !keys.is_empty() {
+                let removed: u64 = self.query("DEL", redis::cmd("DEL").arg(keys)).await?;
+                purged += removed;
+            }
+            if next == 0 {
+                return Ok(purged);
+            }
+            cursor = next;
+        }
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>, AppError> {
+        let remaining: i64 = self
+            .query("PTTL", redis::cmd("PTTL").arg(self.key(key)))
+            .await?;
+        // -2: no such key; -1: stored without an expiry, which this cache never does.
+        Ok(u64::try_from(remaining).ok().map(Duration::from_millis))
+    }
+
+    fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+}
+
+fn cache_error(operation: &str, err: redis::RedisError) -> AppError {
+    AppError::new(
+        ErrorCode::UpstreamFailure,
+        format!("redis {operation} failed: {err}"),
+    )
+}
+
+/// Escapes the characters `SCAN MATCH` treats as glob syntax.
+fn glob_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(millis)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    type Store = Arc<Mutex<HashMap<Vec<u8>, (Vec<u8>, Instant)>>>;
+
+    /// Serves GET, SET .. PX, DEL, PTTL and SCAN .. MATCH <prefix>* over RESP, enough to stand in for a real server.
+    /// Set `RSENGINE_TEST_REDIS_URL` to run the tests against one instead.
+    async fn stand_in() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let address = listener.local_addr().expect("address");
+        let store = Store::default();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serve(socket, Arc::clone(&store)));
+            }
+        });
+        format!("redis://{address}")
+    }
+
+    async fn serve(socket: TcpStream, store: Store) {
+        let (reader, mut writer) = socket.into_split();
+        let mut reader = BufReader::new(reader);
+        while let Some(args) = read_command(&mut reader).await {
+            let reply = execute(&store, &args);
+            if writer.write_all(&reply).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn read_line(reader: &mut BufReader<impl AsyncReadExt + Unpin>) -> Option<String> {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.ok().filter(|n| *n > 0)?;
+        Some(line.trim_end().to_string())
+    }
+
+    async fn read_command(
+        reader: &mut BufReader<impl AsyncReadExt + Unpin>,
+    ) -> Option<Vec<Vec<u8>>> {
+        let count: usize = read_line(reader).await?.strip_prefix('*')?.parse().ok()?;
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len: usize = read_line(reader).await?.strip_prefix('$')?.parse().ok()?;
+            let mut arg = vec![0; len + 2];
+            reader.read_exact(&mut arg).await.ok()?;
+            arg.truncate(len);
+            args.push(arg);
+        }
+        Some(args)
+    }
+
+    fn execute(store: &Store, args: &[Vec<u8>]) -> Vec<u8> {
+        let mut store = store.lock().unwrap();
+        let now = Instant::now();
+        store.retain(|_, (_, expires_at)| *expires_at > now);
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        match (name.as_str(), args) {
+            ("GET", [_, key]) => match store.get(key) {
+                Some((value, _)) => {
+                    let mut reply = format!("${}\r\n", value.len()).into_bytes();
+                    reply.extend_from_slice(value);
+                    reply.extend_from_slice(b"\r\n");
+                    reply
+                }
+                None => b"$-1\r\n".to_vec(),
+            },
+            ("SET", [_, key, value, px, ttl]) if px.eq_ignore_ascii_case(b"PX") => {
+                let ttl: u64 = String::from_utf8_lossy(ttl).parse().unwrap();
+                let expires_at = now + Duration::from_millis(ttl);
+                store.insert(key.clone(), (value.clone(), expires_at));
+                b"+OK\r\n".to_vec()
+            }
+            ("DEL", [_, keys @ ..]) => {
+                let removed = keys
+                    .iter()
+                    .filter(|key| store.remove(*key).is_some())
+                    .count();
+                format!(":{removed}\r\n").into_bytes()
+            }
+            ("SCAN", [_, _cursor, _match, pattern, ..]) => {
+                // Only literal prefixes are supported: drop the trailing `*` and the escapes.
+                let prefix: Vec<u8> = pattern[..pattern.len() - 1]
+                    .iter()
+                    .copied()
+                    .filter(|byte| *byte != b'\\')
+                    .collect();
+                let keys: Vec<_> = store
+                    .keys()
+                    .filter(|key| key.starts_with(&prefix))
+                    .collect();
+                let mut reply = format!("*2\r\n$1\r\n0\r\n*{}\r\n", keys.len()).into_bytes();
+                for key in keys {
+                    reply.extend_from_slice(format!("${}\r\n", key.len()).as_bytes());
+                    reply.extend_from_slice(key);
+                    reply.extend_from_slice(b"\r\n");
+                }
+                reply
+            }
+            ("PTTL", [_, key]) => {
+                let remaining = store
+                    .get(key)
+                    .map_or(-2, |(_, expires_at)| (*expires_at - now).as_millis() as i64);
+                format!(":{remaining}\r\n").into_bytes()
+            }
+            _ => format!("-ERR unknown command '{name}'\r\n").into_bytes(),
+        }
+    }
+
+    async fn cache() -> RedisCache {
+        let url = match std::env::var("RSENGINE_TEST_REDIS_URL") {
+            Ok(url) => url,
+            Err(_) => stand_in().await,
+        };
+        RedisCache::connect(&url, Duration::from_secs(2))
+            .await
+            .expect("connect")
+            .with_key_prefix(format!("rsengine:test:{}:", uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn documents_round_trip_with_their_ttl() {
+        let cache = cache().await;
+        assert_eq!(cache.get("home").await.expect("get"), Lookup::Miss);
+        assert_eq!(cache.ttl("home").await.expect("ttl"), None);
+
+        let ttl = Duration::from_secs(60);
+        cache
+            .set(
+                "home",
+                Bytes::from_static(b"<html>"),
+                ttl,
+                Duration::from_secs(30),
+            )
+            .await
+            .expect("set");
+
+        assert_eq!(
+            cache.get("home").await.expect("get"),
+            Lookup::Fresh(Bytes::from_static(b"<html>"))
+        );
+        let remaining = cache.ttl("home").await.expect("ttl").expect("stored");
+        assert!(remaining > ttl && remaining <= Duration::from_secs(90));
+
+        cache.delete("home").await.expect("delete");
+        assert_eq!(cache.get("home").await.expect("get"), Lookup::Miss);
+    }
+
+    #[tokio::test]
+    async fn entries_past_their_ttl_are_stale_until_the_key_expires() {
+        let cache = cache().await;
+        let body = Bytes::from_static(b"old");
+        cache
+            .set(
+                "a",
+                body.clone(),
+                Duration::from_millis(1),
+                Duration::from_secs(60),
+            )
+            .await
+            .expect("set");
+        cache
+            .set("b", body.clone(), Duration::from_millis(1), Duration::ZERO)
+            .await
+            .expect("set");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert_eq!(cache.get("a").await.expect("get"), Lookup::Stale(body));
+        assert_eq!(cache.get("b").await.expect("get"), Lookup::Miss);
+    }
+
+    #[tokio::test]
+    async fn purging_removes_only_matching_keys() {
+        let cache = cache().await;
+        let body = Bytes::from_static(b"doc");
+        let ttl = Duration::from_secs(60);
+        for key in ["home|/", "home|/*", "product|/p/1"] {
+            cache
+                .set(key, body.clone(), ttl, Duration::ZERO)
+                .await
+                .expect("set");
+        }
+
+        assert_eq!(cache.purge_prefix("home|").await.expect("purge"), 2);
+        assert_eq!(cache.get("home|/*").await.expect("get"), Lookup::Miss);
+        assert_eq!(
+            cache.get("product|/p/1").await.expect("get"),
+            Lookup::Fresh(body)
+        );
+    }
+
+    #[tokio::test]
+    async fn oversized_documents_are_not_stored() {
+        let cache = cache().await.with_max_bytes(4);
+        cache
+            .set(
+                "big",
+                Bytes::from_static(b"too large"),
+                Duration::from_secs(60),
+                Duration::ZERO,
+            )
+            .await
+            .expect("set");
+        assert_eq!(cache.get("big").await.expect("get"), Lookup::Miss);
+    }
+
+    #[tokio::test]
+    async fn unreachable_servers_fail_to_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let address = listener.local_addr().expect("address");
+        drop(listener);
+
+        let err = RedisCache::connect(&format!("redis://{address}"), Duration::from_millis(200))
+            .await
+            .err()
+            .expect("connection refused");
+        assert!(matches!(err.code(), ErrorCode::UpstreamFailure));
+        assert!(RedisCache::connect("not a url", Duration::from_millis(200))
+            .await
+            .is_err());
+    }
+}
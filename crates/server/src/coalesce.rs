@@ -0,0 +1,124 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use common::{AppError, ErrorCode};
+use tokio::sync::watch;
+
+/// Outcome shared with requests that waited on another request's render.
+type Outcome = Option<Result<Bytes, (ErrorCode, String)>>;
+
+/// Renders currently in progress, keyed by cache key, so concurrent misses share one render.
+#[derive(Default)]
+pub struct RenderFlights {
+    flights: Mutex<HashMap<String, watch::Receiver<Outcome>>>,
+}
+
+/// Either the right to render `key`, or a handle on the render already doing so.
+pub enum Claim {
+    Leader(Flight),
+    Follower(Waiter),
+}
+
+impl RenderFlights {
+    /// Claims the render for `key`; only the first caller while no render is running leads.
+    pub fn claim(self: &Arc<Self>, key: &str) -> Claim {
+        let mut flights = self.lock();
+        if let Some(receiver) = flights.get(key) {
+            return Claim::Follower(Waiter(receiver.clone()));
+        }
+
+        let (sender, receiver) = watch::channel(None);
+        flights.insert(key.to_string(), receiver);
+        Claim::Leader(Flight {
+            flights: Arc::clone(self),
+            key: key.to_string(),
+            sender,
+        })
+    }
+
+    /// Whether a render for `key` is in progress.
+    pub fn is_rendering(&self, key: &str) -> bool {
+        self.lock().contains_key(key)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, watch::Receiver<Outcome>>> {
+        self.flights.lock().expect("render flights mutex poisoned")
+    }
+}
+
+/// Held by the request performing a render; followers are released when it completes or drops.
+pub struct Flight {
+    flights: Arc<RenderFlights>,
+    key: String,
+    sender: watch::Sender<Outcome>,
+}
+
+impl Flight {
+    /// Hands the finished document to every waiting request.
+    pub fn complete(self, body: Bytes) {
+        self.sender.send_replace(Some(Ok(body)));
+    }
+
+    /// Reports the render failure to every waiting request.
+    pub fn fail(self, error: &AppError) {
+        self.sender
+            .send_replace(Some(Err((error.code(), error.message().to_string()))));
+    }
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        self.flights.lock().remove(&self.key);
+    }
+}
+
+/// A request waiting on another request's render.
+pub struct Waiter(watch::Receiver<Outcome>);
+
+impl Waiter {
+    /// Waits for the leader's outcome; `None` means it was abandoned without one, for example
+    /// because its client disconnected.
+    pub async fn wait(mut self) -> Option<Result<Bytes, AppError>> {
+        let outcome = self.0.wait_for(Option::is_some).await.ok()?.clone()?;
+        Some(outcome.map_err(|(code, message)| AppError::new(code, message)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn followers_receive_the_leaders_document() {
+        let flights = Arc::new(RenderFlights::default());
+        let Claim::Leader(flight) = flights.claim("home") else {
+            panic!("first claim should lead");
+        };
+        let Claim::Follower(waiter) = flights.claim("home") else {
+            panic!("second claim should follow");
+        };
+
+        flight.complete(Bytes::from_static(b"<html>"));
+        let body = waiter.wait().await.expect("outcome").expect("document");
+        assert_eq!(&body[..], b"<html>");
+        assert!(!flights.is_rendering("home"));
+    }
+
+    #[tokio::test]
+    async fn abandoned_flights_release_followers_without_an_outcome() {
+        let flights = Arc::new(RenderFlights::default());
+        let Claim::Leader(flight) = flights.claim("home") else {
+            panic!("first claim should lead");
+        };
+        let Claim::Follower(waiter) = flights.claim("home") else {
+            panic!("second claim should follow");
+        };
+
+        drop(flight);
+        assert!(waiter.wait().await.is_none());
+        assert!(matches!(flights.claim("home"), Claim::Leader(_)));
+    }
+}
@@ -0,0 +1,1336 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use common::{RouteConfig, RoutePattern};
+use runtime::{registry::DEFAULT_BUNDLE, ConsoleConfig, PoolConfig, RuntimeConfig};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{Map, Value};
+use serde_path_to_error::Segment;
+use thiserror::Error;
+use tracing_subscriber::EnvFilter;
+
+use crate::telemetry::TelemetryConfig;
+
+/// Prefix shared by every environment variable that overrides the config file.
+pub const ENV_PREFIX: &str = "RSENGINE_";
+
+/// Server settings loaded from `--config`, environment variables and command-line flags.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the HTTP listener binds to.
+    pub bind: SocketAddr,
+    pub runtime: RuntimeSettings,
+    pub telemetry: TelemetryConfig,
+    pub cache: CacheSettings,
+    pub admin: AdminSettings,
+    /// Bundles served alongside the `[runtime]` one, keyed by the id routes select them with.
+    pub bundles: BTreeMap<String, BundleSettings>,
+    /// Routes served by the runtime; the default `/stream` route is used when empty.
+    pub routes: Vec<RouteConfig>,
+}
+
+/// The `[runtime]` section: bundle, engine limits and pool sizing.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeSettings {
+    /// JavaScript bundle exporting the `stream` handler.
+    pub bundle: Option<PathBuf>,
+    /// Source map for the bundle; discovered next to it when unset.
+    pub source_map: Option<PathBuf>,
+    /// Snapshot built by `cargo xtask snapshot`; engines boot from it while it matches the bundle.
+    pub snapshot: Option<PathBuf>,
+    /// Bundle registry directory; when set, the bundle is its active `default` version and
+    /// `bundle` / `source_map` are ignored.
+    pub registry: Option<PathBuf>,
+    /// Reloads the bundle when it or its source map changes on disk.
+    pub watch: bool,
+    /// Quiet period after a change before the bundle is reloaded.
+    #[serde(with = "humantime_serde")]
+    pub watch_debounce: Duration,
+    /// Friendly name used to tag logs and metrics for this runtime.
+    pub name: String,
+    #[serde(with = "humantime_serde")]
+    pub render_timeout: Duration,
+    #[serde(with = "humantime_serde")]
+    pub cpu_time_slice: Duration,
+    pub heap_limit_bytes: usize,
+    /// Shows clients the full error of a failed render, with its stack remapped to the original
+    /// sources, or of a failed data fetch. For development only; otherwise clients get the
+    /// request id and the details are only logged.
+    pub expose_render_errors: bool,
+    pub pool: PoolSettings,
+    pub console: ConsoleSettings,
+}
+
+/// A `[bundles.<id>]` section: another bundle with its own engine pool. Unset limits are taken
+/// from `[runtime]`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BundleSettings {
+    /// JavaScript bundle exporting the `stream` handler; with a registry, the active version of
+    /// the registry's `<id>` bundle is served instead.
+    pub bundle: Option<PathBuf>,
+    /// Source map for the bundle; discovered next to it when unset.
+    pub source_map: Option<PathBuf>,
+    /// Snapshot of the bundle that engines boot from while it matches the bundle.
+    pub snapshot: Option<PathBuf>,
+    /// Name used to tag logs and metrics for this bundle's runtime; the id when unset.
+    pub name: Option<String>,
+    #[serde(with = "humantime_serde")]
+    pub render_timeout: Option<Duration>,
+    #[serde(with = "humantime_serde")]
+    pub cpu_time_slice: Option<Duration>,
+    pub heap_limit_bytes: Option<usize>,
+    pub pool: Option<PoolSettings>,
+}
+
+/// The `[runtime.pool]` section, mirroring [`PoolConfig`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolSettings {
+    pub min_size: usize,
+    pub max_size: usize,
+    #[serde(with = "humantime_serde")]
+    pub idle_timeout: Duration,
+    pub max_renders_per_engine: Option<u64>,
+}
+
+/// The `[runtime.console]` section, mirroring [`ConsoleConfig`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsoleSettings {
+    pub messages_per_second: u32,
+    pub burst: u32,
+    pub max_arg_bytes: usize,
+}
+
+/// The `[cache]` section: storage for routes that set `cache_ttl_seconds`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSettings {
+    pub backend: CacheBackend,
+    /// Upper bound on cached HTML: in total for `memory`, per document for `redis`. `0`
+    /// disables caching.
+    pub max_bytes: usize,
+    pub redis: RedisSettings,
+}
+
+/// Where rendered documents are kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    /// In-process LRU cache, private to each server.
+    #[default]
+    Memory,
+    /// A Redis-protocol server shared by every server pointing at it.
+    Redis,
+}
+
+impl FromStr for CacheBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "memory" => Ok(Self::Memory),
+            "redis" => Ok(Self::Redis),
+            _ => Err("expected 'memory' or 'redis'".to_string()),
+        }
+    }
+}
+
+/// The `[cache.redis]` section, used when `backend = "redis"`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisSettings {
+    /// Connection URL such as `redis://127.0.0.1:6379/0`.
+    pub url: Option<String>,
+    /// Prepended to every cache key.
+    pub key_prefix: String,
+    /// Longest a cache command may take before the request renders without the cache.
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+/// The `[admin]` section: the management API, enabled by setting `token`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminSettings {
+    /// Bearer token every admin request must present; the admin API is disabled when unset.
+    pub token: Option<String>,
+    /// Serves the admin API on this separate address instead of under `/admin` on `bind`.
+    pub bind: Option<SocketAddr>,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            backend: CacheBackend::default(),
+            max_bytes: 64 * 1024 * 1024,
+            redis: RedisSettings::default(),
+        }
+    }
+}
+
+impl Default for RedisSettings {
+    fn default() -> Self {
+        Self {
+            url: None,
+            key_prefix: crate::cache::DEFAULT_REDIS_KEY_PREFIX.to_string(),
+            timeout: Duration::from_millis(250),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            runtime: RuntimeSettings::default(),
+            telemetry: TelemetryConfig::default(),
+            cache: CacheSettings::default(),
+            admin: AdminSettings::default(),
+            bundles: BTreeMap::new(),
+            routes: Vec::new(),
+        }
+    }
+}
+
+impl Default for RuntimeSettings {
+    fn default() -> Self {
+        Self {
+            bundle: None,
+            source_map: None,
+            snapshot: None,
+            registry: None,
+            watch: false,
+            watch_debounce: Duration::from_millis(250),
+            name: "rsengine".to_string(),
+            render_timeout: Duration::from_secs(10),
+            cpu_time_slice: Duration::from_secs(2),
+            heap_limit_bytes: 256 * 1024 * 1024,
+            expose_render_errors: false,
+            pool: PoolSettings::default(),
+            console: ConsoleSettings::default(),
+        }
+    }
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        let defaults = PoolConfig::default();
+        Self {
+            min_size: defaults.min_size,
+            max_size: defaults.max_size,
+            idle_timeout: defaults.idle_timeout,
+            max_renders_per_engine: defaults.max_renders_per_engine,
+        }
+    }
+}
+
+impl Default for ConsoleSettings {
+    fn default() -> Self {
+        let defaults = ConsoleConfig::default();
+        Self {
+            messages_per_second: defaults.messages_per_second,
+            burst: defaults.burst,
+            max_arg_bytes: defaults.max_arg_bytes,
+        }
+    }
+}
+
+/// A single invalid setting, named by its path in the config file or its environment variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Failure to produce a usable [`ServerConfig`].
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read config file '{}'", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("failed to parse config file '{}': {message}", path.display())]
+    Parse { path: PathBuf, message: String },
+    #[error("config file '{}' must end in .toml, .yaml or .yml", path.display())]
+    UnsupportedFormat { path: PathBuf },
+    #[error("invalid configuration:{}", list(.0))]
+    Invalid(Vec<FieldError>),
+}
+
+fn list(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|error| format!("\n  - {error}"))
+        .collect()
+}
+
+/// Settings given on the command line; these win over the file and the environment.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigOverrides {
+    pub bundle: Option<PathBuf>,
+    /// `(id, path)` pairs that set or add `[bundles.<id>]` bundle files.
+    pub bundles: Vec<(String, PathBuf)>,
+    pub runtime_name: Option<String>,
+    pub watch: Option<bool>,
+}
+
+impl ServerConfig {
+    /// Reads `path` (when given), applies environment and command-line overrides and validates
+    /// the result, reporting every invalid field at once.
+    pub fn load(
+        path: Option<&Path>,
+        env: impl IntoIterator<Item = (String, String)>,
+        overrides: ConfigOverrides,
+    ) -> Result<Self, ConfigError> {
+        let Parsed {
+            mut config,
+            mut errors,
+            dropped,
+        } = match path {
+            Some(path) => Self::parse_file(path)?,
+            None => Parsed::default(),
+        };
+
+        errors.extend(config.apply_env(env));
+        if let Some(bundle) = overrides.bundle {
+            config.runtime.bundle = Some(bundle);
+        }
+        for (id, bundle) in overrides.bundles {
+            config.bundles.entry(id).or_default().bundle = Some(bundle);
+        }
+        if let Some(name) = overrides.runtime_name {
+            config.runtime.name = name;
+        }
+        if let Some(watch) = overrides.watch {
+            config.runtime.watch = watch;
+        }
+        // Fields that failed to parse hold their defaults, which would be reported again.
+        let covered: Vec<String> = errors
+            .iter()
+            .map(|error| error.field.clone())
+            .chain(dropped)
+            .collect();
+        errors.extend(
+            config
+                .validate()
+                .into_iter()
+                .filter(|error| !covered.iter().any(|field| is_within(&error.field, field))),
+        );
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError::Invalid(errors))
+        }
+    }
+
+    /// Parses a TOML or YAML file, chosen by extension, reporting every field that is unknown
+    /// or has the wrong type.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let parsed = Self::parse_file(path)?;
+        if parsed.errors.is_empty() {
+            Ok(parsed.config)
+        } else {
+            Err(ConfigError::Invalid(parsed.errors))
+        }
+    }
+
+    fn parse_file(path: &Path) -> Result<Parsed, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let parse_error = |message: String| ConfigError::Parse {
+            path: path.to_path_buf(),
+            message,
+        };
+
+        // Syntax errors stop here; the document is then converted section by section so that
+        // every mistyped field is reported rather than only the first.
+        let document = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => {
+                toml::from_str::<Value>(&contents).map_err(|err| parse_error(err.to_string()))?
+            }
+            Some("yaml" | "yml") => serde_yaml::from_str::<Value>(&contents)
+                .map_err(|err| parse_error(err.to_string()))?,
+            _ => {
+                return Err(ConfigError::UnsupportedFormat {
+                    path: path.to_path_buf(),
+                })
+            }
+        };
+        match document {
+            Value::Object(sections) => Ok(Parsed::from_sections(sections)),
+            // An empty YAML file.
+            Value::Null => Ok(Parsed::default()),
+            _ => Err(parse_error("expected a table of settings".to_string())),
+        }
+    }
+
+    /// Applies `RSENGINE_*` variables (and the legacy `PORT`), returning the ones that did not
+    /// parse.
+    pub fn apply_env(
+        &mut self,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        for (name, value) in env {
+            let applied = match name.strip_prefix(ENV_PREFIX) {
+                Some(key) => self.apply_env_var(key, &value),
+                None if name == "PORT" => parse::<u16>(&value).map(|port| self.bind.set_port(port)),
+                None => Ok(()),
+            };
+            if let Err(message) = applied {
+                errors.push(FieldError::new(name, message));
+            }
+        }
+        errors
+    }
+
+    fn apply_env_var(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let runtime = &mut self.runtime;
+        match key {
+            "BIND" => self.bind = parse(value)?,
+            "BUNDLE" => runtime.bundle = Some(PathBuf::from(value)),
+            "SOURCE_MAP" => runtime.source_map = Some(PathBuf::from(value)),
+            "SNAPSHOT" => runtime.snapshot = Some(PathBuf::from(value)),
+            "REGISTRY" => runtime.registry = Some(PathBuf::from(value)),
+            "WATCH" => runtime.watch = parse(value)?,
+            "WATCH_DEBOUNCE" => runtime.watch_debounce = parse_duration(value)?,
+            "RUNTIME_NAME" => runtime.name = value.to_string(),
+            "RENDER_TIMEOUT" => runtime.render_timeout = parse_duration(value)?,
+            "CPU_TIME_SLICE" => runtime.cpu_time_slice = parse_duration(value)?,
+            "HEAP_LIMIT_BYTES" => runtime.heap_limit_bytes = parse(value)?,
+            "EXPOSE_RENDER_ERRORS" => runtime.expose_render_errors = parse(value)?,
+            "POOL_MIN_SIZE" => runtime.pool.min_size = parse(value)?,
+            "POOL_MAX_SIZE" => runtime.pool.max_size = parse(value)?,
+            "POOL_IDLE_TIMEOUT" => runtime.pool.idle_timeout = parse_duration(value)?,
+            "POOL_MAX_RENDERS_PER_ENGINE" => {
+                runtime.pool.max_renders_per_engine = Some(parse(value)?)
+            }
+            "CONSOLE_MESSAGES_PER_SECOND" => runtime.console.messages_per_second = parse(value)?,
+            "CONSOLE_BURST" => runtime.console.burst = parse(value)?,
+            "CONSOLE_MAX_ARG_BYTES" => runtime.console.max_arg_bytes = parse(value)?,
+            "LOG_FILTER" => self.telemetry.log_filter = value.to_string(),
+            "LOG_FORMAT" => self.telemetry.log_format = value.trim().parse()?,
+            "METRICS_BIND" => self.telemetry.metrics_bind = Some(parse(value)?),
+            "OTLP_ENDPOINT" => self.telemetry.otlp_endpoint = Some(value.to_string()),
+            "SERVICE_NAME" => self.telemetry.service_name = value.to_string(),
+            "CACHE_BACKEND" => self.cache.backend = value.trim().parse()?,
+            "CACHE_MAX_BYTES" => self.cache.max_bytes = parse(value)?,
+            "CACHE_REDIS_URL" => self.cache.redis.url = Some(value.to_string()),
+            "CACHE_REDIS_KEY_PREFIX" => self.cache.redis.key_prefix = value.to_string(),
+            "CACHE_REDIS_TIMEOUT" => self.cache.redis.timeout = parse_duration(value)?,
+            "ADMIN_TOKEN" => self.admin.token = Some(value.to_string()),
+            "ADMIN_BIND" => self.admin.bind = Some(parse(value)?),
+            // Unknown variables are ignored so unrelated tooling can share the prefix.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Checks every setting, returning one error per invalid field.
+    pub fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let runtime = &self.runtime;
+
+        match (&runtime.registry, &runtime.bundle) {
+            (Some(registry), _) if !registry.is_dir() => errors.push(FieldError::new(
+                "runtime.registry",
+                format!("'{}' is not a directory", registry.display()),
+            )),
+            (Some(_), _) => {}
+            (None, None) => errors.push(FieldError::new(
+                "runtime.bundle",
+                "is required (set it in the config file, RSENGINE_BUNDLE or --bundle)",
+            )),
+            (None, Some(bundle)) if !bundle.is_file() => errors.push(FieldError::new(
+                "runtime.bundle",
+                format!("'{}' is not a readable file", bundle.display()),
+            )),
+            (None, Some(_)) => {}
+        }
+        if let Some(snapshot) = &runtime.snapshot {
+            if !snapshot.is_file() {
+                errors.push(FieldError::new(
+                    "runtime.snapshot",
+                    format!("'{}' is not a readable file", snapshot.display()),
+                ));
+            }
+        }
+        if runtime.name.trim().is_empty() {
+            errors.push(FieldError::new("runtime.name", "must not be empty"));
+        }
+        for (field, value) in [
+            ("runtime.render_timeout", runtime.render_timeout),
+            ("runtime.cpu_time_slice", runtime.cpu_time_slice),
+            ("runtime.pool.idle_timeout", runtime.pool.idle_timeout),
+        ] {
+            if value.is_zero() {
+                errors.push(FieldError::new(field, "must be greater than zero"));
+            }
+        }
+        if runtime.heap_limit_bytes == 0 {
+            errors.push(FieldError::new(
+                "runtime.heap_limit_bytes",
+                "must be greater than zero",
+            ));
+        }
+        validate_pool("runtime.pool", &runtime.pool, &mut errors);
+        if runtime.console.max_arg_bytes == 0 {
+            errors.push(FieldError::new(
+                "runtime.console.max_arg_bytes",
+                "must be greater than zero",
+            ));
+        }
+
+        if let Err(err) = EnvFilter::try_new(&self.telemetry.log_filter) {
+            errors.push(FieldError::new("telemetry.log_filter", err.to_string()));
+        }
+        if let Some(endpoint) = &self.telemetry.otlp_endpoint {
+            if !(endpoint.starts_with("http://") || endpoint.starts_with("https://")) {
+                errors.push(FieldError::new(
+                    "telemetry.otlp_endpoint",
+                    format!("'{endpoint}' must be an http:// or https:// URL"),
+                ));
+            }
+        }
+        if self.telemetry.service_name.trim().is_empty() {
+            errors.push(FieldError::new(
+                "telemetry.service_name",
+                "must not be empty",
+            ));
+        }
+        if self.telemetry.metrics_bind == Some(self.bind) {
+            errors.push(FieldError::new(
+                "telemetry.metrics_bind",
+                format!("{} is already used by bind", self.bind),
+            ));
+        }
+
+        if self.cache.backend == CacheBackend::Redis {
+            match &self.cache.redis.url {
+                None => errors.push(FieldError::new(
+                    "cache.redis.url",
+                    "is required when cache.backend is 'redis'",
+                )),
+                Some(url) => {
+                    if let Err(err) = redis::Client::open(url.as_str()) {
+                        errors.push(FieldError::new(
+                            "cache.redis.url",
+                            format!("'{url}' is invalid: {err}"),
+                        ));
+                    }
+                }
+            }
+            if self.cache.redis.timeout.is_zero() {
+                errors.push(FieldError::new(
+                    "cache.redis.timeout",
+                    "must be greater than zero",
+                ));
+            }
+        }
+
+        match &self.admin.token {
+            Some(token) if token.trim().is_empty() => {
+                errors.push(FieldError::new("admin.token", "must not be empty"))
+            }
+            Some(_) => {}
+            None if self.admin.bind.is_some() => errors.push(FieldError::new(
+                "admin.token",
+                "is required when admin.bind is set",
+            )),
+            None => {}
+        }
+        if let Some(admin_bind) = self.admin.bind {
+            if admin_bind == self.bind || Some(admin_bind) == self.telemetry.metrics_bind {
+                errors.push(FieldError::new(
+                    "admin.bind",
+                    format!("{admin_bind} is already used by another listener"),
+                ));
+            }
+        }
+
+        for (id, bundle) in &self.bundles {
+            self.validate_bundle(id, bundle, &mut errors);
+        }
+
+        let mut ids = HashSet::new();
+        for (index, route) in self.routes.iter().enumerate() {
+            if route.id.trim().is_empty() {
+                errors.push(FieldError::new(
+                    format!("routes[{index}].id"),
+                    "must not be empty",
+                ));
+            } else if !ids.insert(route.id.as_str()) {
+                errors.push(FieldError::new(
+                    format!("routes[{index}].id"),
+                    format!("'{}' is declared more than once", route.id),
+                ));
+            }
+            if let Err(err) = RoutePattern::parse(&route.pattern) {
+                errors.push(FieldError::new(
+                    format!("routes[{index}].pattern"),
+                    err.message(),
+                ));
+            }
+            let mut keys = HashSet::new();
+            for (position, data) in route.data.iter().enumerate() {
+                let field = format!("routes[{index}].data[{position}]");
+                if data.provider.trim().is_empty() {
+                    errors.push(FieldError::new(
+                        format!("{field}.provider"),
+                        "must not be empty",
+                    ));
+                } else if !keys.insert(data.key()) {
+                    errors.push(FieldError::new(
+                        format!("{field}.key"),
+                        format!("'{}' is already fetched by this route", data.key()),
+                    ));
+                }
+                if data.timeout_ms == Some(0) {
+                    errors.push(FieldError::new(
+                        format!("{field}.timeout_ms"),
+                        "must be greater than zero",
+                    ));
+                }
+            }
+            if let Some(bundle) = &route.bundle {
+                if bundle != DEFAULT_BUNDLE && !self.bundles.contains_key(bundle) {
+                    errors.push(FieldError::new(
+                        format!("routes[{index}].bundle"),
+                        format!("'{bundle}' is not declared under [bundles]"),
+                    ));
+                }
+            }
+        }
+
+        errors
+    }
+
+    fn validate_bundle(&self, id: &str, bundle: &BundleSettings, errors: &mut Vec<FieldError>) {
+        let section = format!("bundles.{id}");
+        if id == DEFAULT_BUNDLE {
+            errors.push(FieldError::new(
+                section.as_str(),
+                "'default' is reserved for the [runtime] bundle",
+            ));
+        } else if id.trim().is_empty() {
+            errors.push(FieldError::new(section.as_str(), "id must not be empty"));
+        }
+
+        match (&self.runtime.registry, &bundle.bundle) {
+            (Some(_), _) => {}
+            (None, None) => errors.push(FieldError::new(
+                format!("{section}.bundle"),
+                "is required unless runtime.registry is set",
+            )),
+            (None, Some(path)) if !path.is_file() => errors.push(FieldError::new(
+                format!("{section}.bundle"),
+                format!("'{}' is not a readable file", path.display()),
+            )),
+            (None, Some(_)) => {}
+        }
+        if let Some(snapshot) = &bundle.snapshot {
+            if !snapshot.is_file() {
+                errors.push(FieldError::new(
+                    format!("{section}.snapshot"),
+                    format!("'{}' is not a readable file", snapshot.display()),
+                ));
+            }
+        }
+        if bundle
+            .name
+            .as_deref()
+            .is_some_and(|name| name.trim().is_empty())
+        {
+            errors.push(FieldError::new(
+                format!("{section}.name"),
+                "must not be empty",
+            ));
+        }
+        for (field, value) in [
+            ("render_timeout", bundle.render_timeout),
+            ("cpu_time_slice", bundle.cpu_time_slice),
+        ] {
+            if value.is_some_and(|value| value.is_zero()) {
+                errors.push(FieldError::new(
+                    format!("{section}.{field}"),
+                    "must be greater than zero",
+                ));
+            }
+        }
+        if bundle.heap_limit_bytes == Some(0) {
+            errors.push(FieldError::new(
+                format!("{section}.heap_limit_bytes"),
+                "must be greater than zero",
+            ));
+        }
+        if let Some(pool) = &bundle.pool {
+            if pool.idle_timeout.is_zero() {
+                errors.push(FieldError::new(
+                    format!("{section}.pool.idle_timeout"),
+                    "must be greater than zero",
+                ));
+            }
+            validate_pool(&format!("{section}.pool"), pool, errors);
+        }
+    }
+
+    /// Builds the runtime configuration; call after [`ServerConfig::validate`] succeeds.
+    pub fn runtime_config(&self) -> RuntimeConfig {
+        let runtime = &self.runtime;
+        let console = ConsoleConfig {
+            messages_per_second: runtime.console.messages_per_second,
+            burst: runtime.console.burst,
+            max_arg_bytes: runtime.console.max_arg_bytes,
+        };
+
+        let config = RuntimeConfig::new(runtime.bundle.clone().unwrap_or_default())
+            .with_name(runtime.name.clone())
+            .with_pool(pool_config(&runtime.pool))
+            .with_render_timeout(Some(runtime.render_timeout))
+            .with_cpu_time_slice(Some(runtime.cpu_time_slice))
+            .with_heap_limit_bytes(Some(runtime.heap_limit_bytes))
+            .with_console(console);
+        let config = match &runtime.source_map {
+            Some(path) => config.with_source_map(path),
+            None => config,
+        };
+        match &runtime.snapshot {
+            Some(path) => config.with_snapshot(path),
+            None => config,
+        }
+    }
+
+    /// Builds the runtime configuration of every `[bundles.<id>]` section, inheriting unset
+    /// limits from `[runtime]`; call after [`ServerConfig::validate`] succeeds.
+    pub fn bundle_configs(&self) -> Vec<(String, RuntimeConfig)> {
+        let defaults = self.runtime_config();
+        self.bundles
+            .iter()
+            .map(|(id, bundle)| {
+                let mut config = defaults.clone();
+                config.bundle_path = bundle.bundle.clone().unwrap_or_default();
+                config.source_map_path = bundle.source_map.clone();
+                config.snapshot_path = bundle.snapshot.clone();
+                config.name = bundle.name.clone().unwrap_or_else(|| id.clone());
+                if let Some(pool) = &bundle.pool {
+                    config.pool = pool_config(pool);
+                }
+                if let Some(timeout) = bundle.render_timeout {
+                    config.render_timeout = Some(timeout);
+                }
+                if let Some(slice) = bundle.cpu_time_slice {
+                    config.cpu_time_slice = Some(slice);
+                }
+                if let Some(limit) = bundle.heap_limit_bytes {
+                    config.heap_limit_bytes = Some(limit);
+                }
+                (id.clone(), config)
+            })
+            .collect()
+    }
+}
+
+fn validate_pool(section: &str, pool: &PoolSettings, errors: &mut Vec<FieldError>) {
+    if pool.max_size == 0 {
+        errors.push(FieldError::new(
+            format!("{section}.max_size"),
+            "must be positive",
+        ));
+    } else if pool.min_size > pool.max_size {
+        errors.push(FieldError::new(
+            format!("{section}.min_size"),
+            format!(
+                "{} exceeds {section}.max_size {}",
+                pool.min_size, pool.max_size
+            ),
+        ));
+    }
+    if pool.max_renders_per_engine == Some(0) {
+        errors.push(FieldError::new(
+            format!("{section}.max_renders_per_engine"),
+            "must be positive",
+        ));
+    }
+}
+
+fn pool_config(pool: &PoolSettings) -> PoolConfig {
+    PoolConfig {
+        min_size: pool.min_size,
+        max_size: pool.max_size,
+        idle_timeout: pool.idle_timeout,
+        max_renders_per_engine: pool.max_renders_per_engine,
+    }
+}
+
+/// A config file converted into settings, with the fields that could not be.
+#[derive(Default)]
+struct Parsed {
+    config: ServerConfig,
+    /// One error per unknown or mistyped field; those fields keep their defaults.
+    errors: Vec<FieldError>,
+    /// Entries left out of `config` entirely because they could not be read at all.
+    dropped: Vec<String>,
+}
+
+impl Parsed {
+    fn from_sections(sections: Map<String, Value>) -> Self {
+        let mut parsed = Self::default();
+        for (key, value) in sections {
+            match key.as_str() {
+                "bind" => parsed.section(value, "bind", |config, bind| config.bind = bind),
+                "runtime" => {
+                    parsed.section(value, "runtime", |config, runtime| config.runtime = runtime)
+                }
+                "telemetry" => parsed.section(value, "telemetry", |config, telemetry| {
+                    config.telemetry = telemetry
+                }),
+                "cache" => parsed.section(value, "cache", |config, cache| config.cache = cache),
+                "admin" => parsed.section(value, "admin", |config, admin| config.admin = admin),
+                "bundles" => match value {
+                    Value::Object(bundles) => {
+                        for (id, bundle) in bundles {
+                            let field = format!("bundles.{id}");
+                            parsed.section(bundle, &field, |config, bundle| {
+                                config.bundles.insert(id, bundle);
+                            });
+                        }
+                    }
+                    other => parsed.mistyped("bundles", &other, "a table of bundles"),
+                },
+                "routes" => match value {
+                    Value::Array(routes) => {
+                        for (index, route) in routes.into_iter().enumerate() {
+                            let field = format!("routes[{index}]");
+                            parsed
+                                .section(route, &field, |config, route| config.routes.push(route));
+                        }
+                        // Later routes moved up a place, so their indices no longer match the file.
+                        if parsed
+                            .dropped
+                            .iter()
+                            .any(|field| field.starts_with("routes["))
+                        {
+                            parsed.dropped.push("routes".to_string());
+                        }
+                    }
+                    other => parsed.mistyped("routes", &other, "an array of routes"),
+                },
+                _ => parsed
+                    .errors
+                    .push(FieldError::new(key, "is not a known setting")),
+            }
+        }
+        parsed
+    }
+
+    /// Converts `value` into the setting at `field` and applies it with `apply`. Fields inside it
+    /// that are unknown or mistyped are reported and left at their defaults; when that still
+    /// does not produce a setting, for example because a required field is missing, it is
+    /// dropped.
+    fn section<T: DeserializeOwned>(
+        &mut self,
+        mut value: Value,
+        field: &str,
+        apply: impl FnOnce(&mut ServerConfig, T),
+    ) {
+        let mut reported: Vec<String> = Vec::new();
+        loop {
+            let err = match serde_path_to_error::deserialize::<_, T>(&value) {
+                Ok(setting) => return apply(&mut self.config, setting),
+                Err(err) => err,
+            };
+            let name = field_name(field, err.path());
+            // A required field that was just removed; the original error already covers it.
+            let removed_required = reported.iter().any(|earlier| is_within(earlier, &name));
+            if !removed_required {
+                self.errors
+                    .push(FieldError::new(name.clone(), err.inner().to_string()));
+                reported.push(name);
+            }
+            if removed_required || !remove(&mut value, err.path()) {
+                self.dropped.push(field.to_string());
+                return;
+            }
+        }
+    }
+
+    fn mistyped(&mut self, field: &str, value: &Value, expected: &str) {
+        let found = match value {
+            Value::Null => "nothing",
+            Value::Bool(_) => "a boolean",
+            Value::Number(_) => "a number",
+            Value::String(_) => "a string",
+            Value::Array(_) => "an array",
+            Value::Object(_) => "a table",
+        };
+        self.errors.push(FieldError::new(
+            field,
+            format!("expected {expected}, found {found}"),
+        ));
+    }
+}
+
+/// Names the field at `path` inside the setting `field`, e.g. `runtime.pool.max_size`.
+fn field_name(field: &str, path: &serde_path_to_error::Path) -> String {
+    let mut name = field.to_string();
+    for segment in path {
+        match segment {
+            Segment::Seq { index } => name.push_str(&format!("[{index}]")),
+            Segment::Map { key } => {
+                name.push('.');
+                name.push_str(key);
+            }
+            Segment::Enum { .. } | Segment::Unknown => {}
+        }
+    }
+    name
+}
+
+/// Removes the entry at `path` from `value`, so the field it names takes its default. Returns
+/// `false` when the path does not end in a table key, for example at a missing required field.
+fn remove(value: &mut Value, path: &serde_path_to_error::Path) -> bool {
+    let segments: Vec<&Segment> = path.iter().collect();
+    let Some((Segment::Map { key }, parents)) = segments.split_last() else {
+        return false;
+    };
+    let mut current = value;
+    for segment in parents {
+        current = match (segment, current) {
+            (Segment::Map { key }, Value::Object(map)) => match map.get_mut(key) {
+                Some(child) => child,
+                None => return false,
+            },
+            (Segment::Seq { index }, Value::Array(items)) => match items.get_mut(*index) {
+                Some(child) => child,
+                None => return false,
+            },
+            _ => return false,
+        };
+    }
+    match current {
+        Value::Object(map) => map.remove(key).is_some(),
+        _ => false,
+    }
+}
+
+/// Whether the field `name` is `field` or one of the fields inside it.
+fn is_within(name: &str, field: &str) -> bool {
+    name.strip_prefix(field)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.') || rest.starts_with('['))
+}
+
+fn parse<T>(value: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|err| format!("'{value}' is invalid: {err}"))
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    humantime_serde::re::humantime::parse_duration(value.trim())
+        .map_err(|err| format!("'{value}' is not a duration: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use common::RenderMode;
+    use tempfile::{Builder, NamedTempFile};
+
+    use super::*;
+    use crate::telemetry::LogFormat;
+
+    fn config_file(suffix: &str, contents: &str) -> NamedTempFile {
+        let mut file = Builder::new().suffix(suffix).tempfile().expect("tmp file");
+        file.write_all(contents.as_bytes()).expect("write config");
+        file
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn toml_files_are_loaded() {
+        let bundle = NamedTempFile::new().expect("bundle");
+        let file = config_file(
+            ".toml",
+            &format!(
+                r#"
+bind = "127.0.0.1:8080"
+
+[runtime]
+bundle = "{}"
+render_timeout = "3s"
+
+[runtime.pool]
+max_size = 8
+idle_timeout = "1m"
+
+[telemetry]
+log_format = "json"
+
+[[routes]]
+id = "product"
+pattern = "/products/:id"
+render_mode = "Streaming"
+"#,
+                bundle.path().display()
+            ),
+        );
+
+        let config = ServerConfig::load(Some(file.path()), Vec::new(), ConfigOverrides::default())
+            .expect("config");
+
+        assert_eq!(config.bind, "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(config.runtime.render_timeout, Duration::from_secs(3));
+        assert_eq!(config.runtime.pool.max_size, 8);
+        assert_eq!(config.runtime.pool.idle_timeout, Duration::from_secs(60));
+        assert_eq!(config.telemetry.log_format, LogFormat::Json);
+        assert_eq!(config.routes[0].render_mode, RenderMode::Streaming);
+    }
+
+    #[test]
+    fn yaml_files_are_loaded_and_env_wins() {
+        let bundle = NamedTempFile::new().expect("bundle");
+        let file = config_file(
+            ".yaml",
+            &format!(
+                "runtime:\n  bundle: {}\n  name: from-file\nroutes:\n  - id: home\n    pattern: /\n",
+                bundle.path().display()
+            ),
+        );
+
+        let config = ServerConfig::load(
+            Some(file.path()),
+            env(&[
+                ("RSENGINE_RUNTIME_NAME", "from-env"),
+                ("RSENGINE_POOL_MAX_SIZE", "2"),
+                ("RSENGINE_CONSOLE_MESSAGES_PER_SECOND", "0"),
+                ("RSENGINE_WATCH", "true"),
+                ("RSENGINE_EXPOSE_RENDER_ERRORS", "true"),
+                ("PORT", "4000"),
+            ]),
+            ConfigOverrides::default(),
+        )
+        .expect("config");
+
+        assert_eq!(config.runtime.name, "from-env");
+        assert_eq!(config.runtime.pool.max_size, 2);
+        assert_eq!(config.runtime.console.messages_per_second, 0);
+        assert!(config.runtime.watch);
+        assert!(config.runtime.expose_render_errors);
+        assert_eq!(config.bind.port(), 4000);
+        assert_eq!(config.routes[0].id, "home");
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let file = config_file(
+            ".toml",
+            r#"
+[runtime.pool]
+min_size = 5
+max_size = 2
+
+[[routes]]
+id = "home"
+pattern = "home"
+
+[[routes]]
+id = "home"
+pattern = "/:"
+
+[[routes.data]]
+provider = "product"
+timeout_ms = 0
+
+[[routes.data]]
+provider = "product"
+"#,
+        );
+
+        let err = ServerConfig::load(
+            Some(file.path()),
+            env(&[
+                ("RSENGINE_RENDER_TIMEOUT", "soon"),
+                ("RSENGINE_SNAPSHOT", "/nonexistent/bundle.snapshot"),
+            ]),
+            ConfigOverrides::default(),
+        )
+        .expect_err("config should be rejected");
+        let ConfigError::Invalid(errors) = &err else {
+            panic!("unexpected error: {err}");
+        };
+
+        let fields: Vec<&str> = errors.iter().map(|error| error.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "RSENGINE_RENDER_TIMEOUT",
+                "runtime.bundle",
+                "runtime.snapshot",
+                "runtime.pool.min_size",
+                "routes[0].pattern",
+                "routes[1].id",
+                "routes[1].pattern",
+                "routes[1].data[0].timeout_ms",
+                "routes[1].data[1].key",
+            ]
+        );
+        assert!(err
+            .to_string()
+            .contains("\n  - runtime.bundle: is required"));
+    }
+
+    #[test]
+    fn redis_backend_requires_a_url() {
+        let mut config = ServerConfig::default();
+        let errors = config.apply_env(env(&[("RSENGINE_CACHE_BACKEND", "Redis")]));
+        assert!(errors.is_empty());
+        assert_eq!(config.cache.backend, CacheBackend::Redis);
+        assert!(config
+            .validate()
+            .iter()
+            .any(|error| error.field == "cache.redis.url"));
+
+        let errors = config.apply_env(env(&[
+            ("RSENGINE_CACHE_REDIS_URL", "redis://cache:6379/1"),
+            ("RSENGINE_CACHE_REDIS_TIMEOUT", "100ms"),
+            ("RSENGINE_CACHE_BACKEND", "memcached"),
+        ]));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "RSENGINE_CACHE_BACKEND");
+        assert_eq!(config.cache.redis.timeout, Duration::from_millis(100));
+        assert!(config
+            .validate()
+            .iter()
+            .all(|error| !error.field.starts_with("cache.")));
+    }
+
+    #[test]
+    fn metrics_listener_must_not_share_the_main_address() {
+        let mut config = ServerConfig::default();
+        let errors = config.apply_env(env(&[("RSENGINE_METRICS_BIND", "0.0.0.0:3000")]));
+        assert!(errors.is_empty());
+
+        let fields: Vec<String> = config.validate().into_iter().map(|e| e.field).collect();
+        assert!(fields.contains(&"telemetry.metrics_bind".to_string()));
+
+        config.apply_env(env(&[("RSENGINE_METRICS_BIND", "127.0.0.1:9100")]));
+        let fields: Vec<String> = config.validate().into_iter().map(|e| e.field).collect();
+        assert!(!fields.contains(&"telemetry.metrics_bind".to_string()));
+    }
+
+    #[test]
+    fn admin_listener_requires_a_token() {
+        let mut config = ServerConfig::default();
+        config.apply_env(env(&[("RSENGINE_ADMIN_BIND", "127.0.0.1:9200")]));
+        let fields: Vec<String> = config.validate().into_iter().map(|e| e.field).collect();
+        assert!(fields.contains(&"admin.token".to_string()));
+
+        config.apply_env(env(&[
+            ("RSENGINE_ADMIN_TOKEN", "secret"),
+            ("RSENGINE_ADMIN_BIND", "0.0.0.0:3000"),
+        ]));
+        let fields: Vec<String> = config.validate().into_iter().map(|e| e.field).collect();
+        assert!(!fields.contains(&"admin.token".to_string()));
+        assert!(fields.contains(&"admin.bind".to_string()));
+    }
+
+    #[test]
+    fn extra_bundles_inherit_runtime_limits() {
+        let bundle = NamedTempFile::new().expect("bundle");
+        let path = bundle.path().display();
+        let file = config_file(
+            ".toml",
+            &format!(
+                r#"
+[runtime]
+bundle = "{path}"
+render_timeout = "3s"
+
+[bundles.checkout]
+bundle = "{path}"
+heap_limit_bytes = 1024
+
+[bundles.checkout.pool]
+max_size = 2
+
+[[routes]]
+id = "checkout"
+pattern = "/checkout"
+bundle = "checkout"
+"#
+            ),
+        );
+        let config = ServerConfig::load(Some(file.path()), env(&[]), ConfigOverrides::default())
+            .expect("config");
+
+        let bundles = config.bundle_configs();
+        let [(id, checkout)] = bundles.as_slice() else {
+            panic!("unexpected bundles: {bundles:?}");
+        };
+        assert_eq!(id, "checkout");
+        assert_eq!(checkout.name, "checkout");
+        assert_eq!(checkout.render_timeout, Some(Duration::from_secs(3)));
+        assert_eq!(checkout.heap_limit_bytes, Some(1024));
+        assert_eq!(checkout.pool.max_size, 2);
+
+        let mut config = config;
+        config.routes[0].bundle = Some("docs".to_string());
+        config
+            .bundles
+            .insert(DEFAULT_BUNDLE.to_string(), BundleSettings::default());
+        let fields: Vec<String> = config.validate().into_iter().map(|e| e.field).collect();
+        assert_eq!(
+            fields,
+            [
+                "bundles.default",
+                "bundles.default.bundle",
+                "routes[0].bundle"
+            ]
+        );
+    }
+
+    #[test]
+    fn example_config_parses() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../config/rsengine.example.toml");
+        let config = ServerConfig::from_file(&path).expect("example config");
+
+        assert_eq!(config.routes.len(), 2);
+        assert!(config
+            .validate()
+            .iter()
+            .all(|error| error.field == "runtime.bundle"));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let file = config_file(
+            ".toml",
+            r#"
+[runtime]
+bundel = "app.js"
+
+[[routes]]
+id = "home"
+pattern = "/"
+cache_ttl_second = 60
+render_mod = "streaming"
+
+[[routes.data]]
+provider = "catalog"
+timeot_ms = 5
+"#,
+        );
+
+        let err = ServerConfig::from_file(file.path()).expect_err("typos should be rejected");
+        let ConfigError::Invalid(errors) = &err else {
+            panic!("unexpected error: {err}");
+        };
+        let reported: Vec<(&str, &str)> = errors
+            .iter()
+            .map(|error| (error.field.as_str(), error.message.as_str()))
+            .collect();
+        for (field, key) in [
+            ("routes[0].cache_ttl_second", "cache_ttl_second"),
+            ("routes[0].render_mod", "render_mod"),
+            ("routes[0].data[0].timeot_ms", "timeot_ms"),
+            ("runtime.bundel", "bundel"),
+        ] {
+            assert!(
+                reported.iter().any(|(name, message)| *name == field
+                    && message.contains(&format!("unknown field `{key}`"))),
+                "{field} not reported: {err}"
+            );
+        }
+        assert_eq!(errors.len(), 4, "{err}");
+    }
+
+    #[test]
+    fn every_mistyped_field_is_reported() {
+        let file = config_file(
+            ".toml",
+            r#"
+bind = 3000
+
+[runtime]
+bundle = "app.js"
+render_mode = "fast"
+
+[runtime.pool]
+max_size = "ten"
+
+[[routes]]
+id = "home"
+pattern = "/"
+render_mode = "eventually"
+
+[[routes]]
+pattern = "/about"
+"#,
+        );
+
+        let err = ServerConfig::load(Some(file.path()), env(&[]), ConfigOverrides::default())
+            .expect_err("config should be rejected");
+        let ConfigError::Invalid(errors) = &err else {
+            panic!("unexpected error: {err}");
+        };
+
+        let fields: Vec<&str> = errors.iter().map(|error| error.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "bind",
+                "routes[0].render_mode",
+                "routes[1]",
+                "runtime.pool.max_size",
+                "runtime.render_mode",
+                // Checks on the settings that did parse still run.
+                "runtime.bundle",
+            ],
+            "{err}"
+        );
+        assert!(err
+            .to_string()
+            .contains("runtime.pool.max_size: invalid type: string \"ten\""));
+    }
+}
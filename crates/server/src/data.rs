@@ -0,0 +1,215 @@
+//! Fetches the data a route declares before it renders.
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use common::{AppError, DataProvider, ErrorCode, RequestContext, RouteData};
+use futures_util::future::try_join_all;
+use metrics::histogram;
+use serde_json::Value;
+use tokio::time;
+use tracing::{field, info_span, warn, Instrument};
+
+/// Longest a fetch may take when its route does not set `timeout_ms`.
+pub const DEFAULT_DATA_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Data providers by the name routes refer to them with.
+#[derive(Clone, Default)]
+pub struct DataProviders {
+    providers: BTreeMap<String, Arc<dyn DataProvider>>,
+}
+
+impl DataProviders {
+    /// Makes `provider` available to routes as `name`, replacing any provider of that name.
+    pub fn register(&mut self, name: impl Into<String>, provider: Arc<dyn DataProvider>) {
+        self.providers.insert(name.into(), provider);
+    }
+
+    /// Whether a provider is registered as `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.providers.contains_key(name)
+    }
+
+    /// Runs every fetch in `sources` concurrently, each under its own timeout, and returns the
+    /// results by key. The first required fetch to fail fails the whole call and cancels the
+    /// others; optional fetches that fail resolve to `null`. Dropping the returned future, as
+    /// happens when the client goes away, cancels every fetch still running.
+    pub async fn fetch(
+        &self,
+        sources: &[RouteData],
+        request: &RequestContext,
+    ) -> Result<BTreeMap<String, Value>, AppError> {
+        if sources.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+        let fetches = sources.iter().map(|source| self.fetch_one(source, request));
+        Ok(try_join_all(fetches).await?.into_iter().collect())
+    }
+
+    async fn fetch_one(
+        &self,
+        source: &RouteData,
+        request: &RequestContext,
+    ) -> Result<(String, Value), AppError> {
+        let key = source.key().to_string();
+        let limit = source
+            .timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_DATA_TIMEOUT);
+
+        let span = info_span!(
+            "data_fetch",
+            provider = %source.provider,
+            key = %key,
+            outcome = field::Empty
+        );
+        let started = Instant::now();
+        let result = match self.providers.get(&source.provider) {
+            Some(provider) => time::timeout(limit, provider.fetch(request))
+                .instrument(span.clone())
+                .await
+                .unwrap_or_else(|_| {
+                    Err(AppError::new(
+                        ErrorCode::UpstreamFailure,
+                        format!("timed out after {}ms", limit.as_millis()),
+                    ))
+                }),
+            None => Err(AppError::new(
+                ErrorCode::Internal,
+                "no provider is registered under that name",
+            )),
+        }
+        .map_err(|err| {
+            let message = format!(
+                "data provider '{}' failed: {}",
+                source.provider,
+                err.message()
+            );
+            err.with_message(message)
+        });
+
+        let outcome = match &result {
+            Ok(_) => "ok",
+            Err(err) => err.code().as_str(),
+        };
+        span.record("outcome", outcome);
+        histogram!(
+            "data_fetch_duration_seconds",
+            started.elapsed().as_secs_f64(),
+            "provider" => source.provider.clone(),
+            "outcome" => outcome,
+        );
+
+        match result {
+            Ok(value) => Ok((key, value)),
+            Err(err) if source.optional => {
+                warn!(
+                    parent: &span,
+                    request_id = %request.trace.request_id,
+                    error = %err,
+                    "optional data fetch failed; rendering without it"
+                );
+                Ok((key, Value::Null))
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use async_trait::async_trait;
+    use http::{HeaderMap, Method};
+    use serde_json::json;
+
+    use super::*;
+
+    struct Fixed(Value);
+
+    #[async_trait]
+    impl DataProvider for Fixed {
+        async fn fetch(&self, _request: &RequestContext) -> Result<Value, AppError> {
+            Ok(self.0.clone())
+        }
+    }
+
+    /// Never answers, and records whether its fetch was dropped before completing.
+    #[derive(Default)]
+    struct Hanging(Arc<AtomicBool>);
+
+    struct SetOnDrop(Arc<AtomicBool>);
+
+    impl Drop for SetOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait]
+    impl DataProvider for Hanging {
+        async fn fetch(&self, _request: &RequestContext) -> Result<Value, AppError> {
+            let _cancelled = SetOnDrop(Arc::clone(&self.0));
+            std::future::pending().await
+        }
+    }
+
+    fn request() -> RequestContext {
+        RequestContext::from_http_parts(&Method::GET, "/", &HeaderMap::new())
+    }
+
+    #[tokio::test]
+    async fn results_are_keyed_and_optional_failures_become_null() {
+        let mut providers = DataProviders::default();
+        providers.register("product", Arc::new(Fixed(json!({ "id": 7 }))));
+        providers.register("reviews", Arc::new(Hanging::default()));
+        let sources = [
+            RouteData::new("product").with_key("item"),
+            RouteData::new("reviews").with_timeout_ms(10).optional(),
+        ];
+
+        let data = providers.fetch(&sources, &request()).await.expect("data");
+        assert_eq!(data["item"], json!({ "id": 7 }));
+        assert_eq!(data["reviews"], Value::Null);
+
+        let unregistered = [RouteData::new("ratings").optional()];
+        let data = providers
+            .fetch(&unregistered, &request())
+            .await
+            .expect("data");
+        assert_eq!(data["ratings"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn a_required_failure_cancels_the_other_fetches() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut providers = DataProviders::default();
+        providers.register("slow", Arc::new(Hanging(Arc::clone(&cancelled))));
+        let sources = [
+            RouteData::new("slow").with_timeout_ms(60_000),
+            RouteData::new("missing"),
+        ];
+
+        let err = providers
+            .fetch(&sources, &request())
+            .await
+            .expect_err("unknown provider");
+        assert!(err.message().contains("'missing'"), "{err}");
+        assert!(matches!(err.code(), ErrorCode::Internal));
+        assert!(cancelled.load(Ordering::SeqCst));
+
+        let timed_out = providers
+            .fetch(&[RouteData::new("slow").with_timeout_ms(10)], &request())
+            .await
+            .expect_err("timeout");
+        assert!(matches!(timed_out.code(), ErrorCode::UpstreamFailure));
+        assert!(
+            timed_out.message().contains("timed out after 10ms"),
+            "{timed_out}"
+        );
+    }
+}
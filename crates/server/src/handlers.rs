@@ -1,6 +1,7 @@
 use std::{
     convert::Infallible,
-    time::{SystemTime, UNIX_EPOCH},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use async_trait::async_trait;
@@ -8,53 +9,400 @@ use axum::{
     body::Body,
     http::{
         header::{self, HeaderValue},
-        StatusCode,
+        HeaderMap, StatusCode,
     },
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     Extension,
 };
 use bytes::Bytes;
-use common::{AppError, ErrorCode};
+use common::{AppError, ErrorCode, RenderMode, RequestContext, RouteData};
 use html_escape::encode_text;
-use runtime::ResponseWriter;
+use metrics::{histogram, increment_counter};
+use runtime::{RenderRuntime, ResponseWriter};
 use tokio::sync::mpsc;
 use tokio_stream::{wrappers::ReceiverStream, StreamExt};
-use tracing::{debug, error, warn};
+use tracing::{debug, error, field, info_span, warn, Instrument, Span};
+use uuid::Uuid;
 
-use crate::{app::AppState, context::RequestContextExtractor, errors::HandlerResult};
+use crate::{
+    app::AppState,
+    cache::{self, Lookup, RenderCache},
+    coalesce::{Claim, Flight},
+    context::RequestContextExtractor,
+    data::DataProviders,
+    errors::HandlerResult,
+    telemetry,
+};
+
+/// Response header reporting whether a cacheable route was served from the cache.
+pub const CACHE_STATUS_HEADER: &str = "x-cache";
 
-pub async fn stream(
+/// Renders the route matching the request path in the mode it is configured for, subject to
+/// per-request overrides.
+pub async fn render(
     Extension(state): Extension<AppState>,
-    RequestContextExtractor(context): RequestContextExtractor,
-) -> HandlerResult<impl IntoResponse> {
-    debug!(request_id = %context.trace.request_id, "stream request received");
+    headers: HeaderMap,
+    RequestContextExtractor(mut context): RequestContextExtractor,
+) -> HandlerResult<Response> {
+    let started = Instant::now();
+    // With an exporter installed, the bundle's `traceparent` points at the exported request span.
+    let request_span = Span::current();
+    if let Some((trace_id, span_id, flags)) = telemetry::exported_span(&request_span) {
+        context.trace = context.trace.with_span(trace_id, span_id, flags);
+    }
+    request_span.record("trace_id", field::display(context.trace.trace_id));
+    let routes = state.routes();
+    let (route, matched) = routes.resolve(&context.path).ok_or_else(|| {
+        AppError::new(
+            ErrorCode::NotFound,
+            format!("no route matches '{}'", context.path),
+        )
+    })?;
+    request_span.record("route", matched.id.as_str());
+    let runtime = state.route_runtime(route)?;
+    request_span.record("runtime", runtime.name());
+    let mode = route.render_mode.for_request(&headers);
+    debug!(
+        request_id = %context.trace.request_id,
+        route = %matched.id,
+        mode = ?mode,
+        "render request received"
+    );
+
+    let first_byte = FirstByteTimer {
+        started,
+        route: matched.id.clone(),
+        runtime: runtime.name().to_string(),
+    };
+    let context = context.with_route(matched);
+
+    let cache_slot = match route.cache_ttl_seconds.filter(|ttl| *ttl > 0) {
+        Some(ttl) => {
+            let slot = CacheSlot {
+                cache: state.cache(),
+                key: cache::cache_key(route, &context),
+                ttl: Duration::from_secs(ttl),
+                stale_for: Duration::from_secs(route.cache_stale_seconds.unwrap_or_default()),
+                flight: None,
+            };
+            let lookup_span = info_span!("cache_lookup", route = %route.id, status = field::Empty);
+            let lookup = slot
+                .cache
+                .get(&slot.key)
+                .instrument(lookup_span.clone())
+                .await
+                .unwrap_or_else(|err| {
+                    // A cache outage should cost a render, not the request.
+                    warn!(route = %route.id, error = %err, "render cache lookup failed");
+                    increment_counter!("render_cache_errors_total", "operation" => "get");
+                    Lookup::Miss
+                });
+            lookup_span.record("status", lookup.status());
+            match lookup {
+                Lookup::Fresh(body) => {
+                    increment_counter!("render_cache_hits_total", "route" => route.id.clone());
+                    return Ok(document(body, Some(CACHE_HIT)));
+                }
+                Lookup::Stale(body) => {
+                    increment_counter!("render_cache_stale_total", "route" => route.id.clone());
+                    // Only the first stale hit refreshes the entry; the rest keep serving it.
+                    if let Claim::Leader(flight) = state.flights().claim(&slot.key) {
+                        let data = (state.data_providers(), route.data.clone());
+                        revalidate(
+                            Arc::clone(&runtime),
+                            data,
+                            context,
+                            slot.with_flight(flight),
+                        );
+                    }
+                    return Ok(document(body, Some(CACHE_STALE)));
+                }
+                Lookup::Miss => {
+                    increment_counter!("render_cache_misses_total", "route" => route.id.clone());
+                    match state.flights().claim(&slot.key) {
+                        Claim::Leader(flight) => Some(slot.with_flight(flight)),
+                        Claim::Follower(waiter) => match waiter.wait().await {
+                            Some(outcome) => {
+                                increment_counter!(
+                                    "render_cache_coalesced_total",
+                                    "route" => route.id.clone()
+                                );
+                                return Ok(document(outcome?, Some(CACHE_COALESCED)));
+                            }
+                            // The render we waited on was abandoned; render on our own.
+                            None => Some(slot),
+                        },
+                    }
+                }
+            }
+        }
+        None => None,
+    };
+
+    let expose_errors = state.exposes_render_errors();
+    // Dropped with the request, so a client that goes away cancels the fetches.
+    let context = match state.data_providers().fetch(&route.data, &context).await {
+        Ok(data) => context.with_data(data),
+        Err(err) => {
+            let request_id = context.trace.request_id;
+            error!(request_id = %request_id, error = %err, "route data fetch failed");
+            let err = client_error(&err, &request_id, expose_errors);
+            if let Some(slot) = cache_slot {
+                slot.fail(&err);
+            }
+            return Err(err.into());
+        }
+    };
+
+    match mode {
+        RenderMode::Blocking => {
+            let span = render_span(&context, "blocking");
+            blocking(runtime, context, cache_slot, first_byte, expose_errors)
+                .instrument(span)
+                .await
+        }
+        RenderMode::Streaming => Ok(streaming(
+            runtime,
+            context,
+            cache_slot,
+            first_byte,
+            expose_errors,
+        )),
+    }
+}
+
+const CACHE_HIT: HeaderValue = HeaderValue::from_static("HIT");
+const CACHE_STALE: HeaderValue = HeaderValue::from_static("STALE");
+const CACHE_COALESCED: HeaderValue = HeaderValue::from_static("COALESCED");
+const CACHE_MISS: HeaderValue = HeaderValue::from_static("MISS");
+
+/// Where a completed render of a cacheable route is stored, and who is waiting for it.
+struct CacheSlot {
+    cache: Arc<dyn RenderCache>,
+    key: String,
+    ttl: Duration,
+    stale_for: Duration,
+    flight: Option<Flight>,
+}
+
+impl CacheSlot {
+    fn with_flight(mut self, flight: Flight) -> Self {
+        self.flight = Some(flight);
+        self
+    }
+
+    /// Hands `body` to waiting requests, then stores it in the background so a slow cache
+    /// backend delays neither them nor the response. Storage failures are only logged.
+    fn store(self, body: Bytes) {
+        if let Some(flight) = self.flight {
+            flight.complete(body.clone());
+        }
+        let (cache, key) = (self.cache, self.key);
+        let (ttl, stale_for) = (self.ttl, self.stale_for);
+        tokio::spawn(
+            async move {
+                if let Err(err) = cache.set(&key, body, ttl, stale_for).await {
+                    warn!(key = %key, error = %err, "failed to store render in cache");
+                    increment_counter!("render_cache_errors_total", "operation" => "set");
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    fn fail(self, error: &AppError) {
+        if let Some(flight) = self.flight {
+            flight.fail(error);
+        }
+    }
+}
+
+/// Records `render_time_to_first_byte_seconds`: from the request reaching the handler until the
+/// first bytes of a rendered document are handed to the HTTP body.
+struct FirstByteTimer {
+    started: Instant,
+    route: String,
+    runtime: String,
+}
+
+impl FirstByteTimer {
+    fn record(self) {
+        histogram!(
+            "render_time_to_first_byte_seconds",
+            self.started.elapsed().as_secs_f64(),
+            "route" => self.route,
+            "runtime" => self.runtime,
+        );
+    }
+}
+
+/// What a client is told about a failed render or data fetch. The error itself, which may carry
+/// the bundle's stack or an upstream's message, is only passed on when the server exposes render
+/// errors; otherwise the client gets its code and the request id to find it in the logs by.
+fn client_error(err: &AppError, request_id: &Uuid, expose: bool) -> AppError {
+    let message = if expose {
+        err.message().to_string()
+    } else {
+        format!("render failed; see the server logs for request {request_id}")
+    };
+    AppError::new(err.code(), message)
+}
+
+/// Span covering a render stage, nested under the request span that started it.
+fn render_span(context: &RequestContext, mode: &'static str) -> Span {
+    let route = context.route.as_ref().map(|route| route.id.as_str());
+    info_span!("render", route = route.unwrap_or_default(), mode)
+}
+
+/// Re-renders a stale entry in the background, fetching the route's data afresh, and replaces
+/// it on success.
+fn revalidate(
+    runtime: Arc<RenderRuntime>,
+    (providers, sources): (Arc<DataProviders>, Vec<RouteData>),
+    context: RequestContext,
+    slot: CacheSlot,
+) {
+    let span = render_span(&context, "revalidate");
+    tokio::spawn(
+        async move {
+            let mut writer = BufferedWriter::default();
+            let rendered = match providers.fetch(&sources, &context).await {
+                Ok(data) => {
+                    let context = context.clone().with_data(data);
+                    runtime.stream_response(&context, &mut writer).await
+                }
+                Err(err) => Err(err),
+            };
+            match rendered {
+                Ok(()) => slot.store(Bytes::from(writer.body)),
+                Err(err) => {
+                    warn!(
+                        request_id = %context.trace.request_id,
+                        error = %err,
+                        "background revalidation failed; keeping the stale render"
+                    );
+                    slot.fail(&err);
+                }
+            }
+        }
+        .instrument(span),
+    );
+}
 
-    let runtime = state.runtime();
+/// A complete HTML document, optionally tagged with its `x-cache` status.
+fn document(body: Bytes, cache_status: Option<HeaderValue>) -> Response {
+    let mut response = (
+        StatusCode::OK,
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            ),
+            (header::CONTENT_LENGTH, HeaderValue::from(body.len())),
+        ],
+        body,
+    )
+        .into_response();
+    if let Some(status) = cache_status {
+        response.headers_mut().insert(CACHE_STATUS_HEADER, status);
+    }
+    response
+}
+
+/// Buffers the whole render so failures can still be reported with an error status.
+async fn blocking(
+    runtime: Arc<RenderRuntime>,
+    context: RequestContext,
+    cache_slot: Option<CacheSlot>,
+    first_byte: FirstByteTimer,
+    expose_errors: bool,
+) -> HandlerResult<Response> {
+    let mut writer = BufferedWriter::default();
+    if let Err(err) = runtime.stream_response(&context, &mut writer).await {
+        error!(request_id = %context.trace.request_id, error = %err, "render runtime failed");
+        let err = client_error(&err, &context.trace.request_id, expose_errors);
+        if let Some(slot) = cache_slot {
+            slot.fail(&err);
+        }
+        return Err(err.into());
+    }
+
+    first_byte.record();
+    let body = Bytes::from(writer.body);
+    let cache_status = match cache_slot {
+        Some(slot) => {
+            slot.store(body.clone());
+            Some(CACHE_MISS)
+        }
+        None => None,
+    };
+    Ok(document(body, cache_status))
+}
+
+/// Streams chunks as the bundle produces them; failures after the first byte are rendered inline.
+fn streaming(
+    runtime: Arc<RenderRuntime>,
+    context: RequestContext,
+    cache_slot: Option<CacheSlot>,
+    first_byte: FirstByteTimer,
+    expose_errors: bool,
+) -> Response {
     let (sender, receiver) = mpsc::channel::<Bytes>(16);
-    let headers = [(
+    let mut headers = HeaderMap::new();
+    headers.insert(
         header::CONTENT_TYPE,
         HeaderValue::from_static("text/html; charset=utf-8"),
-    )];
+    );
+    if cache_slot.is_some() {
+        headers.insert(CACHE_STATUS_HEADER, CACHE_MISS);
+    }
 
     let request_id = context.trace.request_id;
-    tokio::spawn(async move {
-        let mut writer = ChannelStreamWriter::new(sender);
-        if let Err(err) = runtime.stream_response(&context, &mut writer).await {
-            error!(request_id = %request_id, error = %err, "render runtime failed");
-            if let Err(send_err) = writer.write_error(&err).await {
-                warn!(
-                    request_id = %request_id,
-                    error = %send_err,
-                    "failed to stream error chunk"
-                );
+    // The request span stays open until the body is finished, so the render nests under it.
+    let span = render_span(&context, "streaming");
+    tokio::spawn(
+        async move {
+            let mut writer = ChannelStreamWriter::new(sender);
+            writer.first_byte = Some(first_byte);
+            if let Some(slot) = &cache_slot {
+                writer.capture_up_to(slot.cache.max_bytes());
+            }
+            let rendered = runtime.stream_response(&context, &mut writer).await;
+            match (&rendered, cache_slot) {
+                (Ok(()), Some(slot)) => {
+                    // Oversized documents are not cached; waiting requests then render their own.
+                    if let Some(body) = writer.take_captured() {
+                        slot.store(Bytes::from(body));
+                    }
+                }
+                (Err(err), Some(slot)) if !matches!(err.code(), ErrorCode::ClientClosed) => {
+                    slot.fail(&client_error(err, &request_id, expose_errors));
+                }
+                _ => {}
+            }
+            if let Err(err) = rendered {
+                if matches!(err.code(), ErrorCode::ClientClosed) {
+                    debug!(request_id = %request_id, "client went away mid-render");
+                    return;
+                }
+                error!(request_id = %request_id, error = %err, "render runtime failed");
+                let err = client_error(&err, &request_id, expose_errors);
+                if let Err(send_err) = writer.write_error(&err).await {
+                    warn!(
+                        request_id = %request_id,
+                        error = %send_err,
+                        "failed to stream error chunk"
+                    );
+                }
             }
         }
-    });
+        .instrument(span),
+    );
 
-    let stream = ReceiverStream::new(receiver).map(|chunk| Ok::<Bytes, Infallible>(chunk));
+    let stream = ReceiverStream::new(receiver).map(Ok::<Bytes, Infallible>);
     let body = Body::from_stream(stream);
 
-    Ok((StatusCode::OK, headers, body))
+    (StatusCode::OK, headers, body).into_response()
 }
 
 pub fn register_process_metrics() {
@@ -67,22 +415,73 @@ pub fn register_process_metrics() {
     }
 }
 
+/// Collects the complete document for blocking renders.
+#[derive(Default)]
+struct BufferedWriter {
+    body: String,
+}
+
+#[async_trait]
+impl ResponseWriter for BufferedWriter {
+    async fn write(&mut self, chunk: String) -> Result<(), AppError> {
+        self.body.push_str(&chunk);
+        Ok(())
+    }
+}
+
+/// Chunks are coalesced until the runtime flushes or this many bytes are pending.
+const FLUSH_THRESHOLD: usize = 16 * 1024;
+
 struct ChannelStreamWriter {
-    sender: mpsc::Sender<Bytes>,
+    sender: Option<mpsc::Sender<Bytes>>,
+    buffer: String,
+    /// Copy of everything written so far, kept while the render may still be cached.
+    captured: Option<String>,
+    capture_limit: usize,
+    /// Recorded when the first chunk reaches the response body.
+    first_byte: Option<FirstByteTimer>,
 }
 
 impl ChannelStreamWriter {
     fn new(sender: mpsc::Sender<Bytes>) -> Self {
-        Self { sender }
+        Self {
+            sender: Some(sender),
+            buffer: String::new(),
+            captured: None,
+            capture_limit: 0,
+            first_byte: None,
+        }
     }
 
-    async fn send(&mut self, chunk: String) -> Result<(), AppError> {
-        self.sender.send(Bytes::from(chunk)).await.map_err(|_| {
+    /// Keeps a copy of the document for the cache, giving up once it exceeds `limit` bytes.
+    fn capture_up_to(&mut self, limit: usize) {
+        self.captured = Some(String::new());
+        self.capture_limit = limit;
+    }
+
+    fn take_captured(&mut self) -> Option<String> {
+        self.captured.take()
+    }
+
+    async fn send_buffer(&mut self) -> Result<(), AppError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let sender = self.sender.as_ref().ok_or_else(|| {
+            AppError::new(ErrorCode::Internal, "response stream was already closed")
+        })?;
+        let chunk = Bytes::from(std::mem::take(&mut self.buffer));
+        sender.send(chunk).await.map_err(|_| {
             AppError::new(
                 ErrorCode::Internal,
                 "response stream closed before chunk could be delivered",
             )
-        })
+        })?;
+        if let Some(first_byte) = self.first_byte.take() {
+            first_byte.record();
+        }
+        Ok(())
     }
 
     async fn write_error(&mut self, error: &AppError) -> Result<(), AppError> {
@@ -90,13 +489,51 @@ impl ChannelStreamWriter {
             "<section><h2>Render Error</h2><pre>{}</pre></section>",
             encode_text(error.message())
         );
-        self.send(chunk).await
+        self.write(chunk).await?;
+        self.close().await
     }
 }
 
 #[async_trait]
 impl ResponseWriter for ChannelStreamWriter {
     async fn write(&mut self, chunk: String) -> Result<(), AppError> {
-        self.send(chunk).await
+        if self.sender.is_none() {
+            return Err(AppError::new(
+                ErrorCode::Internal,
+                "response stream was already closed",
+            ));
+        }
+
+        if let Some(captured) = &mut self.captured {
+            captured.push_str(&chunk);
+            if captured.len() > self.capture_limit {
+                self.captured = None;
+            }
+        }
+
+        self.buffer.push_str(&chunk);
+        if self.buffer.len() >= FLUSH_THRESHOLD {
+            self.send_buffer().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), AppError> {
+        self.send_buffer().await
+    }
+
+    async fn close(&mut self) -> Result<(), AppError> {
+        let flushed = self.send_buffer().await;
+        // Dropping the sender ends the response body.
+        self.sender = None;
+        flushed
+    }
+
+    async fn disconnected(&self) {
+        match &self.sender {
+            // Hyper drops the body stream, and with it the receiver, when the client goes away.
+            Some(sender) => sender.closed().await,
+            None => std::future::pending().await,
+        }
     }
 }
@@ -0,0 +1,141 @@
+use std::{collections::BTreeMap, path::PathBuf, sync::Mutex};
+
+use axum::{http::StatusCode, Extension, Json};
+use runtime::{registry::DEFAULT_BUNDLE, PoolStats, RenderRuntime};
+use serde::Serialize;
+
+use crate::app::AppState;
+
+/// Conditions tracked outside the runtime that keep the server from taking traffic.
+#[derive(Default)]
+pub struct HealthState {
+    /// Most recent reload failure per bundle id.
+    reload_failures: Mutex<BTreeMap<String, String>>,
+}
+
+impl HealthState {
+    /// Marks the server unready until a later reload of `bundle` succeeds.
+    pub fn record_reload_failure(&self, bundle: &str, message: impl Into<String>) {
+        self.lock().insert(bundle.to_string(), message.into());
+    }
+
+    /// Clears a previously recorded reload failure of `bundle`.
+    pub fn clear_reload_failure(&self, bundle: &str) {
+        self.lock().remove(bundle);
+    }
+
+    /// The most recent reload failure of `bundle` that has not been cleared.
+    pub fn reload_failure(&self, bundle: &str) -> Option<String> {
+        self.lock().get(bundle).cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, String>> {
+        self.reload_failures
+            .lock()
+            .expect("health state mutex poisoned")
+    }
+}
+
+#[derive(Serialize)]
+pub struct Liveness {
+    status: &'static str,
+}
+
+/// Reports that the process is up and serving HTTP; it does not consult the runtime.
+pub async fn healthz() -> Json<Liveness> {
+    Json(Liveness { status: "ok" })
+}
+
+/// Detail returned by `/readyz`, one entry per readiness check. The default bundle is reported
+/// at the top level and any other bundles under `bundles`.
+#[derive(Serialize)]
+pub struct Readiness {
+    status: &'static str,
+    #[serde(flatten)]
+    default: BundleReadiness,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    bundles: BTreeMap<String, BundleReadiness>,
+}
+
+#[derive(Serialize)]
+struct BundleReadiness {
+    runtime: String,
+    bundle: BundleCheck,
+    engine_pool: PoolCheck,
+    reload: ReloadCheck,
+}
+
+impl BundleReadiness {
+    fn new(runtime: &RenderRuntime, reload_failure: Option<String>) -> Self {
+        // A runtime only exists once its bundle has been evaluated successfully.
+        Self {
+            runtime: runtime.name().to_string(),
+            bundle: BundleCheck {
+                ready: true,
+                path: runtime.bundle_path().to_path_buf(),
+            },
+            engine_pool: PoolCheck {
+                ready: runtime.is_warm(),
+                stats: runtime.pool_stats(),
+            },
+            reload: ReloadCheck {
+                ready: reload_failure.is_none(),
+                error: reload_failure,
+            },
+        }
+    }
+
+    fn ready(&self) -> bool {
+        self.bundle.ready && self.engine_pool.ready && self.reload.ready
+    }
+}
+
+#[derive(Serialize)]
+struct BundleCheck {
+    ready: bool,
+    path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct PoolCheck {
+    ready: bool,
+    #[serde(flatten)]
+    stats: PoolStats,
+}
+
+#[derive(Serialize)]
+struct ReloadCheck {
+    ready: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Reports whether the server can render: every bundle is loaded, its engine pool is warm and
+/// its last reload did not fail. Returns `503` with the same detail otherwise.
+pub async fn readyz(Extension(state): Extension<AppState>) -> (StatusCode, Json<Readiness>) {
+    let health = state.health();
+    let mut bundles: BTreeMap<String, BundleReadiness> = state
+        .bundles()
+        .into_iter()
+        .map(|(bundle, runtime)| {
+            let readiness = BundleReadiness::new(&runtime, health.reload_failure(&bundle));
+            (bundle, readiness)
+        })
+        .collect();
+    let default = bundles
+        .remove(DEFAULT_BUNDLE)
+        .expect("the default bundle is always registered");
+
+    let ready = default.ready() && bundles.values().all(BundleReadiness::ready);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let report = Readiness {
+        status: if ready { "ready" } else { "unavailable" },
+        default,
+        bundles,
+    };
+    (status, Json(report))
+}
@@ -1,8 +1,17 @@
+pub mod access_log;
+pub mod admin;
 pub mod app;
+pub mod cache;
+pub mod coalesce;
+pub mod config;
 pub mod context;
+pub mod data;
 pub mod errors;
 pub mod handlers;
+pub mod health;
+pub mod reload;
 pub mod telemetry;
 
 pub use app::{build_router, AppState};
+pub use config::{ConfigError, ServerConfig};
 pub use telemetry::{init_metrics, init_tracing};
@@ -2,37 +2,137 @@ use std::{net::SocketAddr, path::PathBuf};
 
 use anyhow::{Context, Result};
 use clap::Parser;
-use runtime::{RenderRuntime, RuntimeConfig};
-use server::{app::AppState, build_router, handlers, telemetry};
+use runtime::{
+    registry::DEFAULT_BUNDLE, BundleRegistry, FsBundleRegistry, RenderRuntime, RuntimeConfig,
+};
+use server::{
+    admin,
+    app::AppState,
+    build_router,
+    cache::{MemoryCache, RedisCache},
+    config::{CacheBackend, ConfigOverrides, ServerConfig},
+    handlers, reload, telemetry,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Rust SSR streaming server")]
 struct Cli {
+    /// TOML or YAML file with server, runtime, telemetry and route settings.
+    #[arg(long, value_name = "CONFIG_PATH")]
+    config: Option<PathBuf>,
+
     /// Path to the JavaScript bundle that exports a `stream` handler.
     #[arg(long, value_name = "BUNDLE_PATH")]
-    bundle: PathBuf,
+    bundle: Option<PathBuf>,
+
+    /// Serve another bundle under ID, for routes that set `bundle = "ID"`. Repeatable.
+    #[arg(long = "extra-bundle", value_name = "ID=BUNDLE_PATH", value_parser = parse_extra_bundle)]
+    extra_bundles: Vec<(String, PathBuf)>,
 
     /// Friendly name used to tag logs and metrics for this runtime.
-    #[arg(long, default_value = "rsengine")]
-    runtime_name: String,
+    #[arg(long)]
+    runtime_name: Option<String>,
+
+    /// Reload the bundle whenever it changes on disk.
+    #[arg(long)]
+    watch: bool,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
-
-    telemetry::init_tracing().context("failed to initialise tracing")?;
-    let _ = telemetry::init_metrics().context("failed to initialise metrics")?;
-
-    let runtime_config = RuntimeConfig::new(cli.bundle).with_name(cli.runtime_name);
-    let runtime =
-        RenderRuntime::try_new(runtime_config).context("failed to initialise render runtime")?;
+    let overrides = ConfigOverrides {
+        bundle: cli.bundle,
+        bundles: cli.extra_bundles,
+        runtime_name: cli.runtime_name,
+        watch: cli.watch.then_some(true),
+    };
+    let config = ServerConfig::load(cli.config.as_deref(), std::env::vars(), overrides)?;
+
+    telemetry::init_tracing_with(&config.telemetry).context("failed to initialise tracing")?;
+    let metrics = telemetry::init_metrics().context("failed to initialise metrics")?;
+
+    let registry = config.runtime.registry.as_ref().map(FsBundleRegistry::new);
+    let runtime = load_bundle(registry.as_ref(), DEFAULT_BUNDLE, config.runtime_config()).await?;
+    let mut state = AppState::new(runtime);
+    for (id, bundle_config) in config.bundle_configs() {
+        let runtime = load_bundle(registry.as_ref(), &id, bundle_config).await?;
+        state = state.with_bundle(id, runtime);
+    }
     handlers::register_process_metrics();
 
-    let state = AppState::new(runtime);
+    if let Some(registry) = registry {
+        state = state.with_registry(registry);
+    }
+    let cache = &config.cache;
+    let mut state = match cache.backend {
+        CacheBackend::Memory => state.with_cache(MemoryCache::new(cache.max_bytes)),
+        CacheBackend::Redis => {
+            let url = cache.redis.url.as_deref().unwrap_or_default();
+            let redis = RedisCache::connect(url, cache.redis.timeout)
+                .await
+                .context("failed to connect to the redis render cache")?;
+            state.with_cache(
+                redis
+                    .with_key_prefix(cache.redis.key_prefix.clone())
+                    .with_max_bytes(cache.max_bytes),
+            )
+        }
+    };
+    if config.runtime.expose_render_errors {
+        tracing::warn!("render errors, with their stacks, are shown to clients");
+        state = state.with_exposed_render_errors();
+    }
+    if !config.routes.is_empty() {
+        state = state.with_routes(config.routes.clone())?;
+    }
+    // The binary registers no data providers; routes that declare data need a build that does.
+    state.check_data_providers()?;
+    match config.telemetry.metrics_bind {
+        Some(metrics_addr) => {
+            let listener = tokio::net::TcpListener::bind(metrics_addr)
+                .await
+                .with_context(|| format!("failed to bind metrics listener to {metrics_addr}"))?;
+            tracing::info!("serving metrics on http://{metrics_addr}/metrics");
+            let router = telemetry::metrics_router(metrics);
+            tokio::spawn(async move {
+                if let Err(err) = axum::serve(listener, router).await {
+                    tracing::error!(error = %err, "metrics listener terminated");
+                }
+            });
+        }
+        None => state = state.with_metrics(metrics),
+    }
+    match (&config.admin.token, config.admin.bind) {
+        (Some(token), Some(admin_addr)) => {
+            let listener = tokio::net::TcpListener::bind(admin_addr)
+                .await
+                .with_context(|| format!("failed to bind admin listener to {admin_addr}"))?;
+            tracing::info!("serving the admin API on http://{admin_addr}/admin");
+            let router = admin::admin_router(state.clone(), token.clone());
+            tokio::spawn(async move {
+                if let Err(err) = axum::serve(listener, router).await {
+                    tracing::error!(error = %err, "admin listener terminated");
+                }
+            });
+        }
+        (Some(token), None) => state = state.with_admin(token.clone()),
+        (None, _) => {}
+    }
+    // Stop watching when dropped at shutdown.
+    let mut watchers = Vec::new();
+    if config.runtime.watch {
+        for (id, _) in state.bundles() {
+            watchers.push(reload::watch_bundle(
+                state.clone(),
+                &id,
+                config.runtime.watch_debounce,
+            )?);
+        }
+    }
     let router = build_router(state);
 
-    let addr = bind_address();
+    let addr = config.bind;
     let listener = tokio::net::TcpListener::bind(addr)
         .await
         .with_context(|| format!("failed to bind to {addr}"))?;
@@ -46,16 +146,41 @@ async fn main() -> Result<()> {
         .await
         .context("server terminated unexpectedly")?;
 
+    telemetry::shutdown_tracing();
     Ok(())
 }
 
-fn bind_address() -> SocketAddr {
-    let port = std::env::var("PORT")
-        .ok()
-        .and_then(|value| value.parse::<u16>().ok())
-        .unwrap_or(3000);
+/// Creates the runtime for bundle `id`, serving its active registry version when there is a
+/// registry.
+async fn load_bundle(
+    registry: Option<&FsBundleRegistry>,
+    id: &str,
+    mut config: RuntimeConfig,
+) -> Result<RenderRuntime> {
+    if let Some(registry) = registry {
+        let version = registry
+            .resolve(id)
+            .await
+            .with_context(|| format!("failed to resolve the active version of bundle '{id}'"))?;
+        tracing::info!(
+            bundle = %id,
+            version = %version.version,
+            content_hash = %version.content_hash,
+            "serving bundle version from the registry"
+        );
+        config = config.with_bundle_version(&version);
+    }
+    RenderRuntime::try_new(config)
+        .with_context(|| format!("failed to initialise the render runtime for bundle '{id}'"))
+}
 
-    SocketAddr::from(([0, 0, 0, 0], port))
+fn parse_extra_bundle(value: &str) -> Result<(String, PathBuf), String> {
+    match value.split_once('=') {
+        Some((id, path)) if !id.is_empty() && !path.is_empty() => {
+            Ok((id.to_string(), PathBuf::from(path)))
+        }
+        _ => Err(format!("expected ID=BUNDLE_PATH, got '{value}'")),
+    }
 }
 
 #[cfg(unix)]
@@ -0,0 +1,253 @@
+//! Reloads the bundle when it changes on disk or a registry version is activated.
+
+use std::{
+    ffi::OsString,
+    future::Future,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use common::{AppError, ErrorCode};
+use metrics::{histogram, increment_counter};
+use notify::{
+    event::{AccessKind, AccessMode},
+    EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use runtime::{BundleRegistry, BundleVersion, RenderRuntime, RuntimeConfig};
+use tokio::{
+    sync::mpsc,
+    task::{self, JoinHandle},
+    time,
+};
+use tracing::{error, info};
+
+use crate::app::{unknown_bundle, AppState};
+
+/// Loads the file `bundle`'s current runtime was created from into a new runtime, off the
+/// request path, and swaps it in only if it evaluates. On failure the previous bundle keeps
+/// serving and `/readyz` reports the error until a later reload succeeds. Waits for any
+/// activation or rollback of the bundle in progress, and reloads what it switched to.
+pub async fn reload_bundle(state: &AppState, bundle: &str) -> Result<(), AppError> {
+    let _switching = state.lock_bundle(bundle).await?;
+    let config = current_config(state, bundle)?;
+    let path = config.bundle_path.display().to_string();
+    let started = Instant::now();
+
+    match load_runtime(config).await {
+        Ok(runtime) => {
+            state.replace_bundle_runtime(bundle, runtime)?;
+            state.health().clear_reload_failure(bundle);
+            let elapsed = started.elapsed();
+            info!(
+                bundle = %bundle,
+                path = %path,
+                duration_ms = elapsed.as_secs_f64() * 1000.0,
+                "bundle reloaded"
+            );
+            increment_counter!("bundle_reloads_total", "outcome" => "success");
+            histogram!("bundle_reload_duration_seconds", elapsed.as_secs_f64());
+            Ok(())
+        }
+        Err(err) => {
+            state.health().record_reload_failure(bundle, err.message());
+            error!(
+                bundle = %bundle,
+                path = %path,
+                error = %err,
+                "bundle reload failed; still serving the previous bundle"
+            );
+            increment_counter!("bundle_reloads_total", "outcome" => "failure");
+            Err(err)
+        }
+    }
+}
+
+/// Serves `version` of `bundle` from the state's registry. The version is loaded first and
+/// only becomes the registry's active version once it evaluates, so a broken build leaves both
+/// the registry and the running bundle untouched.
+pub async fn activate_version(
+    state: &AppState,
+    bundle: &str,
+    version: &str,
+) -> Result<BundleVersion, AppError> {
+    let registry = require_registry(state)?;
+    let _switching = state.lock_bundle(bundle).await?;
+    let target = registry.version(bundle, version).await?;
+    switch_version(state, bundle, target, || registry.activate(bundle, version)).await
+}
+
+/// Serves the version of `bundle` that was active before the current one.
+pub async fn rollback_version(state: &AppState, bundle: &str) -> Result<BundleVersion, AppError> {
+    let registry = require_registry(state)?;
+    let _switching = state.lock_bundle(bundle).await?;
+    let target = registry.previous(bundle).await?.ok_or_else(|| {
+        AppError::new(
+            ErrorCode::BadRequest,
+            format!("there is no earlier version of bundle '{bundle}' to roll back to"),
+        )
+    })?;
+    switch_version(state, bundle, target, || registry.rollback(bundle)).await
+}
+
+/// The state's registry, or an error explaining that versions need one.
+pub(crate) fn require_registry(state: &AppState) -> Result<Arc<dyn BundleRegistry>, AppError> {
+    state.registry().ok_or_else(|| {
+        AppError::new(
+            ErrorCode::BadRequest,
+            "bundle versions cannot be switched without a bundle registry",
+        )
+    })
+}
+
+/// The configuration `bundle`'s current runtime was created with.
+fn current_config(state: &AppState, bundle: &str) -> Result<RuntimeConfig, AppError> {
+    state
+        .bundle_runtime(bundle)
+        .map(|runtime| runtime.config().clone())
+        .ok_or_else(|| unknown_bundle(bundle))
+}
+
+/// Loads `target` with the bundle's current configuration, moves the registry's pointer with
+/// `commit`, then swaps the runtime in. Callers hold the bundle's lock throughout, so the
+/// registry's active version and the served runtime change together.
+async fn switch_version<F, Fut>(
+    state: &AppState,
+    bundle: &str,
+    target: BundleVersion,
+    commit: F,
+) -> Result<BundleVersion, AppError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<BundleVersion, AppError>>,
+{
+    let config = current_config(state, bundle)?.with_bundle_version(&target);
+    let started = Instant::now();
+
+    let switched = match load_runtime(config).await {
+        Ok(runtime) => commit().await.map(|activated| (runtime, activated)),
+        Err(err) => Err(err),
+    };
+    match switched {
+        Ok((runtime, activated)) => {
+            state.replace_bundle_runtime(bundle, runtime)?;
+            state.health().clear_reload_failure(bundle);
+            let elapsed = started.elapsed();
+            info!(
+                bundle = %bundle,
+                version = %activated.version,
+                content_hash = %activated.content_hash,
+                duration_ms = elapsed.as_secs_f64() * 1000.0,
+                "bundle version activated"
+            );
+            increment_counter!("bundle_reloads_total", "outcome" => "success");
+            histogram!("bundle_reload_duration_seconds", elapsed.as_secs_f64());
+            Ok(activated)
+        }
+        Err(err) => {
+            error!(
+                bundle = %bundle,
+                version = %target.version,
+                error = %err,
+                "bundle version was not activated; still serving the previous bundle"
+            );
+            increment_counter!("bundle_reloads_total", "outcome" => "failure");
+            Err(err)
+        }
+    }
+}
+
+/// Creates a runtime on the blocking pool, since evaluating a bundle can take a while.
+async fn load_runtime(config: RuntimeConfig) -> Result<RenderRuntime, AppError> {
+    task::spawn_blocking(move || RenderRuntime::try_new(config))
+        .await
+        .unwrap_or_else(|err| {
+            Err(AppError::new(
+                ErrorCode::Internal,
+                "bundle reload task terminated unexpectedly",
+            )
+            .with_source(err))
+        })
+}
+
+/// Watches the bundle and reloads it on change until dropped.
+pub struct BundleWatcher {
+    _watcher: RecommendedWatcher,
+    task: JoinHandle<()>,
+}
+
+impl Drop for BundleWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Starts watching the file `bundle` is served from, and its source map, reloading once no
+/// further change has been seen for `debounce` so a bundler's partial writes are never loaded.
+pub fn watch_bundle(state: AppState, bundle: &str, debounce: Duration) -> Result<BundleWatcher> {
+    let config = current_config(&state, bundle)?;
+    let id = bundle.to_string();
+    let bundle = config.bundle_path.as_path();
+    let directory = match bundle.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    // Bundlers often replace files by renaming over them, so watch the directory by file name.
+    let mut default_source_map = file_name(bundle);
+    default_source_map.push(".map");
+    let mut names = vec![file_name(bundle), default_source_map];
+    if let Some(source_map) = &config.source_map_path {
+        names.push(file_name(source_map));
+    }
+
+    let (changed, mut changes) = mpsc::channel(1);
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        let relevant = match event.kind {
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+            // Reads, including our own when reloading, do not change the bundle.
+            EventKind::Access(_) => false,
+            _ => true,
+        };
+        let ours = event.paths.iter().any(|path| {
+            path.file_name()
+                .is_some_and(|name| names.iter().any(|watched| watched == name))
+        });
+        if relevant && ours {
+            // A full channel already has a reload pending.
+            let _ = changed.try_send(());
+        }
+    })
+    .context("failed to create the bundle watcher")?;
+    watcher
+        .watch(directory, RecursiveMode::NonRecursive)
+        .with_context(|| format!("failed to watch '{}'", directory.display()))?;
+
+    info!(bundle = %id, path = %bundle.display(), "watching the bundle for changes");
+    let task = tokio::spawn(async move {
+        while changes.recv().await.is_some() {
+            loop {
+                match time::timeout(debounce, changes.recv()).await {
+                    Ok(Some(())) => continue,
+                    Ok(None) => return,
+                    Err(_) => break,
+                }
+            }
+            // Failures are logged and reported through `/readyz` by `reload_bundle`.
+            let _ = reload_bundle(&state, &id).await;
+        }
+    });
+
+    Ok(BundleWatcher {
+        _watcher: watcher,
+        task,
+    })
+}
+
+fn file_name(path: &Path) -> OsString {
+    path.file_name().map(OsString::from).unwrap_or_default()
+}
@@ -1,25 +1,137 @@
-use std::sync::{Mutex, OnceLock};
+use std::{
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
 
 use anyhow::{Context, Result};
+use axum::{
+    http::{header, HeaderMap, HeaderValue},
+    response::IntoResponse,
+    routing::get,
+    Extension, Router,
+};
+use common::{
+    trace::{TRACEPARENT_HEADER, TRACESTATE_HEADER},
+    SpanId, TraceId, TraceParent,
+};
 use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
-use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use opentelemetry::{
+    trace::{self as otel, TraceContextExt, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace as sdktrace, Resource};
+use serde::Deserialize;
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
 
 static TRACING_INIT: OnceLock<()> = OnceLock::new();
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
 static METRICS_HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
 static METRICS_GUARD: Mutex<()> = Mutex::new(());
 
+/// Telemetry options read from the `[telemetry]` section of the server config.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// `tracing-subscriber` filter directives used when `RUST_LOG` is unset.
+    pub log_filter: String,
+    /// How log lines are written to stdout.
+    pub log_format: LogFormat,
+    /// Serves `/metrics` on this separate address instead of the main listener when set.
+    pub metrics_bind: Option<SocketAddr>,
+    /// OTLP/gRPC collector spans are exported to, such as `http://localhost:4317`; spans are
+    /// not exported when unset.
+    pub otlp_endpoint: Option<String>,
+    /// `service.name` attached to exported spans.
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            log_filter: "info,tower_http=info".to_string(),
+            log_format: LogFormat::default(),
+            metrics_bind: None,
+            otlp_endpoint: None,
+            service_name: "rsengine".to_string(),
+        }
+    }
+}
+
+/// Output format for log lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Single-line human-readable output.
+    #[default]
+    Compact,
+    /// One JSON object per line, carrying the fields of every enclosing span.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "compact" => Ok(Self::Compact),
+            "json" => Ok(Self::Json),
+            _ => Err("expected 'compact' or 'json'".to_string()),
+        }
+    }
+}
+
 /// Configures global tracing subscribers using `tracing-subscriber`.
 pub fn init_tracing() -> Result<()> {
+    init_tracing_with(&TelemetryConfig::default())
+}
+
+/// Configures global tracing subscribers from `config`; `RUST_LOG` still takes precedence.
+pub fn init_tracing_with(config: &TelemetryConfig) -> Result<()> {
     if TRACING_INIT.get().is_some() {
         return Ok(());
     }
 
-    let filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::new("info,tower_http=info"));
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(&config.log_filter)
+            .with_context(|| format!("invalid log filter '{}'", config.log_filter))?,
+    };
+    let (filter, filter_handle) = reload::Layer::new(filter);
+
+    let exporter = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let provider = otlp_tracer_provider(endpoint, &config.service_name)?;
+            let tracer = provider.tracer("rsengine");
+            opentelemetry::global::set_tracer_provider(provider);
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        None => None,
+    };
+
+    let (compact, json) = match config.log_format {
+        LogFormat::Compact => (
+            Some(fmt::layer().with_target(false).with_level(true).compact()),
+            None,
+        ),
+        LogFormat::Json => (None, Some(json_layer(std::io::stdout))),
+    };
 
     if let Err(err) = tracing_subscriber::registry()
         .with(filter)
-        .with(fmt::layer().with_target(false).with_level(true).compact())
+        .with(compact)
+        .with(json)
+        .with(exporter)
         .try_init()
     {
         // Ignore attempts to re-initialise the global subscriber.
@@ -28,10 +140,104 @@ pub fn init_tracing() -> Result<()> {
         }
     }
 
+    let _ = LOG_FILTER.set(filter_handle);
     let _ = TRACING_INIT.set(());
     Ok(())
 }
 
+/// The filter directives in effect, or `None` when tracing was not initialised here.
+pub fn log_filter() -> Option<String> {
+    LOG_FILTER
+        .get()?
+        .with_current(|filter| filter.to_string())
+        .ok()
+}
+
+/// Replaces the filter directives in effect without restarting, for example to turn on debug
+/// logging for a module while investigating an incident.
+pub fn set_log_filter(directives: &str) -> Result<()> {
+    let filter = EnvFilter::try_new(directives)
+        .with_context(|| format!("invalid log filter '{directives}'"))?;
+    let handle = LOG_FILTER
+        .get()
+        .context("tracing was not initialised by the server")?;
+    handle
+        .reload(filter)
+        .context("failed to replace the log filter")
+}
+
+/// The layer `log_format = "json"` installs, writing to `make_writer`. Each line carries the
+/// event's own fields at the top level and the enclosing spans under `spans`, so events inside
+/// a request include its `request_id`, `trace_id`, `route` and `runtime`.
+pub fn json_layer<S, W>(make_writer: W) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    fmt::layer()
+        .json()
+        .flatten_event(true)
+        .with_current_span(false)
+        .with_span_list(true)
+        .with_writer(make_writer)
+}
+
+/// Batches spans to the OTLP collector at `endpoint`; must be called inside the Tokio runtime.
+fn otlp_tracer_provider(endpoint: &str, service_name: &str) -> Result<sdktrace::TracerProvider> {
+    let resource = Resource::new([KeyValue::new("service.name", service_name.to_string())]);
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(sdktrace::Config::default().with_resource(resource))
+        .install_batch(runtime::Tokio)
+        .with_context(|| format!("failed to start the OTLP exporter for {endpoint}"))
+}
+
+/// Flushes spans still waiting to be exported; call before the process exits.
+pub fn shutdown_tracing() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Makes the caller's `traceparent` (and `tracestate`) the parent of `span`, so exported spans
+/// join the caller's trace. Call before the span is first entered.
+pub fn set_remote_parent(span: &Span, headers: &HeaderMap) {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let Some(parent) = header(TRACEPARENT_HEADER).and_then(TraceParent::parse) else {
+        return;
+    };
+    let state = header(TRACESTATE_HEADER)
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_default();
+
+    let remote = otel::SpanContext::new(
+        otel::TraceId::from_bytes(parent.trace_id.to_bytes()),
+        otel::SpanId::from_bytes(parent.parent_id.to_bytes()),
+        otel::TraceFlags::new(parent.flags),
+        true,
+        state,
+    );
+    span.set_parent(opentelemetry::Context::new().with_remote_span_context(remote));
+}
+
+/// Trace id, span id and flags the exporter assigned to `span`, or `None` when spans are not
+/// being exported.
+pub fn exported_span(span: &Span) -> Option<(TraceId, SpanId, u8)> {
+    let context = span.context();
+    let span = context.span();
+    let span_context = span.span_context();
+    span_context.is_valid().then(|| {
+        (
+            TraceId::from_bytes(span_context.trace_id().to_bytes()),
+            SpanId::from_bytes(span_context.span_id().to_bytes()),
+            span_context.trace_flags().to_u8(),
+        )
+    })
+}
+
 /// Installs the global Prometheus recorder and returns a handle for scraping metrics.
 pub fn init_metrics() -> Result<PrometheusHandle> {
     if let Some(handle) = METRICS_HANDLE.get() {
@@ -57,6 +263,56 @@ pub fn init_metrics() -> Result<PrometheusHandle> {
         "http_requests_total",
         "Total number of HTTP requests processed by the server"
     );
+    metrics::describe_counter!(
+        "render_bundle_errors_total",
+        "Errors reported by bundles through `context.onError`"
+    );
+    metrics::describe_counter!(
+        "render_cancelled_total",
+        "Renders cancelled because the client disconnected"
+    );
+    metrics::describe_counter!(
+        "render_cache_hits_total",
+        "Requests served from the render cache without invoking the runtime"
+    );
+    metrics::describe_counter!(
+        "render_cache_misses_total",
+        "Requests to cacheable routes that had to be rendered"
+    );
+    metrics::describe_counter!(
+        "render_cache_stale_total",
+        "Stale cached renders served while a background render refreshed them"
+    );
+    metrics::describe_counter!(
+        "render_cache_coalesced_total",
+        "Cache misses served by waiting on a concurrent render of the same key"
+    );
+    metrics::describe_counter!(
+        "render_cache_errors_total",
+        "Render cache lookups and stores that failed, by operation"
+    );
+    metrics::describe_histogram!(
+        "render_duration_seconds",
+        "Time from invoking the runtime until the render finished, by route, runtime and outcome"
+    );
+    metrics::describe_histogram!(
+        "render_time_to_first_chunk_seconds",
+        "Time from invoking the runtime until the bundle wrote its first chunk"
+    );
+    metrics::describe_histogram!(
+        "render_time_to_first_byte_seconds",
+        "Time from the request reaching the handler until rendered bytes reached the response body"
+    );
+    metrics::describe_histogram!("render_chunks", "Chunks written by the bundle per render");
+    metrics::describe_histogram!("render_bytes", "Bytes written by the bundle per render");
+    metrics::describe_counter!(
+        "bundle_reloads_total",
+        "Bundle reloads triggered by changes on disk, by outcome"
+    );
+    metrics::describe_histogram!(
+        "bundle_reload_duration_seconds",
+        "Time taken to load and warm up a changed bundle before swapping it in"
+    );
     metrics::describe_gauge!(
         "process_start_time_seconds",
         "Unix timestamp for the process start time"
@@ -65,3 +321,22 @@ pub fn init_metrics() -> Result<PrometheusHandle> {
     let _ = METRICS_HANDLE.set(recorder.clone());
     Ok(recorder)
 }
+
+/// Content type of the Prometheus text exposition format.
+const PROMETHEUS_CONTENT_TYPE: HeaderValue =
+    HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8");
+
+/// Serves the metrics recorded through `handle` at `/metrics`, for merging into the main router
+/// or serving on its own listener.
+pub fn metrics_router(handle: PrometheusHandle) -> Router {
+    Router::new()
+        .route("/metrics", get(render_metrics))
+        .layer(Extension(handle))
+}
+
+async fn render_metrics(Extension(handle): Extension<PrometheusHandle>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)],
+        handle.render(),
+    )
+}
@@ -0,0 +1,201 @@
+use std::fs;
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use common::{RenderMode, RouteConfig};
+use http_body_util::BodyExt;
+use runtime::{RenderRuntime, RuntimeConfig};
+use serde_json::{json, Value};
+use server::{app::AppState, build_router, telemetry};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+const TOKEN: &str = "test-token";
+
+/// A registry holding versions `v1` and `v2` of the default bundle, with `v1` active.
+fn registry() -> TempDir {
+    let dir = TempDir::new().expect("registry dir");
+    for version in ["v1", "v2"] {
+        let version_dir = dir.path().join("default").join("versions").join(version);
+        fs::create_dir_all(&version_dir).expect("version dir");
+        fs::write(
+            version_dir.join("bundle.js"),
+            format!("export function stream(context) {{ context.write('{version}'); }}"),
+        )
+        .expect("write bundle");
+    }
+    fs::write(dir.path().join("default").join("current"), "v1").expect("current");
+    dir
+}
+
+fn app(registry: &TempDir) -> Router {
+    telemetry::init_tracing().ok();
+    let bundle = registry.path().join("default/versions/v1/bundle.js");
+    let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle)).expect("runtime");
+    let route = RouteConfig::new("home", "/")
+        .with_render_mode(RenderMode::Blocking)
+        .with_cache_ttl_seconds(60);
+    build_router(
+        AppState::new(runtime)
+            .with_routes(vec![route])
+            .expect("routes")
+            .with_registry(runtime::FsBundleRegistry::new(registry.path()))
+            .with_admin(TOKEN),
+    )
+}
+
+async fn send(
+    app: &Router,
+    method: Method,
+    path: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(path)
+        .header(header::AUTHORIZATION, format!("Bearer {TOKEN}"));
+    let body = match body {
+        Some(body) => {
+            request = request.header(header::CONTENT_TYPE, "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+    let response = app
+        .clone()
+        .oneshot(request.body(body).unwrap())
+        .await
+        .expect("response");
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+async fn render(app: &Router) -> (String, String) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+        .await
+        .expect("response");
+    let cache_status = response.headers()["x-cache"].to_str().unwrap().to_string();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    // Renders are stored in the cache in the background, after the response.
+    tokio::task::yield_now().await;
+    (cache_status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn requests_without_the_token_are_rejected() {
+    let registry = registry();
+    let app = app(&registry);
+
+    for authorization in [None, Some("Bearer wrong-token"), Some(TOKEN)] {
+        let mut request = Request::builder().uri("/admin/runtime");
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .expect("response");
+        assert_eq!(
+            response.status(),
+            StatusCode::UNAUTHORIZED,
+            "{authorization:?}"
+        );
+    }
+
+    let (status, stats) = send(&app, Method::GET, "/admin/runtime", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats["bundles"]["default"]["warm"], true);
+    assert!(stats["bundles"]["default"]["engine_pool"]["idle"]
+        .as_u64()
+        .is_some());
+}
+
+#[tokio::test]
+async fn bundle_versions_are_listed_and_activated() {
+    let registry = registry();
+    let app = app(&registry);
+
+    let (status, bundles) = send(&app, Method::GET, "/admin/bundles", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(bundles["active"]["version"], "v1");
+    assert_eq!(bundles["versions"].as_array().map(Vec::len), Some(2));
+
+    let (status, activated) = send(&app, Method::POST, "/admin/bundles/v2/activate", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(activated["version"], "v2");
+    assert_eq!(render(&app).await.1, "v2");
+
+    let (status, _) = send(&app, Method::POST, "/admin/bundles/v9/activate", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, Method::GET, "/admin/bundles?bundle=missing", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, restored) = send(&app, Method::POST, "/admin/bundles/rollback", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(restored["version"], "v1");
+}
+
+#[tokio::test]
+async fn cache_entries_are_purged_by_route() {
+    let registry = registry();
+    let app = app(&registry);
+    assert_eq!(render(&app).await.0, "MISS");
+    assert_eq!(render(&app).await.0, "HIT");
+
+    let (status, purged) = send(
+        &app,
+        Method::POST,
+        "/admin/cache/purge",
+        Some(json!({ "route": "home" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(purged, json!({ "prefix": "home|", "purged": 1 }));
+    assert_eq!(render(&app).await.0, "MISS");
+
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        "/admin/cache/purge",
+        Some(json!({ "route": "missing" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, Method::POST, "/admin/cache/purge", Some(json!({}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn the_log_filter_is_replaced_at_runtime() {
+    let registry = registry();
+    let app = app(&registry);
+
+    let (status, _) = send(
+        &app,
+        Method::PUT,
+        "/admin/log-filter",
+        Some(json!({ "filter": "debug,hyper=info" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, current) = send(&app, Method::GET, "/admin/log-filter", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(current["filter"].as_str().unwrap().contains("hyper=info"));
+
+    let (status, error) = send(
+        &app,
+        Method::PUT,
+        "/admin/log-filter",
+        Some(json!({ "filter": "info,[" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["code"], "bad_request");
+}
@@ -0,0 +1,89 @@
+use std::io::Write;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use common::{ErrorCode, RenderMode, RouteConfig};
+use http_body_util::BodyExt;
+use runtime::{RenderRuntime, RuntimeConfig};
+use serde_json::Value;
+use server::{app::AppState, build_router, reload, telemetry};
+use tempfile::NamedTempFile;
+use tower::ServiceExt;
+
+fn write_bundle(text: &str) -> NamedTempFile {
+    let mut file = NamedTempFile::new().expect("bundle temp file");
+    write!(
+        file,
+        "export function stream(context) {{ context.write('{text}'); }}"
+    )
+    .expect("write bundle");
+    file
+}
+
+fn runtime(bundle: &NamedTempFile, name: &str) -> RenderRuntime {
+    RenderRuntime::try_new(RuntimeConfig::new(bundle.path()).with_name(name)).expect("runtime")
+}
+
+async fn get(app: &Router, path: &str) -> (StatusCode, String) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(path).body(Body::empty()).unwrap())
+        .await
+        .expect("response");
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn routes_render_with_the_bundle_they_select() {
+    telemetry::init_tracing().ok();
+    let storefront = write_bundle("storefront");
+    let checkout = write_bundle("checkout");
+    let routes = vec![
+        RouteConfig::new("home", "/").with_render_mode(RenderMode::Blocking),
+        RouteConfig::new("checkout", "/checkout")
+            .with_render_mode(RenderMode::Blocking)
+            .with_bundle("checkout"),
+    ];
+    let state = AppState::new(runtime(&storefront, "storefront"))
+        .with_bundle("checkout", runtime(&checkout, "checkout"))
+        .with_routes(routes)
+        .expect("routes");
+    let app = build_router(state.clone());
+
+    assert_eq!(get(&app, "/").await, (StatusCode::OK, "storefront".into()));
+    assert_eq!(
+        get(&app, "/checkout").await,
+        (StatusCode::OK, "checkout".into())
+    );
+
+    let (status, body) = get(&app, "/readyz").await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let readiness: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(readiness["runtime"], "storefront");
+    assert_eq!(readiness["bundles"]["checkout"]["runtime"], "checkout");
+
+    // Reloading one bundle leaves the other serving its own runtime.
+    std::fs::write(
+        checkout.path(),
+        "export function stream(context) { context.write('checkout v2'); }",
+    )
+    .expect("rewrite bundle");
+    reload::reload_bundle(&state, "checkout")
+        .await
+        .expect("reload");
+    assert_eq!(
+        get(&app, "/checkout").await,
+        (StatusCode::OK, "checkout v2".into())
+    );
+    assert_eq!(get(&app, "/").await, (StatusCode::OK, "storefront".into()));
+
+    let err = reload::reload_bundle(&state, "docs")
+        .await
+        .expect_err("unknown bundle");
+    assert!(matches!(err.code(), ErrorCode::NotFound));
+}
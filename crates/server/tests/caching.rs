@@ -0,0 +1,255 @@
+use std::{io::Write, time::Duration};
+
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{header, Request, Response},
+    Router,
+};
+use bytes::Bytes;
+use common::{AppError, ErrorCode, RenderMode, RouteConfig};
+use http_body_util::BodyExt;
+use runtime::{RenderRuntime, RuntimeConfig};
+use server::{
+    app::AppState,
+    build_router,
+    cache::{Lookup, RenderCache},
+    telemetry,
+};
+use tempfile::NamedTempFile;
+use tower::ServiceExt;
+
+fn test_state(bundle_path: &std::path::Path) -> AppState {
+    telemetry::init_tracing().ok();
+    telemetry::init_metrics().ok();
+    let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle_path)).expect("runtime");
+    AppState::new(runtime)
+}
+
+/// A bundle that numbers its renders, optionally taking `delay_ms` to finish each one.
+fn counting_bundle(delay_ms: u64) -> NamedTempFile {
+    let mut bundle = NamedTempFile::new().expect("bundle temp file");
+    writeln!(
+        bundle,
+        "let renders = 0;
+        export async function stream(context) {{
+            const render = ++renders;
+            await new Promise((resolve) => setTimeout(resolve, {delay_ms}));
+            context.write(`render ${{render}} for ${{context.request.cookies.variant}}`);
+        }}"
+    )
+    .expect("write bundle");
+    bundle
+}
+
+fn app(bundle: &NamedTempFile, route: RouteConfig) -> Router {
+    build_router(
+        test_state(bundle.path())
+            .with_routes(vec![route])
+            .expect("routes"),
+    )
+}
+
+async fn get(app: &Router, variant: &str) -> (String, String) {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/")
+                .header(header::COOKIE, format!("variant={variant}"))
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    read(response).await
+}
+
+async fn read(response: Response<Body>) -> (String, String) {
+    let cache_status = response.headers()["x-cache"].to_str().unwrap().to_string();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    // Renders are stored in the cache in the background, after the response.
+    tokio::task::yield_now().await;
+    (cache_status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+fn pair(cache_status: &str, body: &str) -> (String, String) {
+    (cache_status.to_string(), body.to_string())
+}
+
+fn cached_route() -> RouteConfig {
+    let mut route = RouteConfig::new("home", "/").with_cache_ttl_seconds(60);
+    route.cache_vary_cookies = vec!["variant".to_string()];
+    route
+}
+
+#[tokio::test]
+async fn cached_routes_skip_the_runtime_on_hits() {
+    let bundle = counting_bundle(0);
+    let app = app(&bundle, cached_route());
+
+    let mut seen = Vec::new();
+    for variant in ["a", "a", "b"] {
+        seen.push(get(&app, variant).await);
+    }
+
+    assert_eq!(
+        seen,
+        [
+            pair("MISS", "render 1 for a"),
+            pair("HIT", "render 1 for a"),
+            pair("MISS", "render 2 for b"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn stale_entries_are_served_while_one_render_refreshes_them() {
+    let bundle = counting_bundle(50);
+    let route = RouteConfig::new("home", "/")
+        .with_cache_ttl_seconds(1)
+        .with_cache_stale_seconds(60);
+    let app = app(&bundle, route);
+
+    assert_eq!(get(&app, "a").await, pair("MISS", "render 1 for a"));
+    tokio::time::sleep(Duration::from_millis(1_100)).await;
+
+    // Both requests see the stale render; only the first starts a refresh.
+    assert_eq!(get(&app, "a").await, pair("STALE", "render 1 for a"));
+    assert_eq!(get(&app, "a").await, pair("STALE", "render 1 for a"));
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(get(&app, "a").await, pair("HIT", "render 2 for a"));
+}
+
+#[tokio::test]
+async fn concurrent_misses_share_a_single_render() {
+    let bundle = counting_bundle(100);
+    for mode in [RenderMode::Blocking, RenderMode::Streaming] {
+        let app = app(&bundle, cached_route().with_render_mode(mode));
+
+        let responses = futures_util::future::join_all((0..5).map(|_| get(&app, "a"))).await;
+
+        let bodies: Vec<&str> = responses.iter().map(|(_, body)| body.as_str()).collect();
+        assert_eq!(bodies, ["render 1 for a"; 5], "{mode:?}");
+        let misses = responses
+            .iter()
+            .filter(|(cache_status, _)| cache_status == "MISS")
+            .count();
+        assert_eq!(misses, 1, "{mode:?}");
+    }
+}
+
+/// A cache backend whose server is unreachable.
+struct UnreachableCache;
+
+#[async_trait]
+impl RenderCache for UnreachableCache {
+    async fn get(&self, _key: &str) -> Result<Lookup, AppError> {
+        Err(unreachable())
+    }
+
+    async fn set(
+        &self,
+        _key: &str,
+        _body: Bytes,
+        _ttl: Duration,
+        _stale_for: Duration,
+    ) -> Result<(), AppError> {
+        Err(unreachable())
+    }
+
+    async fn delete(&self, _key: &str) -> Result<(), AppError> {
+        Err(unreachable())
+    }
+
+    async fn purge_prefix(&self, _prefix: &str) -> Result<u64, AppError> {
+        Err(unreachable())
+    }
+
+    async fn ttl(&self, _key: &str) -> Result<Option<Duration>, AppError> {
+        Err(unreachable())
+    }
+
+    fn max_bytes(&self) -> usize {
+        usize::MAX
+    }
+}
+
+fn unreachable() -> AppError {
+    AppError::new(ErrorCode::UpstreamFailure, "cache is unreachable")
+}
+
+#[tokio::test]
+async fn cache_failures_fall_back_to_rendering() {
+    let bundle = counting_bundle(0);
+    let app = build_router(
+        test_state(bundle.path())
+            .with_cache(UnreachableCache)
+            .with_routes(vec![cached_route()])
+            .expect("routes"),
+    );
+
+    assert_eq!(get(&app, "a").await, pair("MISS", "render 1 for a"));
+    assert_eq!(get(&app, "a").await, pair("MISS", "render 2 for a"));
+}
+
+/// A cache backend that never answers a store, like a Redis server that stopped responding.
+struct StalledCache;
+
+#[async_trait]
+impl RenderCache for StalledCache {
+    async fn get(&self, _key: &str) -> Result<Lookup, AppError> {
+        Ok(Lookup::Miss)
+    }
+
+    async fn set(
+        &self,
+        _key: &str,
+        _body: Bytes,
+        _ttl: Duration,
+        _stale_for: Duration,
+    ) -> Result<(), AppError> {
+        std::future::pending().await
+    }
+
+    async fn delete(&self, _key: &str) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn purge_prefix(&self, _prefix: &str) -> Result<u64, AppError> {
+        Ok(0)
+    }
+
+    async fn ttl(&self, _key: &str) -> Result<Option<Duration>, AppError> {
+        Ok(None)
+    }
+
+    fn max_bytes(&self) -> usize {
+        usize::MAX
+    }
+}
+
+#[tokio::test]
+async fn responses_do_not_wait_for_the_cache_store() {
+    let bundle = counting_bundle(50);
+    for mode in [RenderMode::Blocking, RenderMode::Streaming] {
+        let app = build_router(
+            test_state(bundle.path())
+                .with_cache(StalledCache)
+                .with_routes(vec![cached_route().with_render_mode(mode)])
+                .expect("routes"),
+        );
+
+        let requests = futures_util::future::join_all((0..3).map(|_| get(&app, "a")));
+        let responses = tokio::time::timeout(Duration::from_secs(5), requests)
+            .await
+            .unwrap_or_else(|_| panic!("{mode:?} responses waited on the cache store"));
+        assert!(
+            responses
+                .iter()
+                .all(|(_, body)| body.starts_with("render ")),
+            "{mode:?}"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use std::io::Write;
+
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use common::{
+    AppError, DataProvider, ErrorCode, RenderMode, RequestContext, RouteConfig, RouteData,
+};
+use http_body_util::BodyExt;
+use runtime::{RenderRuntime, RuntimeConfig};
+use serde_json::{json, Value};
+use server::{app::AppState, build_router, telemetry};
+use tempfile::NamedTempFile;
+use tower::ServiceExt;
+
+/// Answers with the route parameters it was called for.
+struct Product;
+
+#[async_trait]
+impl DataProvider for Product {
+    async fn fetch(&self, request: &RequestContext) -> Result<Value, AppError> {
+        let params = request.route.as_ref().map(|route| &route.params);
+        Ok(json!({ "id": params.and_then(|params| params.get("id")) }))
+    }
+}
+
+struct Failing;
+
+#[async_trait]
+impl DataProvider for Failing {
+    async fn fetch(&self, _request: &RequestContext) -> Result<Value, AppError> {
+        Err(AppError::new(ErrorCode::UpstreamFailure, "catalog is down"))
+    }
+}
+
+fn app(expose_errors: bool) -> (Router, NamedTempFile) {
+    telemetry::init_tracing().ok();
+    let mut bundle = NamedTempFile::new().expect("bundle temp file");
+    write!(
+        bundle,
+        "export function stream(context) {{ context.write(JSON.stringify(context.data)); }}"
+    )
+    .expect("write bundle");
+    let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle.path())).expect("runtime");
+    let routes = vec![
+        RouteConfig::new("product", "/products/:id")
+            .with_render_mode(RenderMode::Streaming)
+            .with_data(RouteData::new("product"))
+            .with_data(RouteData::new("reviews").with_key("reviews").optional()),
+        RouteConfig::new("checkout", "/checkout")
+            .with_render_mode(RenderMode::Blocking)
+            .with_data(RouteData::new("reviews")),
+    ];
+    let mut state = AppState::new(runtime)
+        .with_routes(routes)
+        .expect("routes")
+        .with_data_provider("product", Product)
+        .with_data_provider("reviews", Failing);
+    if expose_errors {
+        state = state.with_exposed_render_errors();
+    }
+    (build_router(state), bundle)
+}
+
+async fn get(app: &Router, path: &str) -> (StatusCode, String) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(path).body(Body::empty()).unwrap())
+        .await
+        .expect("response");
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn route_data_reaches_the_bundle() {
+    let (app, _bundle) = app(false);
+
+    let (status, body) = get(&app, "/products/42").await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let data: Value = serde_json::from_str(&body).expect("json body");
+    assert_eq!(data, json!({ "product": { "id": "42" }, "reviews": null }));
+}
+
+#[tokio::test]
+async fn required_data_failures_fail_the_request() {
+    let (app, _bundle) = app(false);
+
+    let (status, body) = get(&app, "/checkout").await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert!(!body.contains("catalog is down"), "{body}");
+    assert!(body.contains("see the server logs for request"), "{body}");
+}
+
+#[tokio::test]
+async fn data_failures_reach_clients_only_when_errors_are_exposed() {
+    let (app, _bundle) = app(true);
+
+    let (status, body) = get(&app, "/checkout").await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert!(
+        body.contains("data provider 'reviews' failed: catalog is down"),
+        "{body}"
+    );
+}
+
+#[test]
+fn routes_must_name_registered_providers() {
+    let bundle = NamedTempFile::new().expect("bundle temp file");
+    std::fs::write(bundle.path(), "export function stream() {}").expect("write bundle");
+    let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle.path())).expect("runtime");
+    let routes = vec![RouteConfig::new("product", "/products/:id")
+        .with_data(RouteData::new("product"))
+        .with_data(RouteData::new("reviews").optional())];
+    let state = AppState::new(runtime)
+        .with_routes(routes)
+        .expect("routes")
+        .with_data_provider("product", Product);
+
+    let err = state
+        .check_data_providers()
+        .expect_err("unregistered provider");
+    assert!(
+        err.message().contains("route 'product': 'reviews'"),
+        "{err}"
+    );
+    state
+        .with_data_provider("reviews", Failing)
+        .check_data_providers()
+        .expect("every provider registered");
+}
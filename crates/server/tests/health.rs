@@ -0,0 +1,90 @@
+use std::io::Write;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use http_body_util::BodyExt;
+use runtime::{registry::DEFAULT_BUNDLE, RenderRuntime, RuntimeConfig};
+use serde_json::Value;
+use server::{app::AppState, build_router, telemetry};
+use tempfile::NamedTempFile;
+use tower::ServiceExt;
+
+fn test_state(bundle_path: &std::path::Path) -> AppState {
+    telemetry::init_tracing().ok();
+    telemetry::init_metrics().ok();
+    let config = RuntimeConfig::new(bundle_path).with_name("probe-test");
+    AppState::new(RenderRuntime::try_new(config).expect("runtime"))
+}
+
+fn write_bundle() -> NamedTempFile {
+    let mut file = NamedTempFile::new().expect("bundle temp file");
+    writeln!(
+        file,
+        "export function stream(context) {{ context.write('ok'); }}"
+    )
+    .expect("write bundle");
+    file
+}
+
+async fn probe(app: &Router, path: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(path).body(Body::empty()).unwrap())
+        .await
+        .expect("response");
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).expect("json body"))
+}
+
+#[tokio::test]
+async fn healthz_reports_the_process_is_up() {
+    let bundle = write_bundle();
+    let app = build_router(test_state(bundle.path()));
+
+    let (status, body) = probe(&app, "/healthz").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+}
+
+#[tokio::test]
+async fn readyz_reports_each_check() {
+    let bundle = write_bundle();
+    let app = build_router(test_state(bundle.path()));
+
+    let (status, body) = probe(&app, "/readyz").await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["runtime"], "probe-test");
+    assert_eq!(body["bundle"]["ready"], true);
+    assert_eq!(body["bundle"]["path"], bundle.path().to_str().unwrap());
+    assert_eq!(body["engine_pool"]["ready"], true);
+    assert_eq!(body["engine_pool"]["idle"], 1);
+    assert_eq!(body["reload"]["ready"], true);
+    assert!(body["reload"].get("error").is_none());
+}
+
+#[tokio::test]
+async fn readyz_fails_while_a_reload_failure_is_pending() {
+    let bundle = write_bundle();
+    let state = test_state(bundle.path());
+    let app = build_router(state.clone());
+
+    state
+        .health()
+        .record_reload_failure(DEFAULT_BUNDLE, "bundle `stream` export is missing");
+    let (status, body) = probe(&app, "/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["reload"]["ready"], false);
+    assert_eq!(body["reload"]["error"], "bundle `stream` export is missing");
+
+    // Liveness is unaffected, so the orchestrator stops routing without restarting the pod.
+    assert_eq!(probe(&app, "/healthz").await.0, StatusCode::OK);
+
+    state.health().clear_reload_failure(DEFAULT_BUNDLE);
+    assert_eq!(probe(&app, "/readyz").await.0, StatusCode::OK);
+}
@@ -0,0 +1,156 @@
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use common::{RenderMode, RouteConfig};
+use http_body_util::BodyExt;
+use runtime::{RenderRuntime, RuntimeConfig, CONSOLE_TARGET};
+use serde_json::Value;
+use server::{access_log, app::AppState, build_router, telemetry};
+use tempfile::NamedTempFile;
+use tower::ServiceExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+/// Collects everything the JSON layer writes.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Captured {
+    fn lines(&self) -> Vec<Value> {
+        let output = self.0.lock().unwrap();
+        String::from_utf8_lossy(&output)
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("each line is a JSON object"))
+            .collect()
+    }
+}
+
+fn write_bundle() -> NamedTempFile {
+    let mut file = NamedTempFile::new().expect("bundle temp file");
+    writeln!(
+        file,
+        r#"export function stream(context) {{
+            console.warn('rendering', context.request.route.id);
+            if (context.request.route.id === 'broken') throw new Error('boom');
+            context.write('<p>ok</p>');
+        }}"#
+    )
+    .expect("write bundle");
+    file
+}
+
+fn app(bundle: &NamedTempFile) -> Router {
+    let config = RuntimeConfig::new(bundle.path()).with_name("logging-test");
+    let runtime = RenderRuntime::try_new(config).expect("runtime");
+    let routes = vec![
+        RouteConfig::new("home", "/").with_render_mode(RenderMode::Streaming),
+        RouteConfig::new("broken", "/broken").with_render_mode(RenderMode::Blocking),
+    ];
+    build_router(AppState::new(runtime).with_routes(routes).expect("routes"))
+}
+
+async fn get(app: &Router, path: &str) -> (StatusCode, usize) {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(path)
+                .header("traceparent", format!("00-{TRACE_ID}-00f067aa0ba902b7-01"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .expect("response");
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, body.len())
+}
+
+/// Fields of the request span enclosing `line`.
+fn request_span(line: &Value) -> &Value {
+    line["spans"]
+        .as_array()
+        .and_then(|spans| spans.iter().find(|span| span["name"] == "http_request"))
+        .unwrap_or_else(|| panic!("no request span on {line}"))
+}
+
+#[tokio::test]
+async fn json_lines_carry_request_fields_console_output_and_an_access_log_entry() {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber =
+        tracing_subscriber::registry().with(telemetry::json_layer(move || writer.clone()));
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let bundle = write_bundle();
+    let app = app(&bundle);
+    let (status, ok_bytes) = get(&app, "/").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = get(&app, "/broken").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+    let lines = captured.lines();
+    let failure = lines
+        .iter()
+        .find(|line| line["message"] == "render runtime failed")
+        .expect("render failure is logged");
+    assert_eq!(failure["level"], "ERROR");
+    let span = request_span(failure);
+    assert_eq!(span["route"], "broken");
+    assert_eq!(span["runtime"], "logging-test");
+    assert_eq!(span["trace_id"], TRACE_ID);
+    assert!(span["request_id"]
+        .as_str()
+        .is_some_and(|id| id != "unknown"));
+
+    let console = lines
+        .iter()
+        .find(|line| line["target"] == CONSOLE_TARGET && line["message"] == "rendering broken")
+        .expect("bundle console output is logged");
+    assert_eq!(console["level"], "WARN");
+    assert_eq!(console["bundle"], bundle.path().to_str().unwrap());
+    assert_eq!(console["request_id"], span["request_id"]);
+    assert_eq!(request_span(console)["route"], "broken");
+
+    let access: Vec<_> = lines
+        .iter()
+        .filter(|line| line["target"] == access_log::TARGET)
+        .collect();
+    assert_eq!(access.len(), 2, "{lines:#?}");
+    let home = access.iter().find(|line| line["path"] == "/").unwrap();
+    assert_eq!(home["method"], "GET");
+    assert_eq!(home["status"], 200);
+    assert_eq!(home["bytes"], ok_bytes as u64);
+    assert_eq!(home["completed"], true);
+    assert!(home["duration_ms"].as_f64().is_some());
+    assert_eq!(request_span(home)["route"], "home");
+
+    let broken = access
+        .iter()
+        .find(|line| line["path"] == "/broken")
+        .unwrap();
+    assert_eq!(broken["status"], 500);
+    assert_eq!(
+        request_span(broken)["request_id"],
+        request_span(failure)["request_id"]
+    );
+}
@@ -0,0 +1,103 @@
+use std::io::Write;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    Router,
+};
+use http_body_util::BodyExt;
+use runtime::{RenderRuntime, RuntimeConfig};
+use server::{app::AppState, build_router, telemetry};
+use tempfile::NamedTempFile;
+use tower::ServiceExt;
+
+fn test_state(bundle_path: &std::path::Path) -> AppState {
+    telemetry::init_tracing().ok();
+    let handle = telemetry::init_metrics().expect("metrics recorder");
+    let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle_path)).expect("runtime");
+    AppState::new(runtime).with_metrics(handle)
+}
+
+fn write_bundle() -> NamedTempFile {
+    let mut file = NamedTempFile::new().expect("bundle temp file");
+    writeln!(
+        file,
+        "export function stream(context) {{ context.write('<div>Hello</div>'); }}"
+    )
+    .expect("write bundle");
+    file
+}
+
+async fn get(app: &Router, path: &str) -> (StatusCode, Option<String>, String) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(path).body(Body::empty()).unwrap())
+        .await
+        .expect("response");
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map(|value| value.to_str().unwrap().to_string());
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (
+        status,
+        content_type,
+        String::from_utf8(body.to_vec()).unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn metrics_endpoint_exposes_http_series() {
+    let bundle = write_bundle();
+    let app = build_router(test_state(bundle.path()));
+
+    assert_eq!(get(&app, "/stream").await.0, StatusCode::OK);
+
+    let (status, content_type, body) = get(&app, "/metrics").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(content_type
+        .unwrap()
+        .starts_with("text/plain; version=0.0.4"));
+    assert!(body.contains("http_requests_total"), "{body}");
+    assert!(body.contains("http_request_duration_seconds"), "{body}");
+}
+
+#[tokio::test]
+async fn renders_record_stage_metrics_by_route_and_runtime() {
+    let bundle = write_bundle();
+    telemetry::init_tracing().ok();
+    let handle = telemetry::init_metrics().expect("metrics recorder");
+    let config = RuntimeConfig::new(bundle.path()).with_name("stage-metrics");
+    let runtime = RenderRuntime::try_new(config).expect("runtime");
+    let app = build_router(AppState::new(runtime).with_metrics(handle));
+
+    assert_eq!(get(&app, "/stream").await.0, StatusCode::OK);
+
+    let (_, _, body) = get(&app, "/metrics").await;
+    let labels = r#"route="stream",runtime="stage-metrics""#;
+    for series in [
+        format!(r#"render_duration_seconds_count{{{labels},outcome="ok"}} 1"#),
+        format!("render_time_to_first_chunk_seconds_count{{{labels}}} 1"),
+        format!("render_time_to_first_byte_seconds_count{{{labels}}} 1"),
+        format!("render_chunks_sum{{{labels}}} 1"),
+        format!("render_bytes_sum{{{labels}}} 16"),
+    ] {
+        assert!(body.contains(&series), "missing `{series}` in:\n{body}");
+    }
+}
+
+#[tokio::test]
+async fn metrics_are_only_served_when_enabled() {
+    let bundle = write_bundle();
+    telemetry::init_metrics().expect("metrics recorder");
+    let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle.path())).expect("runtime");
+    let app = build_router(AppState::new(runtime));
+
+    // Without a handle the path falls through to the route table, which does not match it.
+    assert_eq!(get(&app, "/metrics").await.0, StatusCode::NOT_FOUND);
+
+    let admin = telemetry::metrics_router(telemetry::init_metrics().unwrap());
+    let (status, _, _) = get(&admin, "/metrics").await;
+    assert_eq!(status, StatusCode::OK);
+}
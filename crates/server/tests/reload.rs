@@ -0,0 +1,213 @@
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use common::{RenderMode, RouteConfig};
+use http_body_util::BodyExt;
+use runtime::{
+    registry::DEFAULT_BUNDLE, BundleRegistry, FsBundleRegistry, RenderRuntime, RuntimeConfig,
+};
+use server::{app::AppState, build_router, reload};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+fn bundle_source(label: &str) -> String {
+    format!("export function stream(context) {{ context.write('{label}'); }}")
+}
+
+fn state(bundle: &Path) -> AppState {
+    let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle)).expect("runtime");
+    AppState::new(runtime)
+        .with_routes(vec![
+            RouteConfig::new("home", "/").with_render_mode(RenderMode::Blocking)
+        ])
+        .expect("routes")
+}
+
+async fn get(app: &Router, path: &str) -> (StatusCode, String) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(path).body(Body::empty()).unwrap())
+        .await
+        .expect("response");
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+/// Polls `check` until it holds, failing after a few seconds.
+async fn eventually<F, Fut>(mut check: F)
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !check().await {
+        assert!(Instant::now() < deadline, "condition not met in time");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn changed_bundles_are_swapped_in_only_when_they_load() {
+    let dir = TempDir::new().expect("tmp dir");
+    let bundle = dir.path().join("app.bundle.js");
+    fs::write(&bundle, bundle_source("v1")).expect("write bundle");
+    let state = state(&bundle);
+    let app = build_router(state.clone());
+    let _watcher = reload::watch_bundle(state.clone(), DEFAULT_BUNDLE, Duration::from_millis(50))
+        .expect("watcher");
+
+    assert_eq!(get(&app, "/").await.1, "v1");
+
+    fs::write(&bundle, bundle_source("v2")).expect("write bundle");
+    eventually(|| async { get(&app, "/").await.1 == "v2" }).await;
+
+    fs::write(&bundle, "export function stream(context) { syntax error").expect("write bundle");
+    eventually(|| async { get(&app, "/readyz").await.0 == StatusCode::SERVICE_UNAVAILABLE }).await;
+    assert!(state
+        .health()
+        .reload_failure(DEFAULT_BUNDLE)
+        .is_some_and(|error| error.contains("failed to evaluate")));
+    assert_eq!(get(&app, "/").await, (StatusCode::OK, "v2".to_string()));
+
+    fs::write(&bundle, bundle_source("v3")).expect("write bundle");
+    eventually(|| async { get(&app, "/").await.1 == "v3" }).await;
+    assert_eq!(get(&app, "/readyz").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn in_flight_renders_finish_on_the_previous_bundle() {
+    let dir = TempDir::new().expect("tmp dir");
+    let bundle = dir.path().join("app.bundle.js");
+    fs::write(
+        &bundle,
+        "export async function stream(context) {
+            await new Promise((resolve) => setTimeout(resolve, 200));
+            context.write('old');
+        }",
+    )
+    .expect("write bundle");
+    let state = state(&bundle);
+    let app = build_router(state.clone());
+
+    let in_flight = tokio::spawn({
+        let app = app.clone();
+        async move { get(&app, "/").await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    fs::write(&bundle, bundle_source("new")).expect("write bundle");
+    reload::reload_bundle(&state, DEFAULT_BUNDLE)
+        .await
+        .expect("reload");
+
+    assert_eq!(get(&app, "/").await.1, "new");
+    assert_eq!(
+        in_flight.await.expect("request task"),
+        (StatusCode::OK, "old".to_string())
+    );
+}
+
+#[tokio::test]
+async fn registry_versions_are_activated_and_rolled_back() {
+    let dir = TempDir::new().expect("tmp dir");
+    let versions = dir.path().join("default").join("versions");
+    for (version, source) in [
+        ("v1", bundle_source("v1")),
+        ("v2", bundle_source("v2")),
+        (
+            "broken",
+            "export function stream(context) { syntax error".to_string(),
+        ),
+    ] {
+        fs::create_dir_all(versions.join(version)).expect("version dir");
+        fs::write(versions.join(version).join("bundle.js"), source).expect("write bundle");
+    }
+    fs::write(dir.path().join("default").join("current"), "v1").expect("current");
+
+    let registry = FsBundleRegistry::new(dir.path());
+    let active = registry.resolve("default").await.expect("resolve");
+    let state = state(&active.bundle_path).with_registry(FsBundleRegistry::new(dir.path()));
+    let app = build_router(state.clone());
+    assert_eq!(get(&app, "/").await.1, "v1");
+
+    let activated = reload::activate_version(&state, DEFAULT_BUNDLE, "v2")
+        .await
+        .expect("activate");
+    assert_eq!(activated.version, "v2");
+    assert_eq!(get(&app, "/").await.1, "v2");
+
+    reload::activate_version(&state, DEFAULT_BUNDLE, "broken")
+        .await
+        .expect_err("broken version should not load");
+    assert_eq!(get(&app, "/").await.1, "v2");
+    assert_eq!(
+        registry.resolve("home").await.expect("resolve").version,
+        "v2"
+    );
+
+    let restored = reload::rollback_version(&state, DEFAULT_BUNDLE)
+        .await
+        .expect("rollback");
+    assert_eq!(restored.version, "v1");
+    assert_eq!(get(&app, "/").await.1, "v1");
+    assert_eq!(
+        registry.resolve("home").await.expect("resolve").version,
+        "v1"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_switches_leave_the_registry_and_runtime_in_step() {
+    let dir = TempDir::new().expect("tmp dir");
+    let versions = dir.path().join("default").join("versions");
+    for version in ["v1", "v2", "v3"] {
+        fs::create_dir_all(versions.join(version)).expect("version dir");
+        fs::write(
+            versions.join(version).join("bundle.js"),
+            bundle_source(version),
+        )
+        .expect("write bundle");
+    }
+    fs::write(dir.path().join("default").join("current"), "v1").expect("current");
+
+    let registry = FsBundleRegistry::new(dir.path());
+    let active = registry.resolve(DEFAULT_BUNDLE).await.expect("resolve");
+    let state = state(&active.bundle_path).with_registry(FsBundleRegistry::new(dir.path()));
+    let app = build_router(state.clone());
+
+    let switches: Vec<_> = (0..12)
+        .map(|round| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                match round % 4 {
+                    0 => reload::activate_version(&state, DEFAULT_BUNDLE, "v2")
+                        .await
+                        .map(drop),
+                    1 => reload::activate_version(&state, DEFAULT_BUNDLE, "v3")
+                        .await
+                        .map(drop),
+                    2 => reload::rollback_version(&state, DEFAULT_BUNDLE)
+                        .await
+                        .map(drop),
+                    _ => reload::reload_bundle(&state, DEFAULT_BUNDLE).await,
+                }
+            })
+        })
+        .collect();
+    for switch in switches {
+        // Rollbacks may find no earlier version; only the end state matters here.
+        let _ = switch.await.expect("switch task");
+    }
+
+    let current = registry.resolve(DEFAULT_BUNDLE).await.expect("resolve");
+    assert_eq!(get(&app, "/").await.1, current.version);
+}
@@ -2,8 +2,9 @@ use std::io::Write;
 
 use axum::{
     body::Body,
-    http::{Request, StatusCode},
+    http::{header, Request, StatusCode},
 };
+use common::{RenderMode, RouteConfig};
 use http_body_util::BodyExt;
 use runtime::{RenderRuntime, RuntimeConfig};
 use server::{app::AppState, build_router, telemetry};
@@ -47,6 +48,179 @@ async fn stream_endpoint_returns_chunked_html() {
 
     let body = response.into_body().collect().await.unwrap().to_bytes();
     let html = String::from_utf8(body.to_vec()).expect("utf8");
-    assert!(html.contains("Streaming SSR response"));
-    assert!(html.contains("Hello"));
+    assert_eq!(html, "<div>Hello</div>");
+}
+
+fn write_failing_bundle() -> NamedTempFile {
+    let mut file = NamedTempFile::new().expect("bundle temp file");
+    writeln!(
+        file,
+        "export function stream(context) {{ context.write('<p>partial</p>'); throw new Error('boom'); }}"
+    )
+    .expect("write bundle");
+    file
+}
+
+#[tokio::test]
+async fn blocking_routes_buffer_the_document() {
+    let bundle = write_bundle();
+    let route = RouteConfig::new("home", "/").with_render_mode(RenderMode::Blocking);
+    let app = build_router(
+        test_state(bundle.path())
+            .with_routes(vec![route])
+            .expect("routes"),
+    );
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_LENGTH], "16");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"<div>Hello</div>");
+}
+
+#[tokio::test]
+async fn blocking_failures_return_an_error_status() {
+    let bundle = write_failing_bundle();
+    let route = RouteConfig::new("home", "/").with_render_mode(RenderMode::Blocking);
+    let app = build_router(
+        test_state(bundle.path())
+            .with_routes(vec![route])
+            .expect("routes"),
+    );
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8(body.to_vec()).expect("utf8");
+    assert!(!html.contains("partial"));
+    assert!(!html.contains("boom"), "{html}");
+    assert!(html.contains("see the server logs for request"), "{html}");
+}
+
+/// Renders the failing bundle on `mode`, returning the response body.
+async fn failed_render(mode: RenderMode, expose: bool) -> String {
+    let bundle = write_failing_bundle();
+    let mut state = test_state(bundle.path())
+        .with_routes(vec![RouteConfig::new("home", "/").with_render_mode(mode)])
+        .expect("routes");
+    if expose {
+        state = state.with_exposed_render_errors();
+    }
+    let response = build_router(state)
+        .oneshot(
+            Request::builder()
+                .uri("/")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    let request_id = response.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8(body.to_vec()).expect("utf8");
+    if !expose {
+        assert!(html.contains(&request_id), "{html}");
+    }
+    html
+}
+
+#[tokio::test]
+async fn render_error_details_are_only_streamed_when_exposed() {
+    let hidden = failed_render(RenderMode::Streaming, false).await;
+    assert!(hidden.starts_with("<p>partial</p><section><h2>Render Error</h2>"));
+    assert!(!hidden.contains("boom"), "{hidden}");
+
+    let exposed = failed_render(RenderMode::Streaming, true).await;
+    assert!(exposed.contains("Error: boom"), "{exposed}");
+
+    let exposed = failed_render(RenderMode::Blocking, true).await;
+    assert!(exposed.contains("boom"), "{exposed}");
+}
+
+#[tokio::test]
+async fn crawlers_get_blocking_renders_on_streaming_routes() {
+    let bundle = write_bundle();
+    let app = build_router(test_state(bundle.path()));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/stream")
+                .header(header::USER_AGENT, "Mozilla/5.0 (compatible; bingbot/2.0)")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_LENGTH], "16");
+}
+
+#[tokio::test]
+async fn routes_expose_their_id_and_params_to_the_bundle() {
+    let mut bundle = NamedTempFile::new().expect("bundle temp file");
+    writeln!(
+        bundle,
+        "export function stream(context) {{
+            const {{ id, params }} = context.request.route;
+            context.write(`${{id}}:${{params.id}}`);
+        }}"
+    )
+    .expect("write bundle");
+    let routes = vec![
+        RouteConfig::new("product", "/products/:id").with_render_mode(RenderMode::Blocking),
+        RouteConfig::new("home", "/"),
+    ];
+    let app = build_router(
+        test_state(bundle.path())
+            .with_routes(routes)
+            .expect("routes"),
+    );
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/products/42?ref=home")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"product:42");
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/missing")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }
@@ -0,0 +1,105 @@
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{RenderMode, RouteConfig};
+use futures_util::future::BoxFuture;
+use http_body_util::BodyExt;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use runtime::{RenderRuntime, RuntimeConfig};
+use server::{app::AppState, build_router};
+use tempfile::NamedTempFile;
+use tower::ServiceExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const CALLER_SPAN_ID: &str = "00f067aa0ba902b7";
+
+/// Keeps every exported span in memory.
+#[derive(Debug, Clone, Default)]
+struct CollectingExporter(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanExporter for CollectingExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        self.0.lock().unwrap().extend(batch);
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
+
+fn write_bundle() -> NamedTempFile {
+    let mut file = NamedTempFile::new().expect("bundle temp file");
+    writeln!(
+        file,
+        "export function stream(context) {{ context.write(context.request.trace.traceparent); }}"
+    )
+    .expect("write bundle");
+    file
+}
+
+#[tokio::test]
+async fn spans_join_the_callers_trace_and_the_bundle_sees_our_span() {
+    let exporter = CollectingExporter::default();
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let bundle = write_bundle();
+    let runtime = RenderRuntime::try_new(RuntimeConfig::new(bundle.path())).expect("runtime");
+    let route = RouteConfig::new("home", "/")
+        .with_render_mode(RenderMode::Blocking)
+        .with_cache_ttl_seconds(60);
+    let app = build_router(
+        AppState::new(runtime)
+            .with_routes(vec![route])
+            .expect("routes"),
+    );
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/")
+                .header("traceparent", format!("00-{TRACE_ID}-{CALLER_SPAN_ID}-01"))
+                .header("tracestate", "vendor=value")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let traceparent = String::from_utf8(body.to_vec()).unwrap();
+    // The render span closes once the background cache store that follows the response is done.
+    tokio::task::yield_now().await;
+
+    let spans = exporter.0.lock().unwrap().clone();
+    let span = |name: &str| {
+        spans
+            .iter()
+            .find(|span| span.name == name)
+            .unwrap_or_else(|| panic!("no `{name}` span in {spans:#?}"))
+    };
+    let request = span("http_request");
+    let lookup = span("cache_lookup");
+    let render = span("render");
+
+    for span in [request, lookup, render] {
+        assert_eq!(span.span_context.trace_id().to_string(), TRACE_ID);
+    }
+    assert_eq!(request.parent_span_id.to_string(), CALLER_SPAN_ID);
+    assert_eq!(lookup.parent_span_id, request.span_context.span_id());
+    assert_eq!(render.parent_span_id, request.span_context.span_id());
+    assert_eq!(request.span_context.trace_state().header(), "vendor=value");
+    assert_eq!(
+        traceparent,
+        format!("00-{TRACE_ID}-{}-01", request.span_context.span_id())
+    );
+}
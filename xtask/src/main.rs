@@ -6,6 +6,7 @@ use std::{
 
 use anyhow::{anyhow, Context, Result};
 use clap::{Parser, Subcommand};
+use runtime::RuntimeConfig;
 
 const EXAMPLE_DIR: &str = "examples/react-ssr-stream";
 const BUNDLE_PATH: &str = "examples/react-ssr-stream/dist/app.bundle.js";
@@ -43,6 +44,18 @@ enum Commands {
         #[arg(trailing_var_arg = true)]
         server_args: Vec<String>,
     },
+    /// Compile a bundle to a snapshot that engines boot from without parsing it.
+    Snapshot {
+        /// Bundle to snapshot; the React sample bundle is built and used when omitted.
+        #[arg(long, value_name = "BUNDLE_PATH")]
+        bundle: Option<PathBuf>,
+        /// Where to write the snapshot; defaults to `<bundle>.snapshot`.
+        #[arg(long, value_name = "SNAPSHOT_PATH")]
+        output: Option<PathBuf>,
+        /// Force reinstalling Node dependencies before building the sample bundle.
+        #[arg(long)]
+        install: bool,
+    },
 }
 
 fn main() -> Result<()> {
@@ -64,6 +77,17 @@ fn main() -> Result<()> {
             let bundle = build_bundle(install)?;
             run_server(bundle, server_args)
         }
+        Commands::Snapshot {
+            bundle,
+            output,
+            install,
+        } => {
+            let bundle = match bundle {
+                Some(bundle) => bundle,
+                None => build_bundle(install)?,
+            };
+            build_snapshot(bundle, output)
+        }
     }
 }
 
@@ -130,6 +154,25 @@ fn run_server(bundle_path: PathBuf, server_args: Vec<String>) -> Result<()> {
     run_command(command, "cargo run -p server")
 }
 
+fn build_snapshot(bundle_path: PathBuf, output: Option<PathBuf>) -> Result<()> {
+    let output = output.unwrap_or_else(|| {
+        let mut output = bundle_path.clone().into_os_string();
+        output.push(".snapshot");
+        PathBuf::from(output)
+    });
+    let info = runtime::write_snapshot(&RuntimeConfig::new(&bundle_path), &output)
+        .with_context(|| format!("failed to snapshot '{}'", bundle_path.display()))?;
+
+    println!(
+        "snapshot ready at {} ({} bytes, bundle sha256 {}, {})",
+        info.path.display(),
+        info.bytes,
+        info.content_hash,
+        info.engine
+    );
+    Ok(())
+}
+
 fn run_command(mut command: Command, label: &str) -> Result<()> {
     let status = command
         .status()